@@ -0,0 +1,299 @@
+// gitattributes: `<pattern> <attr>...` lines read from the .gitattributes
+// file of each directory and from .git/info/attributes, and
+// `[attr]<macro> <attr>...` macro definitions in the top level files
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use wildmatch;
+use wildmatch::Flags;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    // attr
+    Set,
+    // -attr
+    Unset,
+    // attr=value
+    Value(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    // Directory of the .gitattributes file, empty for the top one
+    base: String,
+    pattern: String,
+    // None for "!attr", which resets the attribute to unspecified
+    attrs: Vec<(String, Option<Value>)>,
+}
+
+// The .gitattributes files are read along the paths checked, unreadable ones
+// being taken as empty
+#[derive(Debug)]
+pub struct Attributes {
+    // Rules of the .gitattributes file of each directory read, lowest
+    // precedence first
+    dirs: RefCell<HashMap<String, Vec<Rule>>>,
+    // .git/info/attributes, which takes precedence over the files
+    info: RefCell<Vec<Rule>>,
+    // Attributes set by a macro when it is set
+    macros: RefCell<HashMap<String, Vec<(String, Option<Value>)>>>,
+    // False for attributes given as data
+    read_files: bool,
+}
+
+impl Attributes {
+    // Nothing is read until an attribute is asked for
+    pub fn new() -> Attributes {
+        Attributes {
+            dirs: RefCell::new(HashMap::new()),
+            info: RefCell::new(Vec::new()),
+            macros: RefCell::new(builtin_macros()),
+            read_files: true,
+        }
+    }
+
+    pub fn from_data(data: &str) -> Attributes {
+        let attributes = Attributes {
+            dirs: RefCell::new(HashMap::new()),
+            info: RefCell::new(Vec::new()),
+            macros: RefCell::new(builtin_macros()),
+            read_files: false,
+        };
+        let rules = attributes.parse(data, "");
+        attributes.dirs.borrow_mut().insert(String::new(), rules);
+        attributes
+    }
+
+    // Reads the .gitattributes files of the directories leading to a path,
+    // with .git/info/attributes along the top one
+    fn read_parents(&self, path: &str) {
+        if !self.read_files {
+            return;
+        }
+        if !self.dirs.borrow().contains_key("") {
+            self.read_dir("");
+            let info = Path::new(".git").join("info").join("attributes");
+            let rules = self.parse(&fs::read_to_string(info).unwrap_or_default(), "");
+            *self.info.borrow_mut() = rules;
+        }
+        for (i, _) in path.match_indices('/') {
+            self.read_dir(&path[..i]);
+        }
+    }
+
+    fn read_dir(&self, dir: &str) {
+        if self.dirs.borrow().contains_key(dir) {
+            return;
+        }
+        let file = Path::new(".").join(dir).join(".gitattributes");
+        let rules = self.parse(&fs::read_to_string(file).unwrap_or_default(), dir);
+        self.dirs.borrow_mut().insert(dir.to_string(), rules);
+    }
+
+    // Rules of the lines of the file of a directory, macros can only be
+    // defined at the top level
+    fn parse(&self, data: &str, base: &str) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        for line in data.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let pattern = words.next().unwrap().to_string();
+            let attrs = words.map(parse_attr).collect();
+            match pattern.strip_prefix("[attr]") {
+                Some(name) if base.is_empty() => {
+                    self.macros.borrow_mut().insert(name.to_string(), attrs);
+                }
+                Some(_) => (),
+                None => rules.push(Rule {
+                    base: base.to_string(),
+                    pattern,
+                    attrs,
+                }),
+            }
+        }
+        rules
+    }
+
+    // Attributes of the rules matching a path, highest precedence first
+    fn matching(&self, path: &str) -> Vec<Vec<(String, Option<Value>)>> {
+        self.read_parents(path);
+        let dirs = self.dirs.borrow();
+        let mut parents: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..i]).collect();
+        parents.insert(0, "");
+
+        let info = self.info.borrow();
+        let mut res: Vec<_> = info.iter().rev().filter(|r| matches(r, path)).collect();
+        for dir in parents.iter().rev() {
+            if let Some(rules) = dirs.get(*dir) {
+                res.extend(rules.iter().rev().filter(|r| matches(r, path)));
+            }
+        }
+        res.into_iter().map(|r| r.attrs.clone()).collect()
+    }
+
+    // None when the attribute is unspecified for the path
+    pub fn get(&self, path: &str, name: &str) -> Option<Value> {
+        for attrs in self.matching(path) {
+            if let Some(value) = self.find_in(&attrs, name, 0) {
+                return value;
+            }
+        }
+        None
+    }
+
+    // Value given to an attribute by a list of attributes, the ones set by a
+    // macro coming right before the macro itself
+    fn find_in(
+        &self,
+        attrs: &[(String, Option<Value>)],
+        name: &str,
+        depth: usize,
+    ) -> Option<Option<Value>> {
+        for (attr, value) in attrs.iter().rev() {
+            if attr == name {
+                return Some(value.clone());
+            }
+            let macros = self.macros.borrow();
+            let expansion = match (value, macros.get(attr)) {
+                (Some(Value::Set), Some(expansion)) if depth < 10 => expansion,
+                _ => continue,
+            };
+            if let Some(value) = self.find_in(expansion, name, depth + 1) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    // Every attribute specified for a path, sorted by name
+    pub fn get_all(&self, path: &str) -> Vec<(String, Value)> {
+        let mut names = Vec::new();
+        for attrs in self.matching(path) {
+            self.collect_names(&attrs, &mut names, 0);
+        }
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .filter_map(|name| self.get(path, &name).map(|v| (name, v)))
+            .collect()
+    }
+
+    fn collect_names(
+        &self,
+        attrs: &[(String, Option<Value>)],
+        names: &mut Vec<String>,
+        depth: usize,
+    ) {
+        for (attr, value) in attrs {
+            names.push(attr.to_string());
+            if let (Some(Value::Set), Some(expansion)) = (value, self.macros.borrow().get(attr)) {
+                if depth < 10 {
+                    self.collect_names(expansion, names, depth + 1);
+                }
+            }
+        }
+    }
+
+    pub fn is_set(&self, path: &str, name: &str) -> bool {
+        self.get(path, name) == Some(Value::Set)
+    }
+
+    pub fn is_unset(&self, path: &str, name: &str) -> bool {
+        self.get(path, name) == Some(Value::Unset)
+    }
+}
+
+// "binary" is a built-in macro for "-diff -merge -text"
+fn builtin_macros() -> HashMap<String, Vec<(String, Option<Value>)>> {
+    let binary = ["diff", "merge", "text"]
+        .iter()
+        .map(|name| (name.to_string(), Some(Value::Unset)))
+        .collect();
+    let mut macros = HashMap::new();
+    macros.insert("binary".to_string(), binary);
+    macros
+}
+
+fn parse_attr(word: &str) -> (String, Option<Value>) {
+    match word.find('=') {
+        Some(i) => {
+            let value = Value::Value(word[i + 1..].to_string());
+            (word[..i].to_string(), Some(value))
+        }
+        None if word.starts_with('-') => (word[1..].to_string(), Some(Value::Unset)),
+        None if word.starts_with('!') => (word[1..].to_string(), None),
+        None => (word.to_string(), Some(Value::Set)),
+    }
+}
+
+// Patterns without a '/' match the file name at any depth, the other ones
+// are relative to the directory of their file
+fn matches(rule: &Rule, path: &str) -> bool {
+    let path = match rule.base.is_empty() {
+        true => path,
+        false => match path.strip_prefix(&rule.base) {
+            Some(rest) if rest.starts_with('/') => &rest[1..],
+            _ => return false,
+        },
+    };
+
+    let flags = Flags {
+        pathname: true,
+        casefold: false,
+    };
+    match rule.pattern.contains('/') {
+        true => wildmatch::wildmatch(rule.pattern.trim_start_matches('/'), path, flags),
+        false => {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch::wildmatch(&rule.pattern, name, flags)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use attributes::{Attributes, Value};
+
+    #[test]
+    fn precedence_and_macros() {
+        let attrs = Attributes::from_data(
+            "*.png binary\n*.txt diff\nlogo.png diff\n/docs/*.md -diff eol=lf\nREADME.md !diff\n",
+        );
+        assert!(attrs.is_unset("img/a.png", "diff"));
+        assert!(attrs.is_unset("img/a.png", "text"));
+        assert!(attrs.is_set("img/a.png", "binary"));
+        assert!(attrs.is_set("logo.png", "diff"));
+        assert!(attrs.is_set("notes.txt", "diff"));
+        assert!(attrs.is_unset("docs/a.md", "diff"));
+        assert_eq!(
+            attrs.get("docs/a.md", "eol"),
+            Some(Value::Value("lf".to_string()))
+        );
+        assert_eq!(attrs.get("src/docs/a.md", "diff"), None);
+        assert_eq!(attrs.get("docs/README.md", "diff"), None);
+    }
+
+    #[test]
+    fn user_macros() {
+        let attrs = Attributes::from_data(
+            "[attr]prose text eol=lf -diff
+*.md prose
+README.md diff
+*.jpg binary text
+",
+        );
+        assert!(attrs.is_set("a.md", "text"));
+        assert!(attrs.is_unset("a.md", "diff"));
+        assert!(attrs.is_set("README.md", "diff"));
+        assert!(attrs.is_set("a.jpg", "text"));
+        let all = attrs.get_all("a.md");
+        let names: Vec<&str> = all.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["diff", "eol", "prose", "text"]);
+    }
+}
@@ -1,20 +1,278 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use builtin::diff;
+use builtin::diff::Source;
+use builtin::hash_object;
+use cli;
+use convert::Converter;
+use ignore::Ignore;
 use index;
+use patch;
+use pathspec::Pathspec;
+use rename;
+use work_dir;
+
+#[derive(Debug)]
+pub enum Error {
+    DiffError(diff::Error),
+    IndexError(index::Error),
+    IoError(io::Error),
+    PathspecNotMatched(Vec<String>),
+}
+
+impl From<diff::Error> for Error {
+    fn from(e: diff::Error) -> Error {
+        Error::DiffError(e)
+    }
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+pub struct Options {
+    // Ignored files are added too
+    pub force: bool,
+    // Only tracked files are updated
+    pub update: bool,
+    // The whole working dir is added when no path is given
+    pub all: bool,
+    pub dry_run: bool,
+    pub verbose: bool,
+    pub intent_to_add: bool,
+}
+
+pub fn cmd_add(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--force",
+        "-f",
+        "--all",
+        "-A",
+        "--update",
+        "-u",
+        "--dry-run",
+        "-n",
+        "--verbose",
+        "-v",
+        "--intent-to-add",
+        "-N",
+        "--patch",
+        "-p",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let opts = Options {
+        force: cli::has_flag(flags, "--force", "-f"),
+        update: cli::has_flag(flags, "--update", "-u"),
+        all: cli::has_flag(flags, "--all", "-A"),
+        dry_run: cli::has_flag(flags, "--dry-run", "-n"),
+        verbose: cli::has_flag(flags, "--verbose", "-v"),
+        intent_to_add: cli::has_flag(flags, "--intent-to-add", "-N"),
+    };
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--").cloned().collect();
+    if cli::has_flag(flags, "--patch", "-p") {
+        if let Err(why) = add_patch(&paths) {
+            println!("Could not add paths: {:?}", why);
+        }
+        return;
+    }
+    if paths.is_empty() && !opts.update && !opts.all {
+        println!("Nothing specified, nothing added.");
+        return;
+    }
 
-pub fn cmd_add(args: &[String]) {
-    if let Err(why) = add(args) {
-        println!("Could not add paths: {:?}", why);
+    match add(&paths, &opts) {
+        Err(Error::PathspecNotMatched(items)) => {
+            for item in items {
+                println!("fatal: pathspec '{}' did not match any files", item);
+            }
+        }
+        Err(why) => println!("Could not add paths: {:?}", why),
+        Ok(()) => (),
     }
 }
 
-fn add(paths: &[String]) -> Result<(), index::Error> {
-    let mut entries = index::read_entries()?;
-    entries.retain(|e| !paths.contains(&e.path));
+// Stages the changes of the files matching the paths: new files (unless
+// ignored, or only updating), modified and deleted ones
+pub fn add(paths: &[String], opts: &Options) -> Result<(), Error> {
+    let pathspec = Pathspec::new(paths);
+    let converter = Converter::load()?;
+    let index_mtime = index::modified_time();
+    let mut entries: BTreeMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
+    let mut matched: Vec<String> = Vec::new();
+    // ('add' or 'remove', path)
+    let mut changes = Vec::new();
 
-    for path in paths {
-        let entry = index::Entry::new(path)?;
-        entries.push(entry);
+    // Tracked files
+    let tracked: Vec<String> = entries.keys().cloned().collect();
+    for path in tracked.iter().filter(|p| pathspec.matches(p)) {
+        matched.push(path.to_string());
+        let entry = &entries[path];
+        let meta = match fs::symlink_metadata(path) {
+            // A file replaced by a directory is deleted too
+            Ok(ref meta) if meta.is_dir() && entry.mode != index::MODE_GITLINK => None,
+            Ok(meta) => Some(meta),
+            Err(_) => None,
+        };
+        let meta = match meta {
+            Some(meta) => meta,
+            None => {
+                changes.push(("remove", path.to_string()));
+                entries.remove(path);
+                continue;
+            }
+        };
+        if opts.intent_to_add || entry.is_up_to_date(&meta, index_mtime) {
+            continue;
+        }
+        // Blobs are not written by a dry run
+        let mode = index::mode_of(&meta, Some(entry.mode), converter.trust_executable_bit());
+        let hash = match mode {
+            index::MODE_GITLINK => index::gitlink_hash(path)?,
+            _ => {
+                let data = index::read_blob_data(path, mode, &converter)?;
+                hash_object::hash_object(&data, "blob", !opts.dry_run)?
+            }
+        };
+        if hash != entry.hash || mode != entry.mode {
+            changes.push(("add", path.to_string()));
+        }
+        if !opts.dry_run {
+            let new_entry = index::Entry::with_hash(path, mode, &hash)?;
+            entries.insert(path.to_string(), new_entry);
+        }
     }
 
-    index::write_entries(entries)?;
+    // Untracked files
+    let mut ignored_items = Vec::new();
+    if !opts.update {
+        let tracked: HashSet<String> = entries.keys().cloned().collect();
+        let mut ignore = Ignore::standard()?;
+        let (untracked, ignored) = work_dir::get_untracked_files(&tracked, &mut ignore)?;
+        let mut new_files: Vec<String> = untracked
+            .iter()
+            .map(|p| p.trim_end_matches('/').to_string())
+            .filter(|p| pathspec.matches(p))
+            .collect();
+        let mut ignored_files = Vec::new();
+        for path in &ignored {
+            match path.strip_suffix('/') {
+                Some(dir) if pathspec.may_match_under(dir) => ignored_files.extend(
+                    files_under(dir)?
+                        .into_iter()
+                        .filter(|p| pathspec.matches(p)),
+                ),
+                Some(_) => (),
+                None if pathspec.matches(path) => ignored_files.push(path.to_string()),
+                None => (),
+            }
+        }
+        matched.extend(new_files.iter().cloned());
+        if opts.force {
+            matched.extend(ignored_files.iter().cloned());
+            new_files.extend(ignored_files);
+        }
+
+        for path in new_files {
+            changes.push(("add", path.to_string()));
+            if opts.dry_run {
+                continue;
+            }
+            let entry = match opts.intent_to_add {
+                true => {
+                    let meta = fs::symlink_metadata(&path)?;
+                    let mode = index::mode_of(&meta, None, converter.trust_executable_bit());
+                    index::Entry::intent_to_add(&path, mode)
+                }
+                false => index::Entry::new(&path, None, &converter)?,
+            };
+            entries.insert(path, entry);
+        }
+    }
+
+    // Paths given which are ignored are reported, the other ones which
+    // match nothing are an error
+    let matched: Vec<&str> = matched.iter().map(|p| p.as_str()).collect();
+    let mut unmatched = Vec::new();
+    for item in pathspec.unmatched(&matched) {
+        // "." in an empty working dir
+        if item.is_empty() {
+            continue;
+        }
+        match Path::new(&item).exists() && !opts.update {
+            true => ignored_items.push(item),
+            false => unmatched.push(item),
+        }
+    }
+    if !unmatched.is_empty() {
+        return Err(Error::PathspecNotMatched(unmatched));
+    }
+
+    if opts.dry_run || opts.verbose {
+        changes.sort_by(|a, b| a.1.cmp(&b.1));
+        for (action, path) in &changes {
+            println!("{} '{}'", action, path);
+        }
+    }
+    if !opts.dry_run {
+        index::write_entries(entries.into_iter().map(|(_, e)| e).collect())?;
+    }
+
+    if !ignored_items.is_empty() {
+        println!("The following paths are ignored by one of your .gitignore files:");
+        for path in &ignored_items {
+            println!("{}", path);
+        }
+        println!("Use -f if you really want to add them.");
+    }
+    Ok(())
+}
+
+// Picks the hunks of the changes of tracked files to stage
+fn add_patch(paths: &[String]) -> Result<(), Error> {
+    let pairs = diff::diff_sources(
+        &Source::Index,
+        &Source::WorkDir,
+        &Pathspec::new(paths),
+        &rename::Options::new(),
+    )?;
+    let changes = patch::select_changes(&pairs, "Stage", false)?;
+    index::write_contents(changes)?;
     Ok(())
 }
+
+// Files of a directory and of its subdirectories, other repositories being
+// listed as "path" without being walked
+fn files_under(dir: &str) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_string()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+            let is_dir = entry.file_type()?.is_dir();
+            if is_dir && !entry.path().join(".git").exists() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
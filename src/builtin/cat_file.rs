@@ -1,13 +1,18 @@
+use std::io;
+use std::io::Write;
 use std::str;
 
 use builtin::read_tree;
 use cli;
 use object;
 use object::Object;
+use revision;
 
 #[derive(Debug)]
 pub enum Error {
+    IoError(io::Error),
     ObjectError(object::Error),
+    RevisionError(revision::Error),
     TreeError(read_tree::Error),
 }
 
@@ -27,19 +32,25 @@ pub fn cmd_cat_file(args: &[String], flags: &[String]) {
 }
 
 pub fn cat_file(hash_prefix: &str, mode: &str) -> Result<(), Error> {
-    let object = Object::new(hash_prefix).map_err(Error::ObjectError)?;
+    // Any revision names an object, tags are not peeled
+    let hash = revision::resolve_object(hash_prefix).map_err(Error::RevisionError)?;
+    let object = Object::new(&hash).map_err(Error::ObjectError)?;
     match mode {
         "--type" | "-t" => println!("{}", object.obj_type),
         "--size" | "-s" => println!("{}", object.obj_size),
         "--print" | "-p" => match object.obj_type.as_str() {
-            "blob" | "commit" => {
-                let data = str::from_utf8(&object.data).unwrap();
+            // Blobs may hold binary data
+            "blob" => io::stdout()
+                .write_all(&object.data)
+                .map_err(Error::IoError)?,
+            "commit" | "tag" => {
+                let data = String::from_utf8_lossy(&object.data);
                 println!("{}", data);
             }
             "tree" => {
-                let entries = read_tree::read_tree(hash_prefix).map_err(Error::TreeError)?;
+                let entries = read_tree::read_tree(&hash).map_err(Error::TreeError)?;
                 for entry in entries {
-                    println!("{:o} {} {}", entry.mode, entry.hash, entry.path);
+                    println!("{:06o} {} {}", entry.mode, entry.hash, entry.path);
                 }
             }
             tp => println!("unknown object type: {}", tp),
@@ -0,0 +1,58 @@
+use std::io;
+
+use attributes::{Attributes, Value};
+use cli;
+
+pub fn cmd_check_attr(args: &[String], flags: &[String]) {
+    let accepted_flags = ["-a", "--all"];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+
+    let all = cli::has_flag(flags, "--all", "-a");
+    // check-attr <attr>... -- <path>..., or check-attr <attr> <path>...
+    let (names, paths) = match (all, cli::split_paths(args)) {
+        (true, (args, paths)) => (Vec::new(), [args, paths].concat()),
+        (false, (names, ref paths)) if !paths.is_empty() => (names, paths.to_vec()),
+        (false, (args, _)) if args.len() > 1 => (args[..1].to_vec(), args[1..].to_vec()),
+        _ => {
+            println!("check-attr: command takes 'attr' and 'path' arguments.");
+            return;
+        }
+    };
+
+    if let Err(why) = check_attr(&names, &paths) {
+        println!("Could not check attributes: {:?}", why);
+    }
+}
+
+// "<path>: <attr>: set|unset|unspecified|<value>" for each path and
+// attribute, every specified attribute when `names` is empty
+fn check_attr(names: &[String], paths: &[String]) -> io::Result<()> {
+    let attributes = Attributes::new();
+    for path in paths {
+        let path = path.trim_start_matches("./");
+        let values = match names.is_empty() {
+            true => attributes
+                .get_all(path)
+                .into_iter()
+                .map(|(n, v)| (n, Some(v)))
+                .collect(),
+            false => names
+                .iter()
+                .map(|n| (n.to_string(), attributes.get(path, n)))
+                .collect::<Vec<_>>(),
+        };
+
+        for (name, value) in values {
+            let value = match value {
+                Some(Value::Set) => "set".to_string(),
+                Some(Value::Unset) => "unset".to_string(),
+                Some(Value::Value(v)) => v,
+                None => "unspecified".to_string(),
+            };
+            println!("{}: {}: {}", path, name, value);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+
+use cli;
+use ignore::Ignore;
+use index;
+
+#[derive(Debug)]
+pub enum Error {
+    IndexError(index::Error),
+    IoError(io::Error),
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+pub fn cmd_check_ignore(args: &[String], flags: &[String]) {
+    let accepted_flags = ["-v", "--verbose", "-n", "--non-matching", "--no-index"];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    if args.is_empty() {
+        println!("check-ignore: command takes 'path' arguments.");
+        return;
+    }
+
+    let verbose = cli::has_flag(flags, "--verbose", "-v");
+    let non_matching = cli::has_flag(flags, "--non-matching", "-n");
+    let no_index = cli::has_flag(flags, "--no-index", "");
+    if let Err(why) = check_ignore(args, verbose, non_matching, no_index) {
+        println!("Could not check ignored paths: {:?}", why);
+    }
+}
+
+// Prints the paths which are ignored, with -v the pattern deciding it as
+// "<source>:<line>:<pattern>\t<path>" (negated patterns included)
+fn check_ignore(
+    paths: &[String],
+    verbose: bool,
+    non_matching: bool,
+    no_index: bool,
+) -> Result<(), Error> {
+    // Tracked files are never ignored
+    let tracked: HashSet<String> = match no_index {
+        true => HashSet::new(),
+        false => index::read_entries()?.into_iter().map(|e| e.path).collect(),
+    };
+
+    let mut ignore = Ignore::standard()?;
+    for path in paths {
+        let name = path.trim_start_matches("./").trim_end_matches('/');
+        ignore.read_parents(name)?;
+        let is_dir = path.ends_with('/') || Path::new(name).is_dir();
+        let pattern = match tracked.contains(name) {
+            true => None,
+            false => ignore.find(name, is_dir),
+        };
+
+        match pattern {
+            Some(p) if verbose => println!("{}:{}:{}\t{}", p.source, p.line, p.text, path),
+            Some(p) if !p.negated => println!("{}", path),
+            None if verbose && non_matching => println!("::\t{}", path),
+            _ => (),
+        }
+    }
+    Ok(())
+}
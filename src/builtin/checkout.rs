@@ -1,20 +1,33 @@
 use std::io;
-use std::str;
+use std::path::Path;
 
-use builtin::status;
+use builtin::diff;
+use builtin::diff::Source;
+use builtin::hash_object;
+use cli;
+use convert::Converter;
+use index;
 use object;
 use object::Object;
+use patch;
+use pathspec::Pathspec;
 use refs;
+use rename;
+use revision;
 use work_dir;
 
 #[derive(Debug)]
 pub enum Error {
-    AlreadyOnIt,
+    BranchAlreadyExists(String),
+    DiffError(diff::Error),
+    ExpectedBranch(String),
+    IndexError(index::Error),
     IoError(io::Error),
+    NoPreviousCheckout,
     ObjectError(object::Error),
     ReferenceNotACommit,
+    RevisionError(revision::Error),
     WorkDirError(work_dir::Error),
-    WorkDirNotClean,
 }
 
 impl From<io::Error> for Error {
@@ -23,47 +36,285 @@ impl From<io::Error> for Error {
     }
 }
 
-pub fn cmd_checkout(args: &[String]) {
-    if args.is_empty() {
-        println!("checkout: command takes a 'ref' argument.");
+impl From<revision::Error> for Error {
+    fn from(e: revision::Error) -> Error {
+        Error::RevisionError(e)
+    }
+}
+
+impl From<work_dir::Error> for Error {
+    fn from(e: work_dir::Error) -> Error {
+        Error::WorkDirError(e)
+    }
+}
+
+// Where HEAD is moved by a checkout
+#[derive(Debug, Clone, Copy)]
+pub enum Head<'a> {
+    Branch(&'a str),
+    // Created, or reset to the commit when `true`
+    NewBranch(&'a str, bool),
+    // Branch created by the next commit
+    Orphan(&'a str),
+    // Revision as given by the user
+    Detached(&'a str),
+}
+
+pub fn cmd_checkout(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--force", "-f", "--merge", "-m", "-b", "-B", "--orphan", "--detach", "--patch", "-p",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let mode = match checkout_mode(flags) {
+        Some(mode) => mode,
+        None => return,
+    };
+
+    let (revs, mut paths) = cli::split_paths(args);
+    let has_separator = args.iter().any(|a| a == "--");
+    let creates_branch = flags
+        .iter()
+        .any(|f| f == "-b" || f == "-B" || f == "--orphan");
+    // "checkout <path>" when it is not a revision
+    if !has_separator && !creates_branch && revs.len() == 1 {
+        let is_path = !revision::is_revision(&revs[0]) && Path::new(&revs[0]).exists();
+        if is_path {
+            paths = revs.to_vec();
+        }
+    }
+
+    let res = if cli::has_flag(flags, "--patch", "-p") {
+        // [<rev>] [--] [<paths>]
+        let (rev, paths) = match revs.split_first() {
+            Some((rev, rest)) if has_separator || revision::is_revision(rev) => {
+                (Some(rev.as_str()), [rest, &paths[..]].concat())
+            }
+            _ => (None, [&revs[..], &paths[..]].concat()),
+        };
+        checkout_patch(rev, &paths)
+    } else if has_separator || !paths.is_empty() {
+        match revs.len() {
+            _ if !has_separator => checkout_paths(None, &paths),
+            0 => checkout_paths(None, &paths),
+            1 => checkout_paths(Some(&revs[0]), &paths),
+            _ => {
+                println!("checkout: only one revision can be given before '--'.");
+                return;
+            }
+        }
+    } else if creates_branch {
+        let name = match revs.get(0) {
+            Some(name) => name,
+            None => {
+                println!("checkout: '-b', '-B' and '--orphan' take a branch name.");
+                return;
+            }
+        };
+        let head = match (
+            cli::has_flag(flags, "-B", "-B"),
+            cli::has_flag(flags, "--orphan", "--orphan"),
+        ) {
+            (_, true) => Head::Orphan(name),
+            (reset, false) => Head::NewBranch(name, reset),
+        };
+        let start = revs.get(1).map(|s| s.as_str()).unwrap_or("HEAD");
+        revision::resolve(start)
+            .map_err(Error::RevisionError)
+            .and_then(|commit| switch_to(head, &commit, mode))
     } else {
-        let ref_name = &args[0];
-        if let Err(why) = checkout(ref_name) {
-            println!("Could not checkout: {:?}", why);
+        match revs.get(0) {
+            Some(name) if cli::has_flag(flags, "--detach", "--detach") => {
+                checkout_rev(name, true, mode)
+            }
+            Some(name) => checkout_rev(name, false, mode),
+            // Back to HEAD, throwing away the local changes
+            None if mode == work_dir::Checkout::Force => checkout_rev("HEAD", false, mode),
+            None => {
+                println!("checkout: command takes a 'ref' argument.");
+                return;
+            }
+        }
+    };
+
+    if let Err(why) = res {
+        print_error(why, "checkout");
+    }
+}
+
+// -f/--force, -m/--merge
+pub fn checkout_mode(flags: &[String]) -> Option<work_dir::Checkout> {
+    let force =
+        cli::has_flag(flags, "--force", "-f") || cli::has_flag(flags, "--discard-changes", "");
+    match (force, cli::has_flag(flags, "--merge", "-m")) {
+        (true, true) => {
+            println!("'-f' and '-m' cannot be used together.");
+            None
         }
+        (true, false) => Some(work_dir::Checkout::Force),
+        (false, true) => Some(work_dir::Checkout::Merge),
+        (false, false) => Some(work_dir::Checkout::Safe),
     }
 }
 
-fn checkout(ref_name: &str) -> Result<(), Error> {
-    if !status::is_clean_work_dir() {
-        return Err(Error::WorkDirNotClean);
+pub fn print_error(why: Error, command: &str) {
+    match why {
+        Error::WorkDirError(work_dir::Error::LocalChanges(changed, untracked)) => {
+            print!(
+                "{}",
+                work_dir::format_local_changes(&changed, &untracked, command)
+            );
+        }
+        Error::WorkDirError(work_dir::Error::PathspecNotMatched(items)) => {
+            for item in items {
+                println!(
+                    "error: pathspec '{}' did not match any file(s) known to git",
+                    item
+                );
+            }
+        }
+        why => println!("Could not {}: {:?}", command, why),
     }
+}
 
-    let will_detach_head = !refs::is_branch(&ref_name);
-    let commit = match will_detach_head {
-        true => ref_name.to_string(),
-        false => refs::get_ref_hash(&ref_name)?,
+// Files from the index, or from a revision (to the index too)
+fn checkout_paths(rev: Option<&str>, paths: &[String]) -> Result<(), Error> {
+    let commit = match rev {
+        Some(rev) => Some(revision::resolve(rev)?),
+        None => None,
     };
+    let to_index = commit.is_some();
+    let overlay = true;
+    let restored = work_dir::restore_files(
+        commit.as_ref().map(|c| c.as_str()),
+        &Pathspec::new(paths),
+        (to_index, true),
+        overlay,
+    )?;
+    println!("Updated {} path(s)", restored.len());
+    Ok(())
+}
+
+// Picks hunks of the local changes to throw away, from the working dir, or
+// from both the index and the working dir when going back to a revision
+fn checkout_patch(rev: Option<&str>, paths: &[String]) -> Result<(), Error> {
+    let old = match rev {
+        Some(rev) => Source::Tree(revision::resolve(rev)?),
+        None => Source::Index,
+    };
+    let pairs = diff::diff_sources(
+        &old,
+        &Source::WorkDir,
+        &Pathspec::new(paths),
+        &rename::Options::new(),
+    )
+    .map_err(Error::DiffError)?;
+    let changes = patch::select_changes(&pairs, "Discard", true)?;
 
-    let object = Object::new(&commit).map_err(Error::ObjectError)?;
-    if object.obj_type != "commit" {
-        return Err(Error::ReferenceNotACommit);
+    let converter = Converter::load()?;
+    for (path, file) in &changes {
+        match file {
+            Some((data, mode)) => {
+                let hash = hash_object::hash_object(data, "blob", true)?;
+                work_dir::write_blob(path, &hash, *mode, &converter)?;
+            }
+            None => work_dir::remove_path(path)?,
+        }
+    }
+    match rev {
+        Some(_) => index::write_contents(changes),
+        None => index::refresh(),
     }
+    .map_err(Error::IndexError)
+}
+
+// A branch, "-" for the previously checked out one, or any revision which
+// detaches HEAD
+pub fn checkout_rev(name: &str, detach: bool, mode: work_dir::Checkout) -> Result<(), Error> {
+    let name = match name {
+        "-" => match refs::previous_checkout()? {
+            Some(previous) => previous,
+            None => return Err(Error::NoPreviousCheckout),
+        },
+        // Stays on the current branch
+        "HEAD" if !detach && !refs::is_detached_head() => refs::read_ref("HEAD")?,
+        name => name.to_string(),
+    };
 
-    let head = refs::get_ref_hash("HEAD")?;
-    if ref_name == head {
-        return Err(Error::AlreadyOnIt);
+    if refs::is_branch(&name) && !detach {
+        let commit = refs::get_ref_hash(&name)?;
+        return switch_to(Head::Branch(&name), &commit, mode);
     }
+    let commit = revision::resolve(&name)?;
+    switch_to(Head::Detached(&name), &commit, mode)
+}
 
-    work_dir::update_from_commit(&commit).map_err(Error::WorkDirError)?;
-    refs::write_to_ref("HEAD", ref_name)?;
+// Checks out a commit (keeping or merging local changes depending on the
+// mode) and moves HEAD
+pub fn switch_to(head: Head, commit: &str, mode: work_dir::Checkout) -> Result<(), Error> {
+    let cur_branch = match refs::is_detached_head() {
+        true => None,
+        false => Some(refs::read_ref("HEAD")?),
+    };
+    match head {
+        Head::NewBranch(name, false) | Head::Orphan(name) if refs::is_branch(name) => {
+            return Err(Error::BranchAlreadyExists(name.to_string()));
+        }
+        Head::Branch(name) if cur_branch.as_ref().map(|b| b.as_str()) == Some(name) => {
+            // Forced, the local changes are thrown away
+            if mode == work_dir::Checkout::Force && !commit.is_empty() {
+                work_dir::update_from_commit(commit, mode)?;
+            }
+            println!("Already on {}", name);
+            return Ok(());
+        }
+        _ => (),
+    }
+    if !commit.is_empty() {
+        let object = Object::new(&commit).map_err(Error::ObjectError)?;
+        if object.obj_type != "commit" {
+            return Err(Error::ReferenceNotACommit);
+        }
+    }
 
-    if will_detach_head {
-        println!("Note: checking out {}", ref_name);
-        println!("You are in detached HEAD state.");
-    } else {
-        println!("Switched to branch {}", ref_name);
+    let cur_commit = refs::get_ref_hash("HEAD")?;
+    let conflicts = work_dir::update_from_commit(commit, mode)?;
+    match head {
+        Head::Branch(name) => refs::write_to_ref("HEAD", name)?,
+        Head::NewBranch(name, _) => {
+            let old = refs::get_ref_hash(name)?;
+            refs::write_to_ref(name, commit)?;
+            let message = format!("branch: Created from {}", commit);
+            refs::append_log(name, &old, commit, &message)?;
+            refs::write_to_ref("HEAD", name)?;
+        }
+        Head::Orphan(name) => refs::write_head_to_branch(name)?,
+        Head::Detached(_) => refs::write_to_ref("HEAD", commit)?,
     }
 
+    // Detached HEADs are logged by hash so that "-" finds them back
+    let from = cur_branch.unwrap_or(cur_commit.to_string());
+    let to = match head {
+        Head::Branch(name) | Head::NewBranch(name, _) | Head::Orphan(name) => name,
+        Head::Detached(_) => commit,
+    };
+    let message = format!("checkout: moving from {} to {}", from, to);
+    refs::append_log("HEAD", &cur_commit, commit, &message)?;
+
+    for file in &conflicts {
+        println!("CONFLICT {}", file);
+    }
+    match head {
+        Head::Branch(name) => println!("Switched to branch {}", name),
+        Head::NewBranch(name, true) => println!("Switched to and reset branch {}", name),
+        Head::NewBranch(name, false) | Head::Orphan(name) => {
+            println!("Switched to a new branch {}", name)
+        }
+        Head::Detached(rev) => {
+            println!("Note: checking out {}", rev);
+            println!("You are in detached HEAD state.");
+        }
+    }
     Ok(())
 }
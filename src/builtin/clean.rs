@@ -0,0 +1,413 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+use builtin::config;
+use cli;
+use ignore::Ignore;
+use index;
+use pathspec::Pathspec;
+use work_dir;
+
+#[derive(Debug)]
+pub enum Error {
+    IndexError(index::Error),
+    IoError(io::Error),
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+// Which of the files not tracked are removed
+#[derive(PartialEq)]
+pub enum Ignored {
+    // Only the ones which are not ignored
+    Kept,
+    // All of them (-x)
+    Removed,
+    // Only the ignored ones (-X)
+    Only,
+}
+
+pub struct Options {
+    // Untracked directories are removed as a whole
+    pub dirs: bool,
+    pub ignored: Ignored,
+    // Patterns of files to keep (-e)
+    pub excludes: Vec<String>,
+    // Other repositories are removed too
+    pub remove_repos: bool,
+}
+
+pub fn cmd_clean(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--force",
+        "-f",
+        "--dry-run",
+        "-n",
+        "-d",
+        "-x",
+        "-X",
+        "-e",
+        "--exclude=",
+        "--interactive",
+        "-i",
+        "--quiet",
+        "-q",
+    ];
+    // Short flags can be grouped, like "-fdx"
+    let mut grouped = Vec::new();
+    for flag in flags {
+        let letters = flag.strip_prefix('-').unwrap_or("");
+        match letters.len() > 1 && letters.chars().all(|c| "fndxXeiq".contains(c)) {
+            true => grouped.extend(letters.chars().map(|c| format!("-{}", c))),
+            false => grouped.push(flag.to_string()),
+        }
+    }
+    let flags = &grouped[..];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    // Given twice, other repositories are removed too
+    let nb_forces = flags
+        .iter()
+        .filter(|f| *f == "--force" || *f == "-f")
+        .count();
+    let dry_run = cli::has_flag(flags, "--dry-run", "-n");
+    let interactive = cli::has_flag(flags, "--interactive", "-i");
+    let quiet = cli::has_flag(flags, "--quiet", "-q");
+
+    let config = match config::Config::new() {
+        Ok(config) => config,
+        Err(why) => {
+            println!("Could not clean: {:?}", why);
+            return;
+        }
+    };
+    if nb_forces == 0 && !dry_run && !interactive {
+        match config.get_bool("clean.requireForce") {
+            Some(false) => (),
+            Some(true) => {
+                println!("fatal: clean.requireForce set to true and neither -i, -n, nor -f given; refusing to clean");
+                return;
+            }
+            None => {
+                println!("fatal: clean.requireForce defaults to true and neither -i, -n, nor -f given; refusing to clean");
+                return;
+            }
+        }
+    }
+
+    // The values of "-e" come first in the arguments
+    let nb_excludes = flags.iter().filter(|f| *f == "-e").count();
+    let mut args: Vec<String> = args.iter().filter(|a| *a != "--").cloned().collect();
+    if args.len() < nb_excludes {
+        println!("clean: -e needs a pattern.");
+        return;
+    }
+    let paths = args.split_off(nb_excludes);
+    let mut excludes = args;
+    excludes.extend(
+        flags
+            .iter()
+            .filter_map(|f| f.strip_prefix("--exclude="))
+            .map(|p| p.to_string()),
+    );
+
+    let opts = Options {
+        dirs: flags.iter().any(|f| f == "-d"),
+        ignored: match (
+            flags.iter().any(|f| f == "-x"),
+            flags.iter().any(|f| f == "-X"),
+        ) {
+            (true, true) => {
+                println!("fatal: -x and -X cannot be used together");
+                return;
+            }
+            (true, false) => Ignored::Removed,
+            (false, true) => Ignored::Only,
+            (false, false) => Ignored::Kept,
+        },
+        excludes,
+        remove_repos: nb_forces > 1,
+    };
+
+    let res = files_to_clean(&Pathspec::new(&paths), &opts).and_then(|mut items| {
+        if interactive && !items.is_empty() {
+            items = select_items(items)?;
+        }
+        for item in &items {
+            match dry_run {
+                true => println!("Would remove {}", item),
+                false => {
+                    if !quiet {
+                        println!("Removing {}", item);
+                    }
+                    remove_item(item)?;
+                }
+            }
+        }
+        Ok(())
+    });
+    if let Err(why) = res {
+        println!("Could not clean: {:?}", why);
+    }
+}
+
+// Files (and directories, with a trailing '/') of the working dir to remove
+pub fn files_to_clean(pathspec: &Pathspec, opts: &Options) -> Result<Vec<String>, Error> {
+    let tracked: HashSet<String> = index::read_entries()?.into_iter().map(|e| e.path).collect();
+    let mut ignore = Ignore::standard()?;
+    let (untracked, ignored) = work_dir::get_untracked_files(&tracked, &mut ignore)?;
+    let mut excludes = Ignore::new();
+    for pattern in &opts.excludes {
+        excludes.add_pattern(pattern);
+    }
+    let is_excluded =
+        |path: &str| excludes.is_ignored(path.trim_end_matches('/'), path.ends_with('/'));
+
+    // -e patterns are ignore rules of their own, which -X removes too
+    let mut candidates = Vec::new();
+    let mut kept: Vec<&str> = tracked.iter().map(|p| p.as_str()).collect();
+    for path in &untracked {
+        let is_removed = match opts.ignored {
+            Ignored::Only => is_excluded(path),
+            _ => !is_excluded(path),
+        };
+        // Other repositories
+        let is_repo = path.ends_with('/');
+        match is_removed && pathspec.matches(path.trim_end_matches('/')) {
+            true if !is_repo || (opts.dirs && opts.remove_repos) => candidates.push(path),
+            _ => kept.push(path),
+        }
+    }
+    for path in &ignored {
+        let is_removed = match opts.ignored {
+            Ignored::Kept => false,
+            Ignored::Removed => !is_excluded(path),
+            Ignored::Only => true,
+        };
+        match is_removed && pathspec.matches(path.trim_end_matches('/')) {
+            true => candidates.push(path),
+            false => kept.push(path),
+        }
+    }
+    // Empty directories are not listed as untracked
+    let mut empty = Vec::new();
+    if opts.dirs && opts.ignored != Ignored::Only {
+        find_empty_dirs("", &mut empty)?;
+        empty.retain(|dir| !ignore.is_ignored(dir.trim_end_matches('/'), true));
+    }
+    for path in &empty {
+        match !is_excluded(path) && pathspec.matches(path.trim_end_matches('/')) {
+            true => candidates.push(path),
+            false => kept.push(path),
+        }
+    }
+
+    // Directories holding files which are kept
+    let mut kept_dirs = HashSet::new();
+    for path in kept {
+        let path = path.trim_end_matches('/');
+        for (i, _) in path.match_indices('/') {
+            kept_dirs.insert(&path[..i]);
+        }
+    }
+
+    // Files of untracked directories are removed with the directory (with -d)
+    // or only when given (without), the directory having to match too
+    let mut items = BTreeSet::new();
+    for path in candidates {
+        let trimmed = path.trim_end_matches('/');
+        let untracked_dir = trimmed
+            .match_indices('/')
+            .map(|(i, _)| &trimmed[..i])
+            .find(|dir| !kept_dirs.contains(dir) && pathspec.matches(dir));
+        match untracked_dir {
+            Some(dir) if opts.dirs => {
+                items.insert(format!("{}/", dir));
+            }
+            Some(_) if pathspec.is_empty() => (),
+            _ if path.ends_with('/') && !opts.dirs => (),
+            _ => {
+                items.insert(path.to_string());
+            }
+        }
+    }
+    Ok(items.into_iter().collect())
+}
+
+// Finds the directories under `dir` which have no file in them, nor in
+// their subdirectories, other repositories aside. Returns whether `dir` is
+// one of them.
+fn find_empty_dirs(dir: &str, empty: &mut Vec<String>) -> io::Result<bool> {
+    let mut is_empty = true;
+    let mut empty_subdirs = Vec::new();
+    for entry in fs::read_dir(Path::new(".").join(dir))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = match dir.is_empty() {
+            true => name,
+            false => format!("{}/{}", dir, name),
+        };
+        let is_dir = entry.file_type()?.is_dir();
+        if is_dir && path != ".git" && !entry.path().join(".git").exists() {
+            match find_empty_dirs(&path, empty)? {
+                true => empty_subdirs.push(format!("{}/", path)),
+                false => is_empty = false,
+            }
+        } else {
+            is_empty = false;
+        }
+    }
+    // Only the topmost ones are listed
+    if !is_empty {
+        empty.extend(empty_subdirs);
+    }
+    Ok(is_empty)
+}
+
+fn remove_item(item: &str) -> io::Result<()> {
+    match item.strip_suffix('/') {
+        Some(dir) => fs::remove_dir_all(dir),
+        None => fs::remove_file(item),
+    }
+}
+
+// Line read from stdin, None at the end of it
+fn prompt(text: &str) -> io::Result<Option<String>> {
+    print!("{}", text);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_string()))
+}
+
+// Interactive selection of the items to remove, like git clean -i
+fn select_items(mut items: Vec<String>) -> io::Result<Vec<String>> {
+    loop {
+        if items.is_empty() {
+            println!("No more files to clean, exiting.");
+            return Ok(items);
+        }
+        println!("Would remove the following items:");
+        for item in &items {
+            println!("  {}", item);
+        }
+        println!("*** Commands ***");
+        println!("    1: clean                2: filter by pattern    3: select by numbers");
+        println!("    4: ask each             5: quit                 6: help");
+        let command = match prompt("What now> ")? {
+            Some(command) => command,
+            None => return Ok(Vec::new()),
+        };
+        match command.as_str() {
+            "1" | "c" | "clean" => return Ok(items),
+            "2" | "f" | "filter by pattern" => items = filter_by_patterns(items)?,
+            "3" | "s" | "select by numbers" => items = select_by_numbers(items)?,
+            "4" | "a" | "ask each" => return ask_each(items),
+            "5" | "q" | "quit" => {
+                println!("Bye.");
+                return Ok(Vec::new());
+            }
+            "6" | "h" | "help" | "?" => {
+                println!("clean               - start cleaning");
+                println!("filter by pattern   - exclude items from deletion");
+                println!("select by numbers   - select items to be deleted by numbers");
+                println!("ask each            - confirm each deletion (like \"rm -i\")");
+                println!("quit                - stop cleaning");
+                println!("help                - this screen");
+                println!("?                   - help for prompt selection");
+            }
+            _ => println!("Huh ({})?", command),
+        }
+    }
+}
+
+// Items left once the ones matching ignore patterns are taken out
+fn filter_by_patterns(items: Vec<String>) -> io::Result<Vec<String>> {
+    let patterns = match prompt("Input ignore patterns>> ")? {
+        Some(patterns) => patterns,
+        None => return Ok(items),
+    };
+    let mut ignore = Ignore::new();
+    for pattern in patterns.split_whitespace() {
+        ignore.add_pattern(pattern);
+    }
+    Ok(items
+        .into_iter()
+        .filter(|i| !ignore.is_ignored(i.trim_end_matches('/'), i.ends_with('/')))
+        .collect())
+}
+
+// Items picked by their number: "1 3", "2-4" or "*" for all of them
+fn select_by_numbers(items: Vec<String>) -> io::Result<Vec<String>> {
+    for (i, item) in items.iter().enumerate() {
+        println!("  {}: {}", i + 1, item);
+    }
+    let answer = match prompt("Select items to delete>> ")? {
+        Some(answer) => answer,
+        None => return Ok(items),
+    };
+    if answer.is_empty() {
+        return Ok(items);
+    }
+    let mut selected = vec![false; items.len()];
+    for word in answer.split(|c: char| c == ',' || c.is_whitespace()) {
+        let range = match word {
+            "" => continue,
+            "*" => Some((1, items.len())),
+            _ => {
+                let mut bounds = word.splitn(2, '-').map(|n| n.trim().parse::<usize>());
+                match (bounds.next(), bounds.next()) {
+                    (Some(Ok(start)), None) => Some((start, start)),
+                    (Some(Ok(start)), Some(Ok(end))) => Some((start, end)),
+                    (Some(Ok(start)), Some(Err(_))) if word.ends_with('-') => {
+                        Some((start, items.len()))
+                    }
+                    _ => None,
+                }
+            }
+        };
+        match range {
+            Some((start, end)) if start >= 1 && start <= end => {
+                for s in selected.iter_mut().take(end).skip(start - 1) {
+                    *s = true;
+                }
+            }
+            _ => println!("Huh ({})?", word),
+        }
+    }
+    Ok(items
+        .into_iter()
+        .zip(selected)
+        .filter(|(_, s)| *s)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+fn ask_each(items: Vec<String>) -> io::Result<Vec<String>> {
+    let mut selected = Vec::new();
+    for item in items {
+        match prompt(&format!("Remove {} [y/N]? ", item))? {
+            Some(ref answer) if answer == "y" || answer == "yes" => selected.push(item),
+            Some(_) => (),
+            None => break,
+        }
+    }
+    Ok(selected)
+}
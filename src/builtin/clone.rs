@@ -3,6 +3,7 @@ use std::fs;
 use std::io;
 use std::path::Path;
 
+use builtin::config;
 use builtin::init;
 use builtin::pull;
 use builtin::remote;
@@ -55,6 +56,11 @@ fn clone(repository: &str, directory: &str) -> Result<(), Error> {
 
     env::set_current_dir(&absolute_dir_path)?;
     remote::add_remote("origin", absolute_repo_path.to_str().unwrap())?;
+    // The cloned branch tracks the one of the remote
+    let mut config = config::Config::new()?;
+    config.set_value("branch.master.remote", "origin");
+    config.set_value("branch.master.merge", "refs/heads/master");
+    config.write_config()?;
     if has_commits {
         pull::pull("origin", "master").map_err(Error::PullError)?;
     }
@@ -16,8 +16,10 @@ use refs;
 pub enum Error {
     CannotGetParentFromCommit,
     CannotGetTreeFromCommit,
+    InvalidSignature,
     IoError(io::Error),
     NoCommonAncestor,
+    NotACommitObject,
     NothingToCommit,
     ObjectError(object::Error),
     TreeError(write_tree::Error),
@@ -30,6 +32,117 @@ impl From<io::Error> for Error {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub time: i64,
+    pub tz: String,
+}
+
+impl Signature {
+    // Name <email> 1530000000 +0200
+    pub fn parse(line: &str) -> Result<Signature, Error> {
+        let (open, close) = match (line.find('<'), line.rfind('>')) {
+            (Some(o), Some(c)) if o < c => (o, c),
+            _ => return Err(Error::InvalidSignature),
+        };
+        let name = line[..open].trim().to_string();
+        let email = line[open + 1..close].to_string();
+
+        let mut date = line[close + 1..].split_whitespace();
+        let time = match date.next().map(|t| t.parse::<i64>()) {
+            Some(Ok(t)) => t,
+            _ => return Err(Error::InvalidSignature),
+        };
+        let tz = date.next().unwrap_or("+0000").to_string();
+
+        Ok(Signature {
+            name,
+            email,
+            time,
+            tz,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub hash: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub header: String,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn new(hash: &str) -> Result<Commit, Error> {
+        let object = Object::new(hash).map_err(Error::ObjectError)?;
+        if object.obj_type != "commit" {
+            return Err(Error::NotACommitObject);
+        }
+        let data = String::from_utf8_lossy(&object.data).to_string();
+
+        let (header, message) = match data.find("\n\n") {
+            Some(i) => (&data[..i], &data[i + 2..]),
+            None => (data.as_str(), ""),
+        };
+
+        let mut tree = String::new();
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        for line in header.lines() {
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => continue,
+            };
+            match key {
+                "tree" => tree = value.to_string(),
+                "parent" => parents.push(value.to_string()),
+                "author" => author = Some(Signature::parse(value)?),
+                "committer" => committer = Some(Signature::parse(value)?),
+                _ => (),
+            }
+        }
+
+        if tree.len() != 40 {
+            return Err(Error::CannotGetTreeFromCommit);
+        }
+        let author = match author {
+            Some(a) => a,
+            None => return Err(Error::InvalidSignature),
+        };
+        let committer = committer.unwrap_or(author.clone());
+
+        Ok(Commit {
+            hash: object::expand_hash(hash).map_err(Error::ObjectError)?,
+            tree,
+            parents,
+            author,
+            committer,
+            header: header.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    pub fn subject(&self) -> String {
+        let paragraph = self.message.split("\n\n").next().unwrap_or("");
+        let lines: Vec<&str> = paragraph.lines().map(|l| l.trim()).collect();
+        lines.join(" ")
+    }
+
+    pub fn body(&self) -> String {
+        let message = self.message.trim_start_matches('\n');
+        match message.find("\n\n") {
+            Some(i) => message[i + 2..].trim_start_matches('\n').to_string(),
+            None => String::new(),
+        }
+    }
+}
+
 pub fn cmd_commit(args: &[String], flags: &[String]) {
     let accepted_flags = ["--message", "-m"];
     if cli::has_known_flags(flags, &accepted_flags) {
@@ -45,13 +158,7 @@ pub fn cmd_commit(args: &[String], flags: &[String]) {
 }
 
 pub fn commit(message: &str) -> Result<String, Error> {
-    let user = config::Config::new()?;
-    if user.name.is_empty() || user.email.is_empty() {
-        return Err(Error::UserConfigIncomplete);
-    }
-    let author = format!("{} <{}>", user.name, user.email);
-
-    let commit_tree = write_tree::write_tree().map_err(Error::TreeError)?;
+    let tree = write_tree::write_tree().map_err(Error::TreeError)?;
     let mut parents = Vec::new();
 
     let head = refs::read_ref("HEAD")?;
@@ -74,14 +181,34 @@ pub fn commit(message: &str) -> Result<String, Error> {
         }
 
         let cur_hash = get_tree_hash(&cur_commit)?;
-        if commit_tree == cur_hash && !is_in_merge {
+        if tree == cur_hash && !is_in_merge {
             println!("On {}", head);
             println!("nothing to commit, working tree clean");
             return Err(Error::NothingToCommit);
         }
     }
 
-    let mut header = format!("tree {}", commit_tree);
+    let hash = commit_tree(&tree, &parents, message)?;
+
+    let ref_path = match refs::is_detached_head() {
+        true => Path::new(".git").join("HEAD"),
+        false => Path::new(".git").join("refs").join("heads").join(&head),
+    };
+    fs::write(ref_path, format!("{}\n", hash))?;
+
+    println!("[{} {}] {}", head, &hash[..7], message);
+    Ok(hash)
+}
+
+// Writes a commit object of a tree, authored and committed by the user now
+pub fn commit_tree(tree: &str, parents: &[String], message: &str) -> Result<String, Error> {
+    let user = config::Config::new()?;
+    if user.name.is_empty() || user.email.is_empty() {
+        return Err(Error::UserConfigIncomplete);
+    }
+    let author = format!("{} <{}>", user.name, user.email);
+
+    let mut header = format!("tree {}", tree);
     for parent in parents {
         header.push_str(&format!("\nparent {}", parent));
     }
@@ -102,14 +229,6 @@ pub fn commit(message: &str) -> Result<String, Error> {
 
     let write = true;
     let hash = hash_object::hash_object(commit_content.as_bytes(), "commit", write)?;
-
-    let ref_path = match refs::is_detached_head() {
-        true => Path::new(".git").join("HEAD"),
-        false => Path::new(".git").join("refs").join("heads").join(&head),
-    };
-    fs::write(ref_path, format!("{}\n", hash))?;
-
-    println!("[{} {}] {}", head, &hash[..7], message);
     Ok(hash)
 }
 
@@ -10,51 +10,98 @@ pub struct Config {
     pub name: String,
     pub email: String,
     pub remotes: Vec<remote::Remote>,
+    // Every other variable as (section[.subsection].variable, value)
+    pub values: Vec<(String, String)>,
 }
 
 impl Config {
     pub fn new() -> io::Result<Config> {
+        let config_file = Path::new(".git").join("config");
+        let data = match config_file.exists() {
+            true => fs::read_to_string(config_file)?,
+            false => String::new(),
+        };
+        Ok(Config::from_data(&data))
+    }
+
+    pub fn from_data(data: &str) -> Config {
         let mut name = String::new();
         let mut email = String::new();
         let mut remotes = Vec::new();
+        let mut values = Vec::new();
         let mut cur_section = String::new();
 
-        let config_file = Path::new(".git").join("config");
-        if config_file.exists() {
-            let data = fs::read_to_string(config_file)?;
-            for line in data.lines().map(|l| l.trim()) {
-                if line.starts_with("[") && line.ends_with("]") {
-                    cur_section = line.to_string();
-                }
+        for line in data.lines().map(|l| l.trim()) {
+            if line.starts_with("[") && line.ends_with("]") {
+                cur_section = line.to_string();
+            }
 
-                let elem: Vec<&str> = line.split('=').collect();
-                if elem.len() != 2 {
-                    continue;
-                }
+            let elem: Vec<&str> = line.splitn(2, '=').collect();
+            if elem.len() != 2 {
+                continue;
+            }
 
-                // [section]
-                //      var = value
-                let var = elem[0].trim().to_string();
-                let value = elem[1].trim().to_string();
-                if var == "name" {
-                    name = value;
-                } else if var == "email" {
-                    email = value;
-                } else if var == "url" {
-                    let (_, remote_name) = parse_section_name(&cur_section);
-                    remotes.push(remote::Remote {
-                        name: remote_name.to_string(),
-                        url: value,
-                    });
-                }
+            // [section]
+            //      var = value
+            let var = elem[0].trim().to_lowercase();
+            let value = elem[1].trim().to_string();
+            let (section, subsection) = parse_section_name(&cur_section);
+            let section = section.to_lowercase();
+            if section == "user" && var == "name" {
+                name = value;
+            } else if section == "user" && var == "email" {
+                email = value;
+            } else if section == "remote" && var == "url" {
+                remotes.push(remote::Remote {
+                    name: subsection.to_string(),
+                    url: value,
+                });
+            } else if subsection.is_empty() {
+                values.push((format!("{}.{}", section, var), value));
+            } else {
+                values.push((format!("{}.{}.{}", section, subsection, var), value));
             }
         }
 
-        Ok(Config {
+        Config {
             name: name,
             email: email,
             remotes: remotes,
-        })
+            values: values,
+        }
+    }
+
+    // Last value given to a variable such as "diff.algorithm" or
+    // "branch.master.remote"
+    pub fn get_value(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.values
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // "true", "yes", "on" and "1" (or a variable without value) are true
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get_value(key)
+            .map(|v| match v.to_lowercase().as_str() {
+                "false" | "no" | "off" | "0" => false,
+                _ => true,
+            })
+    }
+
+    pub fn set_value(&mut self, key: &str, value: &str) {
+        let key = normalize_key(key);
+        match self.values.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value.to_string(),
+            None => self.values.push((key, value.to_string())),
+        }
+    }
+
+    pub fn unset_value(&mut self, key: &str) {
+        let key = normalize_key(key);
+        self.values.retain(|(k, _)| *k != key);
     }
 
     pub fn add(&mut self, section: &str, subsection: &str, value: &str) {
@@ -108,19 +155,57 @@ impl Config {
     }
 
     pub fn write_config(&self) -> io::Result<()> {
-        let mut config_fmt = format!("[user]\n\tname = {}\n\temail = {}\n", self.name, self.email);
+        let config_file = Path::new(".git").join("config");
+        fs::write(config_file, self.format())?;
+        Ok(())
+    }
+
+    // Variables of the same section are grouped under a single header, keys
+    // being normalized as for get_value: "[remote "origin"]" holds the url of
+    // the remote as well as any "remote.origin.*" variable
+    fn format(&self) -> String {
+        let mut sections: Vec<(String, Vec<(&str, &str)>)> = Vec::new();
+        let user = vec![("name", self.name.as_str()), ("email", self.email.as_str())];
+        sections.push((String::from("user"), user));
         for remote in &self.remotes {
-            let remote_entry = format!("[remote \"{}\"]\n\turl = {}\n", remote.name, remote.url);
-            config_fmt = format!("{}{}", config_fmt, remote_entry);
+            let section = format!("remote.{}", remote.name);
+            match sections.iter_mut().find(|s| s.0 == section) {
+                Some(s) => s.1.push(("url", &remote.url)),
+                None => sections.push((section, vec![("url", &remote.url)])),
+            }
         }
 
-        let config_file = Path::new(".git").join("config");
-        fs::write(config_file, config_fmt)?;
-        Ok(())
+        for (key, value) in &self.values {
+            let (section, var) = match key.rfind('.') {
+                Some(i) => (normalize_key(&key[..i + 1]), &key[i + 1..]),
+                None => continue,
+            };
+            let section = section.trim_end_matches('.');
+            match sections.iter_mut().find(|s| s.0 == section) {
+                Some(s) => s.1.push((var, value)),
+                None => sections.push((section.to_string(), vec![(var, value)])),
+            }
+        }
+
+        let mut res = String::new();
+        for (section, values) in sections {
+            let header = match section.find('.') {
+                Some(i) => format!("[{} \"{}\"]\n", &section[..i], &section[i + 1..]),
+                None => format!("[{}]\n", section),
+            };
+            res.push_str(&header);
+            for (var, value) in values {
+                res.push_str(&format!("\t{} = {}\n", var, value));
+            }
+        }
+        res
     }
 
     pub fn is_empty(&self) -> bool {
-        self.name.is_empty() && self.email.is_empty() && self.remotes.is_empty()
+        self.name.is_empty()
+            && self.email.is_empty()
+            && self.remotes.is_empty()
+            && self.values.is_empty()
     }
 }
 
@@ -144,8 +229,24 @@ pub fn cmd_config(args: &[String], flags: &[String]) {
 
 pub fn config(option: &str, section: &str, value: &str) -> io::Result<()> {
     let mut user = Config::new()?;
+    let key = section;
     let (section, subsection) = parse_section_name(section);
+    // Only user.name, user.email and the url of remotes are not generic
+    // variables, "remote.<name>" being the url
+    let var = key.splitn(3, '.').nth(2);
+    let is_generic = match section.to_lowercase().as_str() {
+        "user" => subsection != "name" && subsection != "email",
+        "remote" => var.is_some_and(|v| !v.eq_ignore_ascii_case("url")),
+        _ => true,
+    };
     match option {
+        "add" if is_generic => user.set_value(key, value),
+        "get" if is_generic => {
+            if let Some(val) = user.get_value(key) {
+                println!("{}", val);
+            }
+        }
+        "unset" if is_generic => user.unset_value(key),
         "add" => user.add(&section, &subsection, &value),
         "get" => {
             if let Some(val) = user.get(&section, &subsection) {
@@ -159,6 +260,9 @@ pub fn config(option: &str, section: &str, value: &str) -> io::Result<()> {
             for remote in &user.remotes {
                 println!("remote.{} = {}", remote.name, remote.url);
             }
+            for (key, value) in &user.values {
+                println!("{} = {}", key, value);
+            }
         }
         _ => unreachable!(),
     }
@@ -171,6 +275,21 @@ pub fn config(option: &str, section: &str, value: &str) -> io::Result<()> {
     Ok(())
 }
 
+// Section and variable names are case insensitive, subsections are not
+fn normalize_key(key: &str) -> String {
+    let first = key.find('.').unwrap_or(key.len());
+    let last = key.rfind('.').unwrap_or(0);
+    if last <= first {
+        return key.to_lowercase();
+    }
+    format!(
+        "{}{}{}",
+        key[..first].to_lowercase(),
+        &key[first..last],
+        key[last..].to_lowercase()
+    )
+}
+
 fn parse_section_name(section: &str) -> (String, String) {
     match section.starts_with("[") && section.ends_with("]") {
         // [section "subsection"] or [section]
@@ -204,7 +323,7 @@ fn parse_section_name(section: &str) -> (String, String) {
 
 #[cfg(test)]
 mod tests {
-    use builtin::config::parse_section_name;
+    use builtin::config::{normalize_key, parse_section_name, Config};
 
     #[test]
     fn section_parser() {
@@ -226,4 +345,31 @@ mod tests {
         let exp = (String::from("single"), String::new());
         assert_eq!(exp, parse_section_name("single"));
     }
+
+    #[test]
+    fn subsections_keep_a_single_header() {
+        let mut config = Config::from_data("[remote \"origin\"]\n\turl = a/b\n");
+        config.set_value("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*");
+        config.set_value("Remote.origin.Prune", "true");
+        config.set_value("core.bare", "false");
+
+        let data = config.format();
+        assert_eq!(data.matches("[remote \"origin\"]").count(), 1);
+        assert_eq!(data.matches("[core]").count(), 1);
+        let config = Config::from_data(&data);
+        assert_eq!(config.remotes.len(), 1);
+        assert_eq!(config.remotes[0].url, "a/b");
+        assert_eq!(config.get_value("remote.origin.prune"), Some("true"));
+        assert_eq!(
+            config.get_value("remote.origin.fetch"),
+            Some("+refs/heads/*:refs/remotes/origin/*")
+        );
+    }
+
+    #[test]
+    fn key_normalization() {
+        assert_eq!("diff.algorithm", normalize_key("Diff.Algorithm"));
+        assert_eq!("branch.Main.remote", normalize_key("branch.Main.Remote"));
+        assert_eq!("core", normalize_key("CORE"));
+    }
 }
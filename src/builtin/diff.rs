@@ -1,103 +1,1272 @@
 use std::cmp;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
-use std::str;
+use std::io::Write;
+use std::rc::Rc;
 
+use attributes::{Attributes, Value};
+
+use builtin::commit;
+use builtin::config;
+use builtin::hash_object;
+use builtin::read_tree;
+use cli;
+use convert::Converter;
 use index;
 use object;
 use object::Object;
+use pathspec::Pathspec;
+use refs;
+use regex::Regex;
+use rename;
+use revision;
+use xdiff;
+use xdiff::{Algorithm, Edit};
 
 #[derive(Debug)]
 pub enum Error {
+    CommitError(commit::Error),
     IndexError(index::Error),
     IoError(io::Error),
     ObjectError(object::Error),
+    ReadTreeError(read_tree::Error),
+    RevisionError(revision::Error),
+    TooManyRevisions,
 }
 
-enum State {
-    Ins,
-    Del,
-    Eq,
+impl From<commit::Error> for Error {
+    fn from(e: commit::Error) -> Error {
+        Error::CommitError(e)
+    }
+}
+impl From<object::Error> for Error {
+    fn from(e: object::Error) -> Error {
+        Error::ObjectError(e)
+    }
+}
+impl From<read_tree::Error> for Error {
+    fn from(e: read_tree::Error) -> Error {
+        Error::ReadTreeError(e)
+    }
+}
+impl From<revision::Error> for Error {
+    fn from(e: revision::Error) -> Error {
+        Error::RevisionError(e)
+    }
 }
 
-pub fn cmd_diff(args: &[String]) {
-    if let Err(why) = diff(args) {
-        println!("Could not show diff: {:?}", why);
+// How changes are shown by log, show and diff
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub patch: bool,
+    pub stat: bool,
+    pub numstat: bool,
+    pub name_only: bool,
+    pub name_status: bool,
+    pub no_patch: bool,
+    // Lines of context around each hunk
+    pub context: usize,
+    pub algorithm: Algorithm,
+    pub attributes: Rc<Attributes>,
+    pub renames: rename::Options,
+    pub word_diff: Option<WordDiff>,
+    // --word-diff-regex, overrides the one of the diff driver
+    pub word_regex: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WordDiff {
+    // [-removed-]{+added+}
+    Plain,
+    Color,
+    // One word per line, prefixed like in a patch, '~' for newlines
+    Porcelain,
+}
+
+impl WordDiff {
+    pub fn from_name(name: &str) -> Option<WordDiff> {
+        match name {
+            "plain" => Some(WordDiff::Plain),
+            "color" => Some(WordDiff::Color),
+            "porcelain" => Some(WordDiff::Porcelain),
+            _ => None,
+        }
     }
 }
 
-fn diff(paths: &[String]) -> Result<(), Error> {
-    let entries = index::read_entries().map_err(Error::IndexError)?;
-    for entry in &entries {
-        let path = &entry.path;
-        if !paths.is_empty() && !paths.contains(path) {
-            continue;
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            patch: false,
+            stat: false,
+            numstat: false,
+            name_only: false,
+            name_status: false,
+            no_patch: false,
+            context: 3,
+            algorithm: default_algorithm(),
+            renames: rename::Options::from_config(),
+            word_diff: None,
+            word_regex: None,
+            attributes: Rc::new(Attributes::new()),
         }
+    }
 
-        let object = Object::new(&entry.hash).map_err(Error::ObjectError)?;
-        if object.obj_type != "blob" {
-            continue;
+    // Returns false if the flag is not a diff option
+    pub fn parse_flag(&mut self, flag: &str) -> bool {
+        if self.renames.parse_flag(flag) {
+            return true;
+        }
+        if let Some(n) = flag
+            .strip_prefix("--unified=")
+            .or_else(|| flag.strip_prefix("-U"))
+        {
+            return match n.parse::<usize>() {
+                Ok(n) => {
+                    self.context = n;
+                    self.patch = true;
+                    true
+                }
+                Err(_) => false,
+            };
+        }
+
+        if let Some(name) = flag.strip_prefix("--diff-algorithm=") {
+            return match Algorithm::from_name(name) {
+                Some(algorithm) => {
+                    self.algorithm = algorithm;
+                    true
+                }
+                None => false,
+            };
+        }
+
+        if let Some(name) = flag.strip_prefix("--word-diff=") {
+            self.word_diff = WordDiff::from_name(name);
+            self.patch = true;
+            return self.word_diff.is_some();
+        }
+        let regex = flag
+            .strip_prefix("--word-diff-regex=")
+            .or_else(|| flag.strip_prefix("--color-words="));
+        if let Some(regex) = regex {
+            if flag.starts_with("--color-words") {
+                self.word_diff = Some(WordDiff::Color);
+            } else if self.word_diff.is_none() {
+                self.word_diff = Some(WordDiff::Plain);
+            }
+            self.word_regex = Some(regex.to_string());
+            self.patch = true;
+            return Regex::new(regex, false).is_ok();
+        }
+
+        match flag {
+            "-p" | "-u" | "--patch" => self.patch = true,
+            "--word-diff" => {
+                self.word_diff = Some(WordDiff::Plain);
+                self.patch = true;
+            }
+            "--color-words" => {
+                self.word_diff = Some(WordDiff::Color);
+                self.patch = true;
+            }
+            "--stat" => self.stat = true,
+            "--numstat" => self.numstat = true,
+            "--minimal" => self.algorithm = Algorithm::Myers,
+            "--patience" => self.algorithm = Algorithm::Patience,
+            "--histogram" => self.algorithm = Algorithm::Histogram,
+            "--name-only" => self.name_only = true,
+            "--name-status" => self.name_status = true,
+            "-s" | "--no-patch" => {
+                *self = Options {
+                    no_patch: true,
+                    ..Options::new()
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.patch && !self.stat && !self.numstat && !self.name_only && !self.name_status
+    }
+}
+
+pub const FLAGS: [&str; 28] = [
+    "-p",
+    "-u",
+    "--patch",
+    "--stat",
+    "--numstat",
+    "--name-only",
+    "--name-status",
+    "-s",
+    "--no-patch",
+    "-U",
+    "--unified=",
+    "--minimal",
+    "--patience",
+    "--histogram",
+    "--diff-algorithm=",
+    "-M",
+    "--find-renames",
+    "--find-renames=",
+    "-C",
+    "--find-copies",
+    "--find-copies=",
+    "--no-renames",
+    "-l",
+    "--word-diff",
+    "--word-diff=",
+    "--word-diff-regex=",
+    "--color-words",
+    "--color-words=",
+];
+
+// diff.algorithm from the config, Myers otherwise
+fn default_algorithm() -> Algorithm {
+    let config = match config::Config::new() {
+        Ok(config) => config,
+        Err(_) => return Algorithm::Myers,
+    };
+    config
+        .get_value("diff.algorithm")
+        .and_then(Algorithm::from_name)
+        .unwrap_or(Algorithm::Myers)
+}
+
+// A file on one side of a diff
+#[derive(Debug, Clone)]
+pub struct Blob {
+    pub path: String,
+    pub hash: String,
+    pub mode: u32,
+    pub data: Vec<u8>,
+}
+
+impl Blob {
+    pub fn from_object(path: &str, hash: &str, mode: u32) -> Result<Blob, Error> {
+        // The commit of a submodule is not in the repository
+        if mode == index::MODE_GITLINK {
+            return Ok(Blob {
+                path: path.to_string(),
+                hash: hash.to_string(),
+                mode,
+                data: format!("Subproject commit {}\n", hash).into_bytes(),
+            });
+        }
+        let hash = object::expand_hash(hash)?;
+        let object = Object::new(&hash)?;
+        Ok(Blob {
+            path: path.to_string(),
+            hash,
+            mode,
+            data: object.data,
+        })
+    }
+
+    // The content of the blob the file would have, `mode` being the one it
+    // is recorded with
+    pub fn from_file(path: &str, mode: u32, converter: &Converter) -> Result<Blob, Error> {
+        if mode == index::MODE_GITLINK {
+            let hash = index::gitlink_hash(path).map_err(Error::IoError)?;
+            return Blob::from_object(path, &hash, mode);
+        }
+        let data = index::read_blob_data(path, mode, converter).map_err(Error::IoError)?;
+        let hash = hash_object::hash_object(&data, "blob", false).map_err(Error::IoError)?;
+        Ok(Blob {
+            path: path.to_string(),
+            hash,
+            mode,
+            data,
+        })
+    }
+}
+
+// The same file before and after, `None` when it does not exist on that side
+#[derive(Debug)]
+pub struct FilePair {
+    pub old: Option<Blob>,
+    pub new: Option<Blob>,
+    // Similarity of a renamed or copied file with its source
+    pub score: Option<u32>,
+    pub is_copy: bool,
+}
+
+impl FilePair {
+    pub fn path(&self) -> &str {
+        match (&self.old, &self.new) {
+            (_, Some(b)) | (Some(b), None) => &b.path,
+            (None, None) => "",
+        }
+    }
+
+    pub fn status(&self) -> char {
+        match (&self.old, &self.new) {
+            _ if self.is_copy => 'C',
+            _ if self.score.is_some() => 'R',
+            (None, _) => 'A',
+            (_, None) => 'D',
+            _ => 'M',
+        }
+    }
+
+    // "old => new" for renames and copies
+    pub fn display_path(&self) -> String {
+        match (&self.old, &self.new) {
+            (Some(o), Some(n)) if o.path != n.path => format!("{} => {}", o.path, n.path),
+            _ => self.path().to_string(),
+        }
+    }
+
+    fn old_data(&self) -> &[u8] {
+        self.old.as_ref().map_or(&[], |b| &b.data)
+    }
+
+    fn new_data(&self) -> &[u8] {
+        self.new.as_ref().map_or(&[], |b| &b.data)
+    }
+
+    // The diff attribute decides, otherwise a NUL byte in either side
+    pub fn is_binary(&self, attributes: &Attributes) -> bool {
+        match attributes.get(self.path(), "diff") {
+            Some(Value::Unset) => true,
+            Some(_) => false,
+            None => is_binary(self.old_data()) || is_binary(self.new_data()),
+        }
+    }
+}
+
+// Where the files of a diff come from
+#[derive(Debug)]
+pub enum Source {
+    WorkDir,
+    Index,
+    // A commit or a tree, empty for an empty tree
+    Tree(String),
+}
+
+pub fn cmd_diff(args: &[String], flags: &[String]) {
+    let accepted_flags = ["--cached", "--staged"];
+    let accepted_flags = [&accepted_flags[..], &FLAGS[..]].concat();
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+
+    let mut opts = Options::new();
+    let mut cached = false;
+    for flag in flags {
+        if flag == "--cached" || flag == "--staged" {
+            cached = true;
+        } else if !opts.parse_flag(flag) {
+            println!("invalid option: {}", flag);
+            return;
         }
+    }
+    if opts.is_empty() && !opts.no_patch {
+        opts.patch = true;
+    }
+
+    match diff(args, cached, &opts) {
+        // Files may not be valid UTF-8, the diff is written as is
+        Ok(res) => {
+            let _ = io::stdout().write_all(&res);
+        }
+        Err(why) => println!("Could not show diff: {:?}", why),
+    }
+}
 
-        let stored_data = str::from_utf8(&object.data).unwrap();
-        let actual_data = fs::read_to_string(path).map_err(Error::IoError)?;
+// diff [--cached] [<commit> [<commit>]] [-- <path>...]
+fn diff(args: &[String], cached: bool, opts: &Options) -> Result<Vec<u8>, Error> {
+    let (revs, paths) = revision::split_revs_and_paths(args)?;
+    let mut trees = Vec::new();
+    for rev in &revs {
+        match revision::split_range(rev) {
+            Some((a, b, symmetric)) => {
+                let (a, b) = (revision::resolve(&a)?, revision::resolve(&b)?);
+                match symmetric {
+                    true => trees.push(commit::lowest_common_ancestor(&a, &b)?),
+                    false => trees.push(a),
+                }
+                trees.push(b);
+            }
+            None => trees.push(revision::resolve(rev)?),
+        }
+    }
 
-        let stored_lines: Vec<&str> = stored_data.split('\n').collect();
-        let actual_lines: Vec<&str> = actual_data.split('\n').collect();
-        if stored_lines == actual_lines {
+    let (old, new) = match (trees.len(), cached) {
+        (0, false) => (Source::Index, Source::WorkDir),
+        (0, true) => {
+            let head = refs::get_ref_hash("HEAD").unwrap_or_default();
+            (Source::Tree(head), Source::Index)
+        }
+        (1, false) => (Source::Tree(trees.remove(0)), Source::WorkDir),
+        (1, true) => (Source::Tree(trees.remove(0)), Source::Index),
+        (2, false) => (Source::Tree(trees.remove(0)), Source::Tree(trees.remove(0))),
+        _ => return Err(Error::TooManyRevisions),
+    };
+
+    let pairs = diff_sources(&old, &new, &Pathspec::new(&paths), &opts.renames)?;
+    Ok(format_diff(&pairs, opts))
+}
+
+// (path, hash, mode) of every file of the source
+fn read_source(
+    source: &Source,
+    pathspec: &Pathspec,
+    converter: &Converter,
+) -> Result<Vec<(String, String, u32)>, Error> {
+    let mut files = Vec::new();
+    match source {
+        Source::Index | Source::WorkDir => {
+            // Only tracked files are compared with the working dir
+            let index_mtime = index::modified_time();
+            for entry in index::read_entries().map_err(Error::IndexError)? {
+                if !pathspec.matches(&entry.path) {
+                    continue;
+                }
+                if let Source::Index = source {
+                    // Files which are going to be added are new against the
+                    // index too
+                    if !entry.is_intent_to_add() {
+                        files.push((entry.path, entry.hash, entry.mode));
+                    }
+                } else if let Ok(meta) = fs::symlink_metadata(&entry.path) {
+                    let trust_executable_bit = converter.trust_executable_bit();
+                    let mode = index::mode_of(&meta, Some(entry.mode), trust_executable_bit);
+                    if mode == entry.mode && entry.is_up_to_date(&meta, index_mtime) {
+                        files.push((entry.path, entry.hash, mode));
+                        continue;
+                    }
+                    if mode == index::MODE_GITLINK {
+                        // Submodules which are not checked out are unchanged
+                        let hash = index::gitlink_hash(&entry.path).unwrap_or(entry.hash);
+                        files.push((entry.path, hash, mode));
+                        continue;
+                    }
+                    let blob = Blob::from_file(&entry.path, mode, converter)?;
+                    files.push((entry.path, blob.hash, mode));
+                }
+            }
+        }
+        Source::Tree(hash) if hash.is_empty() => (),
+        Source::Tree(hash) => {
+            let mut tree = hash.to_string();
+            if Object::new(hash)?.obj_type == "commit" {
+                tree = commit::get_tree_hash(hash)?;
+            }
+            for entry in read_tree::flatten_tree(&tree)? {
+                if pathspec.matches(&entry.path) {
+                    files.push((entry.path, entry.hash, entry.mode));
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn read_blob(
+    source: &Source,
+    (path, hash, mode): &(String, String, u32),
+    converter: &Converter,
+) -> Result<Blob, Error> {
+    match source {
+        Source::WorkDir => Blob::from_file(path, *mode, converter),
+        _ => Blob::from_object(path, hash, *mode),
+    }
+}
+
+// Files added, deleted or changed between two sources, sorted by path
+pub fn diff_sources(
+    old: &Source,
+    new: &Source,
+    pathspec: &Pathspec,
+    renames: &rename::Options,
+) -> Result<Vec<FilePair>, Error> {
+    let converter = Converter::load().map_err(Error::IoError)?;
+    let old_files = read_source(old, pathspec, &converter)?;
+    let new_files = read_source(new, pathspec, &converter)?;
+    let mut paths: BTreeMap<&str, (Option<usize>, Option<usize>)> = BTreeMap::new();
+    for (i, file) in old_files.iter().enumerate() {
+        paths.entry(&file.0).or_insert((None, None)).0 = Some(i);
+    }
+    for (i, file) in new_files.iter().enumerate() {
+        paths.entry(&file.0).or_insert((None, None)).1 = Some(i);
+    }
+
+    let mut pairs = Vec::new();
+    for (old_i, new_i) in paths.values() {
+        let old_file = old_i.map(|i| &old_files[i]);
+        let new_file = new_i.map(|i| &new_files[i]);
+        if let (Some(o), Some(n)) = (old_file, new_file) {
+            if o.1 == n.1 && o.2 == n.2 {
+                continue;
+            }
+        }
+        pairs.push(FilePair {
+            old: match old_file {
+                Some(f) => Some(read_blob(old, f, &converter)?),
+                None => None,
+            },
+            new: match new_file {
+                Some(f) => Some(read_blob(new, f, &converter)?),
+                None => None,
+            },
+            score: None,
+            is_copy: false,
+        });
+    }
+
+    Ok(find_renames(pairs, renames))
+}
+
+fn as_file(blob: &Option<Blob>, deleted: bool) -> rename::File<'_> {
+    let blob = blob.as_ref().unwrap();
+    rename::File {
+        hash: &blob.hash,
+        data: &blob.data,
+        deleted,
+    }
+}
+
+// Replaces added files coming from deleted (or, for copies, modified) files
+// by renamed or copied ones
+fn find_renames(pairs: Vec<FilePair>, opts: &rename::Options) -> Vec<FilePair> {
+    let sources: Vec<usize> = (0..pairs.len())
+        .filter(|&i| match (&pairs[i].old, &pairs[i].new) {
+            (Some(_), None) => true,
+            (Some(_), Some(_)) => opts.copies,
+            _ => false,
+        })
+        .collect();
+    let dests: Vec<usize> = (0..pairs.len())
+        .filter(|&i| pairs[i].old.is_none())
+        .collect();
+    let source_files: Vec<rename::File> = sources
+        .iter()
+        .map(|&i| as_file(&pairs[i].old, pairs[i].new.is_none()))
+        .collect();
+    let dest_files: Vec<rename::File> = dests
+        .iter()
+        .map(|&i| as_file(&pairs[i].new, false))
+        .collect();
+    let matches = rename::find(&source_files, &dest_files, opts);
+
+    // (destination, source blob, score, is_copy)
+    let mut found = Vec::new();
+    let mut renamed = Vec::new();
+    for (d, m) in matches.iter().enumerate() {
+        if let Some(m) = m {
+            let source = sources[m.source];
+            if !m.is_copy {
+                renamed.push(source);
+            }
+            found.push((dests[d], pairs[source].old.clone(), m.score, m.is_copy));
+        }
+    }
+
+    let mut res = Vec::new();
+    for (i, mut pair) in pairs.into_iter().enumerate() {
+        if renamed.contains(&i) {
             continue;
         }
+        if let Some(f) = found.iter_mut().find(|f| f.0 == i) {
+            pair.old = f.1.take();
+            pair.score = Some(f.2);
+            pair.is_copy = f.3;
+        }
+        res.push(pair);
+    }
+    res.sort_by(|a, b| a.path().cmp(b.path()));
+    res
+}
+
+// Changes between two commits (`oldest` is empty for a root commit)
+pub fn format_commit_changes(
+    oldest: &str,
+    latest: &str,
+    pathspec: &Pathspec,
+    opts: &Options,
+) -> Result<String, Error> {
+    let old = Source::Tree(oldest.to_string());
+    let new = Source::Tree(latest.to_string());
+    let pairs = diff_sources(&old, &new, pathspec, &opts.renames)?;
+    Ok(String::from_utf8_lossy(&format_diff(&pairs, opts)).to_string())
+}
+
+// Same heuristic as git: a NUL byte in the first 8000 bytes
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+// Every output format asked for in `opts`, in git's order
+pub fn format_diff(pairs: &[FilePair], opts: &Options) -> Vec<u8> {
+    let mut res = Vec::new();
+    for pair in pairs {
+        if opts.name_only {
+            res.extend_from_slice(format!("{}\n", pair.path()).as_bytes());
+        } else if opts.name_status {
+            let line = match (&pair.old, pair.score) {
+                (Some(old), Some(score)) => {
+                    let status = format!("{}{:03}", pair.status(), score);
+                    format!("{}\t{}\t{}\n", status, old.path, pair.path())
+                }
+                _ => format!("{}\t{}\n", pair.status(), pair.path()),
+            };
+            res.extend_from_slice(line.as_bytes());
+        }
+    }
 
-        println!("{}:", path);
-        for (state, line) in lcs_diff(&stored_lines, &actual_lines) {
-            let c = match state {
-                State::Ins => '+',
-                State::Del => '-',
-                State::Eq => ' ',
+    let stats: Vec<FileStat> = match opts.stat || opts.numstat {
+        true => pairs.iter().map(|p| file_stat(p, opts)).collect(),
+        false => Vec::new(),
+    };
+    if opts.numstat {
+        for stat in &stats {
+            let line = match stat.binary_sizes {
+                Some(_) => format!("-\t-\t{}\n", stat.path),
+                None => format!("{}\t{}\t{}\n", stat.ins, stat.del, stat.path),
             };
-            println!("{}{}", c, line);
+            res.extend_from_slice(line.as_bytes());
+        }
+    }
+    if opts.stat {
+        res.extend_from_slice(format_stat(&stats).as_bytes());
+    }
+    if (opts.stat || opts.numstat) && opts.patch && !pairs.is_empty() {
+        res.push(b'\n');
+    }
+
+    if opts.patch {
+        for pair in pairs {
+            res.extend_from_slice(&format_patch(pair, opts));
         }
     }
+    res
+}
 
-    Ok(())
+#[derive(Debug)]
+pub struct FileStat {
+    pub path: String,
+    pub ins: usize,
+    pub del: usize,
+    // Old and new sizes of binary files, which have no lines
+    pub binary_sizes: Option<(usize, usize)>,
 }
 
-fn lcs_diff(a: &[&str], b: &[&str]) -> Vec<(State, String)> {
+fn file_stat(pair: &FilePair, opts: &Options) -> FileStat {
+    let mut stat = FileStat {
+        path: pair.display_path(),
+        ins: 0,
+        del: 0,
+        binary_sizes: None,
+    };
+    if pair.is_binary(&opts.attributes) {
+        stat.binary_sizes = Some((pair.old_data().len(), pair.new_data().len()));
+        return stat;
+    }
+
+    let old_lines = split_lines(pair.old_data());
+    let new_lines = split_lines(pair.new_data());
+    let edits = xdiff::diff(&old_lines, &new_lines, opts.algorithm);
+    stat.ins = edits.iter().filter(|&&e| e == Edit::Insert).count();
+    stat.del = edits.iter().filter(|&&e| e == Edit::Delete).count();
+    stat
+}
+
+// Lines keep their '\n', so that a missing newline at the end of file
+// counts as a change
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+fn abbrev(blob: &Option<Blob>) -> &str {
+    match blob {
+        Some(b) => &b.hash[..7],
+        None => "0000000",
+    }
+}
+
+// diff --git header followed by the hunks
+pub fn format_patch(pair: &FilePair, opts: &Options) -> Vec<u8> {
+    let (old_path, new_path) = match (&pair.old, &pair.new) {
+        (Some(o), Some(n)) => (o.path.as_str(), n.path.as_str()),
+        _ => (pair.path(), pair.path()),
+    };
+    let mut res = format!("diff --git a/{} b/{}\n", old_path, new_path);
+    // The rename comes before a mode change of the renamed file
+    if let Some(score) = pair.score {
+        let kind = match pair.is_copy {
+            true => "copy",
+            false => "rename",
+        };
+        res.push_str(&format!(
+            "similarity index {}%\n{} from {}\n{} to {}\n",
+            score, kind, old_path, kind, new_path
+        ));
+    }
+
+    let mut index_mode = String::new();
+    match (&pair.old, &pair.new) {
+        (None, Some(n)) => res.push_str(&format!("new file mode {:o}\n", n.mode)),
+        (Some(o), None) => res.push_str(&format!("deleted file mode {:o}\n", o.mode)),
+        (Some(o), Some(n)) if o.mode != n.mode => {
+            res.push_str(&format!("old mode {:o}\nnew mode {:o}\n", o.mode, n.mode));
+        }
+        (Some(o), Some(_)) => index_mode = format!(" {:o}", o.mode),
+        (None, None) => (),
+    }
+
+    let same_content = match (&pair.old, &pair.new) {
+        (Some(o), Some(n)) => o.hash == n.hash,
+        _ => false,
+    };
+    if same_content {
+        return res.into_bytes();
+    }
+
+    res.push_str(&format!(
+        "index {}..{}{}\n",
+        abbrev(&pair.old),
+        abbrev(&pair.new),
+        index_mode
+    ));
+    let old_name = match pair.old {
+        Some(_) => format!("a/{}", old_path),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match pair.new {
+        Some(_) => format!("b/{}", new_path),
+        None => "/dev/null".to_string(),
+    };
+    if pair.is_binary(&opts.attributes) {
+        res.push_str(&format!(
+            "Binary files {} and {} differ\n",
+            old_name, new_name
+        ));
+        return res.into_bytes();
+    }
+
+    let hunks = match opts.word_diff {
+        Some(mode) => {
+            let regex = word_regex(pair.path(), opts);
+            let (old, new) = (pair.old_data(), pair.new_data());
+            format_word_hunks(old, new, opts.context, opts.algorithm, mode, regex.as_ref())
+        }
+        None => format_hunks(
+            pair.old_data(),
+            pair.new_data(),
+            opts.context,
+            opts.algorithm,
+        ),
+    };
+    let mut res = res.into_bytes();
+    if !hunks.is_empty() {
+        let names = format!("--- {}\n+++ {}\n", old_name, new_name);
+        res.extend_from_slice(names.as_bytes());
+        res.extend_from_slice(&hunks);
+    }
+    res
+}
+
+// @@ -<start>,<count> +<start>,<count> @@ blocks with `context` unchanged
+// lines around each change, close changes share the same hunk
+pub fn format_hunks(old: &[u8], new: &[u8], context: usize, algorithm: Algorithm) -> Vec<u8> {
+    let script = line_script(old, new, algorithm);
     let mut res = Vec::new();
-    let lcs = longest_common_subseq(a, b);
-    let mut i = a.len();
-    let mut j = b.len();
-    loop {
-        if i > 0 && j > 0 && a[i - 1] == b[j - 1] {
-            res.push((State::Eq, a[i - 1].to_string()));
-            i -= 1;
-            j -= 1;
-        } else if j > 0 && (i == 0 || lcs[i][j - 1] >= lcs[i - 1][j]) {
-            res.push((State::Ins, b[j - 1].to_string()));
-            j -= 1;
-        } else if i > 0 && (j == 0 || lcs[i][j - 1] < lcs[i - 1][j]) {
-            res.push((State::Del, a[i - 1].to_string()));
-            i -= 1;
-        } else {
-            break;
-        }
-    }
-    res.reverse();
-    return res;
-}
-
-fn longest_common_subseq(a: &[&str], b: &[&str]) -> Vec<Vec<u32>> {
-    let m = a.len() + 1;
-    let n = b.len() + 1;
-    let mut lcs = vec![vec![0u32; n]; m];
-    for i in 1..m {
-        for j in 1..n {
-            if a[i - 1] == b[j - 1] {
-                lcs[i][j] = lcs[i - 1][j - 1] + 1;
-            } else {
-                lcs[i][j] = cmp::max(lcs[i][j - 1], lcs[i - 1][j]);
-            }
-        }
-    }
-    return lcs;
+    for (start, end) in hunk_bounds(&script, context) {
+        res.extend_from_slice(&format_hunk(&script, start, end));
+    }
+    res
+}
+
+// One hunk of the script, with its header
+pub fn format_hunk(script: &[(Edit, &[u8])], start: usize, end: usize) -> Vec<u8> {
+    let mut res = hunk_header(script, start, end).into_bytes();
+    for (state, line) in &script[start..end] {
+        let c = match state {
+            Edit::Insert => b'+',
+            Edit::Delete => b'-',
+            Edit::Equal => b' ',
+        };
+        res.push(c);
+        res.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            res.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+    res
+}
+
+// Each edit of the line diff along with its line
+pub fn line_script<'a>(
+    old: &'a [u8],
+    new: &'a [u8],
+    algorithm: Algorithm,
+) -> Vec<(Edit, &'a [u8])> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = xdiff::diff(&old_lines, &new_lines, algorithm);
+
+    let mut script = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in edits {
+        match edit {
+            Edit::Delete => {
+                script.push((edit, old_lines[i]));
+                i += 1;
+            }
+            Edit::Insert => {
+                script.push((edit, new_lines[j]));
+                j += 1;
+            }
+            Edit::Equal => {
+                script.push((edit, old_lines[i]));
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    script
+}
+
+// Ranges of the script shown in each hunk
+pub fn hunk_bounds(script: &[(Edit, &[u8])], context: usize) -> Vec<(usize, usize)> {
+    let changed: Vec<usize> = (0..script.len())
+        .filter(|&k| script[k].0 != Edit::Equal)
+        .collect();
+
+    let mut bounds = Vec::new();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(context);
+        while k + 1 < changed.len() && changed[k + 1] - changed[k] - 1 <= 2 * context {
+            k += 1;
+        }
+        let end = cmp::min(script.len(), changed[k] + context + 1);
+        k += 1;
+        bounds.push((start, end));
+    }
+    bounds
+}
+
+fn hunk_header(script: &[(Edit, &[u8])], start: usize, end: usize) -> String {
+    // Lines of each file in a part of the script
+    let count = |edits: &[(Edit, &[u8])]| {
+        let old = edits.iter().filter(|(e, _)| *e != Edit::Insert).count();
+        let new = edits.iter().filter(|(e, _)| *e != Edit::Delete).count();
+        (old, new)
+    };
+    let (old_start, new_start) = count(&script[..start]);
+    let (old_len, new_len) = count(&script[start..end]);
+    format!(
+        "@@ -{} +{} @@\n",
+        hunk_range(old_start, old_len),
+        hunk_range(new_start, new_len)
+    )
+}
+
+// Pattern of a word: --word-diff-regex, then diff.<driver>.wordRegex for the
+// diff attribute of the path, then diff.wordRegex. Words are separated by
+// whitespace otherwise.
+fn word_regex(path: &str, opts: &Options) -> Option<Regex> {
+    let pattern = match &opts.word_regex {
+        Some(pattern) => Some(pattern.to_string()),
+        None => {
+            let config = config::Config::new().ok()?;
+            let driver = match opts.attributes.get(path, "diff") {
+                Some(Value::Value(driver)) => {
+                    config.get_value(&format!("diff.{}.wordregex", driver))
+                }
+                _ => None,
+            };
+            driver
+                .or_else(|| config.get_value("diff.wordregex"))
+                .map(|p| p.to_string())
+        }
+    };
+    // Non-space characters not matched are words of their own
+    pattern.and_then(|p| Regex::new(&format!("({})|[^[:space:]]", p), false).ok())
+}
+
+#[derive(Debug, PartialEq)]
+enum Word {
+    Common(String),
+    Deleted(String),
+    Added(String),
+}
+
+// Hunks where the changed lines are compared word by word, the unchanged
+// lines are shown as is
+pub fn format_word_hunks(
+    old: &[u8],
+    new: &[u8],
+    context: usize,
+    algorithm: Algorithm,
+    mode: WordDiff,
+    regex: Option<&Regex>,
+) -> Vec<u8> {
+    let script = line_script(old, new, algorithm);
+    let mut res = String::new();
+    for (start, end) in hunk_bounds(&script, context) {
+        let header = hunk_header(&script, start, end);
+        match mode {
+            WordDiff::Color => res.push_str(&format!("\x1b[36m{}\x1b[m\n", header.trim_end())),
+            _ => res.push_str(&header),
+        }
+
+        let mut words = Vec::new();
+        let mut k = start;
+        while k < end {
+            if script[k].0 == Edit::Equal {
+                push_common(&mut words, String::from_utf8_lossy(script[k].1).to_string());
+                k += 1;
+                continue;
+            }
+
+            let (mut old_text, mut new_text) = (Vec::new(), Vec::new());
+            while k < end && script[k].0 != Edit::Equal {
+                match script[k].0 {
+                    Edit::Delete => old_text.extend_from_slice(script[k].1),
+                    _ => new_text.extend_from_slice(script[k].1),
+                }
+                k += 1;
+            }
+            let old_text = String::from_utf8_lossy(&old_text);
+            let new_text = String::from_utf8_lossy(&new_text);
+            words.extend(diff_words(&old_text, &new_text, algorithm, regex));
+            // The changed lines always end the line
+            let ends_line = match words.last() {
+                Some(Word::Common(t)) | Some(Word::Deleted(t)) | Some(Word::Added(t)) => {
+                    t.ends_with('\n')
+                }
+                None => true,
+            };
+            if !ends_line {
+                push_common(&mut words, "\n".to_string());
+            }
+        }
+        res.push_str(&format_words(&words, mode));
+    }
+    res.into_bytes()
+}
+
+// Byte ranges of the words of a text
+fn split_words(text: &str, regex: Option<&Regex>) -> Vec<(usize, usize)> {
+    if let Some(regex) = regex {
+        return regex.find_iter(text);
+    }
+
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), start) {
+            (true, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+    words
+}
+
+// The text between the words is taken from the new side
+fn diff_words(old: &str, new: &str, algorithm: Algorithm, regex: Option<&Regex>) -> Vec<Word> {
+    let old_words = split_words(old, regex);
+    let new_words = split_words(new, regex);
+    let old_tokens: Vec<&str> = old_words.iter().map(|&(s, e)| &old[s..e]).collect();
+    let new_tokens: Vec<&str> = new_words.iter().map(|&(s, e)| &new[s..e]).collect();
+    let edits = xdiff::diff(&old_tokens, &new_tokens, algorithm);
+
+    let mut words = Vec::new();
+    let (mut i, mut j, mut pos) = (0, 0, 0);
+    let mut k = 0;
+    while k < edits.len() {
+        if edits[k] == Edit::Equal {
+            let end = new_words[j].1;
+            push_common(&mut words, new[pos..end].to_string());
+            pos = end;
+            i += 1;
+            j += 1;
+            k += 1;
+            continue;
+        }
+
+        let (first_i, first_j) = (i, j);
+        while k < edits.len() && edits[k] != Edit::Equal {
+            match edits[k] {
+                Edit::Delete => i += 1,
+                _ => j += 1,
+            }
+            k += 1;
+        }
+        // Space before an addition. A removal alone directly follows the
+        // previous word, the space being shown after it.
+        if j > first_j && new_words[first_j].0 > pos {
+            let gap_end = new_words[first_j].0;
+            push_common(&mut words, new[pos..gap_end].to_string());
+            pos = gap_end;
+        }
+        if i > first_i {
+            let removed = &old[old_words[first_i].0..old_words[i - 1].1];
+            words.push(Word::Deleted(removed.to_string()));
+        }
+        if j > first_j {
+            let end = new_words[j - 1].1;
+            words.push(Word::Added(new[pos..end].to_string()));
+            pos = end;
+        }
+    }
+    if pos < new.len() {
+        push_common(&mut words, new[pos..].to_string());
+    }
+    words
+}
+
+// Consecutive unchanged texts are shown as one
+fn push_common(words: &mut Vec<Word>, text: String) {
+    if let Some(Word::Common(last)) = words.last_mut() {
+        last.push_str(&text);
+        return;
+    }
+    words.push(Word::Common(text));
+}
+
+fn format_words(words: &[Word], mode: WordDiff) -> String {
+    let mut res = String::new();
+    for word in words {
+        if mode == WordDiff::Porcelain {
+            let (prefix, text) = match word {
+                Word::Common(t) => (' ', t),
+                Word::Deleted(t) => ('-', t),
+                Word::Added(t) => ('+', t),
+            };
+            for (n, part) in text.split('\n').enumerate() {
+                if n > 0 {
+                    res.push_str("~\n");
+                }
+                if !part.is_empty() {
+                    res.push_str(&format!("{}{}\n", prefix, part));
+                }
+            }
+            continue;
+        }
+
+        match (word, mode) {
+            (Word::Common(t), _) => res.push_str(t),
+            (Word::Deleted(t), WordDiff::Color) => res.push_str(&format!("\x1b[31m{}\x1b[m", t)),
+            (Word::Added(t), WordDiff::Color) => res.push_str(&format!("\x1b[32m{}\x1b[m", t)),
+            (Word::Deleted(t), _) => res.push_str(&format!("[-{}-]", t)),
+            (Word::Added(t), _) => res.push_str(&format!("{{+{}+}}", t)),
+        }
+    }
+    res
+}
+
+// An empty range starts at the line before it
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+// path | 3 ++-
+pub fn format_stat(stats: &[FileStat]) -> String {
+    if stats.is_empty() {
+        return String::new();
+    }
+
+    let name_width = stats.iter().map(|s| s.path.len()).max().unwrap_or(0);
+    let max_changes = stats.iter().map(|s| s.ins + s.del).max().unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    let graph_width = 80usize.saturating_sub(name_width + count_width + 6).max(10);
+
+    let mut res = String::new();
+    let (mut total_ins, mut total_del) = (0, 0);
+    for stat in stats {
+        if let Some((old_size, new_size)) = stat.binary_sizes {
+            res.push_str(&format!(
+                " {:name_width$} | Bin {} -> {} bytes\n",
+                stat.path,
+                old_size,
+                new_size,
+                name_width = name_width
+            ));
+            continue;
+        }
+
+        let (ins, del) = (stat.ins, stat.del);
+        total_ins += ins;
+        total_del += del;
+        let (mut plus, mut minus) = (ins, del);
+        if max_changes > graph_width {
+            plus = (ins * graph_width).div_ceil(max_changes);
+            minus = (del * graph_width).div_ceil(max_changes);
+        }
+        res.push_str(&format!(
+            " {:name_width$} | {:>count_width$} {}{}\n",
+            stat.path,
+            ins + del,
+            "+".repeat(plus),
+            "-".repeat(minus),
+            name_width = name_width,
+            count_width = count_width
+        ));
+    }
+
+    let plural = |n: usize, word: &str| match n {
+        1 => format!("{} {}", n, word),
+        _ => format!("{} {}s", n, word),
+    };
+    let mut summary = format!(" {} changed", plural(stats.len(), "file"));
+    if total_ins > 0 || total_del == 0 {
+        summary.push_str(&format!(", {}(+)", plural(total_ins, "insertion")));
+    }
+    if total_del > 0 {
+        summary.push_str(&format!(", {}(-)", plural(total_del, "deletion")));
+    }
+    res.push_str(&summary);
+    res.push('\n');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use builtin::diff::{
+        format_hunks, format_patch, format_word_hunks, is_binary, Blob, FilePair, Options, WordDiff,
+    };
+    use regex::Regex;
+    use xdiff::Algorithm;
+
+    fn hunks(old: &str, new: &str, context: usize) -> String {
+        let hunks = format_hunks(old.as_bytes(), new.as_bytes(), context, Algorithm::Myers);
+        String::from_utf8(hunks).unwrap()
+    }
+
+    #[test]
+    fn hunks_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n";
+        let expected = "@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n";
+        assert_eq!(hunks(old, new, 3), expected);
+        assert_eq!(hunks(old, new, 0), "@@ -5 +5 @@\n-5\n+five\n");
+    }
+
+    #[test]
+    fn close_changes_share_a_hunk() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "A\nb\nc\nd\ne\nf\ng\nH\n";
+        assert_eq!(
+            hunks(old, new, 1),
+            "@@ -1,2 +1,2 @@\n-a\n+A\n b\n@@ -7,2 +7,2 @@\n g\n-h\n+H\n"
+        );
+        assert_eq!(hunks(old, new, 3).matches("@@ -").count(), 1);
+    }
+
+    #[test]
+    fn new_file_and_missing_newline() {
+        assert_eq!(
+            hunks("", "a\nb", 3),
+            "@@ -0,0 +1,2 @@\n+a\n+b\n\\ No newline at end of file\n"
+        );
+        let expected = "@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+a\n";
+        assert_eq!(hunks("a", "a\n", 3), expected);
+    }
+
+    fn word_hunks(old: &str, new: &str, mode: WordDiff, regex: Option<&str>) -> String {
+        let regex = regex.map(|r| Regex::new(r, false).unwrap());
+        let hunks = format_word_hunks(
+            old.as_bytes(),
+            new.as_bytes(),
+            0,
+            Algorithm::Myers,
+            mode,
+            regex.as_ref(),
+        );
+        String::from_utf8(hunks).unwrap()
+    }
+
+    #[test]
+    fn word_diff() {
+        let old = "the quick brown fox\njumps\n";
+        let new = "the slow brown fox\njumps\n";
+        assert_eq!(
+            word_hunks(old, new, WordDiff::Plain, None),
+            "@@ -1 +1 @@\nthe [-quick-]{+slow+} brown fox\n"
+        );
+        assert_eq!(
+            word_hunks(old, new, WordDiff::Porcelain, None),
+            "@@ -1 +1 @@\n the \n-quick\n+slow\n  brown fox\n~\n"
+        );
+        assert_eq!(
+            word_hunks("a b c\n", "a c\n", WordDiff::Plain, None),
+            "@@ -1 +1 @@\na[-b-] c\n"
+        );
+        assert_eq!(
+            word_hunks("a b c\n", "a c\n", WordDiff::Porcelain, None),
+            "@@ -1 +1 @@\n a\n-b\n  c\n~\n"
+        );
+        assert_eq!(
+            word_hunks("a b\nc\n", "a\nc\n", WordDiff::Plain, None),
+            "@@ -1 +1 @@\na[-b-]\n"
+        );
+        assert_eq!(
+            word_hunks(
+                "{\"a\":1}\n",
+                "{\"a\":2}\n",
+                WordDiff::Plain,
+                Some("[a-z0-9]+")
+            ),
+            "@@ -1 +1 @@\n{\"a\":[-1-]{+2+}}\n"
+        );
+    }
+
+    #[test]
+    fn rename_with_mode_change() {
+        let blob = |path: &str, hash: &str, mode: u32, data: &str| Blob {
+            path: path.to_string(),
+            hash: hash.repeat(40),
+            mode,
+            data: data.as_bytes().to_vec(),
+        };
+        let pair = FilePair {
+            old: Some(blob("a", "1", 0o100644, "1\n2\n")),
+            new: Some(blob("b", "2", 0o100755, "1\n2\n3\n")),
+            score: Some(66),
+            is_copy: false,
+        };
+        let patch = String::from_utf8(format_patch(&pair, &Options::new())).unwrap();
+        let header: Vec<&str> = patch.lines().take(7).collect();
+        assert_eq!(
+            header,
+            vec![
+                "diff --git a/a b/b",
+                "similarity index 66%",
+                "rename from a",
+                "rename to b",
+                "old mode 100644",
+                "new mode 100755",
+                "index 1111111..2222222",
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(!is_binary(b"text\n"));
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0"));
+        assert!(!is_binary("caf\u{e9}".as_bytes()));
+        let mut late_nul = vec![b'a'; 9000];
+        late_nul.push(0);
+        assert!(!is_binary(&late_nul));
+    }
 }
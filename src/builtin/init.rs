@@ -17,7 +17,7 @@ pub fn init(dir_name: &str) -> io::Result<()> {
 
     let git_path = Path::new(&dir_name).join(".git");
     fs::create_dir(&git_path)?;
-    for dir in ["objects", "refs", "refs/heads", "refs/remotes"].iter() {
+    for dir in ["objects", "refs", "refs/heads", "refs/remotes", "refs/tags"].iter() {
         fs::create_dir(git_path.join(dir))?;
     }
     fs::write(git_path.join("HEAD"), "ref: refs/heads/master\n")?;
@@ -1,34 +1,820 @@
+use std::collections::HashMap;
 use std::io;
 
-use builtin::cat_file;
 use builtin::commit;
+use builtin::commit::Commit;
+use builtin::diff;
+use builtin::tag;
+use cli;
+use date;
+use pathspec::Pathspec;
 use refs;
+use regex;
+use regex::Regex;
+use revision;
 
 #[derive(Debug)]
 pub enum Error {
-    CatFileError(cat_file::Error),
     CommitError(commit::Error),
+    DiffError(diff::Error),
+    FollowNeedsOnePath,
+    InvalidOption(String),
     RefError(io::Error),
+    RegexError(regex::Error),
+    RevisionError(revision::Error),
+    TagError(tag::Error),
 }
 
-pub fn cmd_log() {
-    if let Err(why) = log() {
-        println!("Cannot go through log: {:?}", why);
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::RefError(e)
     }
 }
+impl From<revision::Error> for Error {
+    fn from(e: revision::Error) -> Error {
+        Error::RevisionError(e)
+    }
+}
+impl From<tag::Error> for Error {
+    fn from(e: tag::Error) -> Error {
+        Error::TagError(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    // format string, true when used as a terminator (tformat)
+    Custom(String, bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decorate {
+    No,
+    Short,
+    Full,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub format: Format,
+    pub abbrev: bool,
+    pub date: date::Mode,
+    pub decorate: Decorate,
+    pub graph: bool,
+    pub all: bool,
+    pub max_count: Option<usize>,
+    pub authors: Vec<String>,
+    pub committers: Vec<String>,
+    pub greps: Vec<String>,
+    pub ignore_case: bool,
+    pub all_match: bool,
+    pub invert_grep: bool,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub follow: bool,
+    pub diff: diff::Options,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            format: Format::Medium,
+            abbrev: false,
+            date: date::Mode::Default,
+            decorate: Decorate::No,
+            graph: false,
+            all: false,
+            max_count: None,
+            authors: Vec::new(),
+            committers: Vec::new(),
+            greps: Vec::new(),
+            ignore_case: false,
+            all_match: false,
+            invert_grep: false,
+            since: None,
+            until: None,
+            follow: false,
+            diff: diff::Options::new(),
+        }
+    }
+
+    pub fn parse(flags: &[String]) -> Result<Options, Error> {
+        let mut opts = Options::new();
+
+        for flag in flags {
+            let (name, value) = match flag.find('=') {
+                Some(i) => (&flag[..i], Some(&flag[i + 1..])),
+                None => (flag.as_str(), None),
+            };
+            match name {
+                "--pretty" | "--format" => {
+                    let value = value.unwrap_or("medium");
+                    opts.format = parse_format(value, name == "--format")?;
+                }
+                "--oneline" => {
+                    opts.format = Format::Oneline;
+                    opts.abbrev = true;
+                }
+                "--abbrev-commit" => opts.abbrev = true,
+                "--no-abbrev-commit" => opts.abbrev = false,
+                "--date" => {
+                    let value = value.unwrap_or("default");
+                    opts.date = match date::Mode::from_name(value) {
+                        Some(mode) => mode,
+                        None => return Err(Error::InvalidOption(flag.to_string())),
+                    };
+                }
+                "--decorate" => {
+                    opts.decorate = match value.unwrap_or("short") {
+                        "short" | "auto" => Decorate::Short,
+                        "full" => Decorate::Full,
+                        "no" => Decorate::No,
+                        _ => return Err(Error::InvalidOption(flag.to_string())),
+                    }
+                }
+                "--no-decorate" => opts.decorate = Decorate::No,
+                "--graph" => opts.graph = true,
+                "--all" => opts.all = true,
+                "--max-count" | "-n" => {
+                    opts.max_count = value.and_then(|v| v.parse::<usize>().ok());
+                }
+                "--author" | "--committer" | "--grep" => {
+                    let value = value.unwrap_or("").to_string();
+                    match name {
+                        "--author" => opts.authors.push(value),
+                        "--committer" => opts.committers.push(value),
+                        _ => opts.greps.push(value),
+                    }
+                }
+                "-i" | "--regexp-ignore-case" => opts.ignore_case = true,
+                "--all-match" => opts.all_match = true,
+                "--invert-grep" => opts.invert_grep = true,
+                "--since" | "--after" | "--until" | "--before" => {
+                    let time = match value.and_then(date::parse) {
+                        Some(t) => t,
+                        None => return Err(Error::InvalidOption(flag.to_string())),
+                    };
+                    match name {
+                        "--since" | "--after" => opts.since = Some(time),
+                        _ => opts.until = Some(time),
+                    }
+                }
+                "--follow" => opts.follow = true,
+                _ if opts.diff.parse_flag(flag) => (),
+                _ => {
+                    // -<n> and -n<n>
+                    let count = flag.trim_start_matches("-n").trim_start_matches('-');
+                    match count.parse::<usize>() {
+                        Ok(n) => opts.max_count = Some(n),
+                        Err(_) => return Err(Error::InvalidOption(flag.to_string())),
+                    }
+                }
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+fn parse_format(value: &str, is_format_flag: bool) -> Result<Format, Error> {
+    let format = match value {
+        "oneline" => Format::Oneline,
+        "short" => Format::Short,
+        "medium" => Format::Medium,
+        "full" => Format::Full,
+        "fuller" => Format::Fuller,
+        "raw" => Format::Raw,
+        v if v.starts_with("format:") => Format::Custom(v[7..].to_string(), is_format_flag),
+        v if v.starts_with("tformat:") => Format::Custom(v[8..].to_string(), true),
+        v if v.contains('%') || is_format_flag => Format::Custom(v.to_string(), true),
+        v => return Err(Error::InvalidOption(v.to_string())),
+    };
+    Ok(format)
+}
 
-fn log() -> Result<(), Error> {
-    let mut commit_hash = refs::get_ref_hash("HEAD").map_err(Error::RefError)?;
-    loop {
-        println!("commit {}", commit_hash);
-        cat_file::cat_file(&commit_hash, "--print").map_err(Error::CatFileError)?;
+pub fn cmd_log(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--pretty",
+        "--pretty=",
+        "--format=",
+        "--oneline",
+        "--abbrev-commit",
+        "--no-abbrev-commit",
+        "--date=",
+        "--decorate",
+        "--decorate=",
+        "--no-decorate",
+        "--graph",
+        "--all",
+        "--max-count=",
+        "--author=",
+        "--committer=",
+        "--grep=",
+        "-i",
+        "--regexp-ignore-case",
+        "--all-match",
+        "--invert-grep",
+        "--since=",
+        "--after=",
+        "--until=",
+        "--before=",
+        "--follow",
+    ];
+    let accepted_flags = [&accepted_flags[..], &diff::FLAGS[..]].concat();
+    let (counts, flags): (Vec<String>, Vec<String>) = flags.iter().cloned().partition(|f| {
+        f.trim_start_matches("-n")
+            .trim_start_matches('-')
+            .parse::<usize>()
+            .is_ok()
+    });
+    if cli::has_known_flags(&flags, &accepted_flags) {
+        let all_flags = [flags, counts].concat();
+        let res = Options::parse(&all_flags).and_then(|opts| log(args, &opts));
+        if let Err(why) = res {
+            println!("Cannot go through log: {:?}", why);
+        }
+    }
+}
+
+struct Filter {
+    authors: Vec<Regex>,
+    committers: Vec<Regex>,
+    greps: Vec<Regex>,
+    all_match: bool,
+    invert_grep: bool,
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl Filter {
+    fn new(opts: &Options) -> Result<Filter, Error> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>, Error> {
+            patterns
+                .iter()
+                .map(|p| Regex::new(p, opts.ignore_case).map_err(Error::RegexError))
+                .collect()
+        };
+
+        Ok(Filter {
+            authors: compile(&opts.authors)?,
+            committers: compile(&opts.committers)?,
+            greps: compile(&opts.greps)?,
+            all_match: opts.all_match,
+            invert_grep: opts.invert_grep,
+            since: opts.since,
+            until: opts.until,
+        })
+    }
+
+    fn matches(&self, commit: &Commit) -> bool {
+        let time = commit.committer.time;
+        if self.since.is_some_and(|s| time < s) || self.until.is_some_and(|u| time > u) {
+            return false;
+        }
+
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+        let committer = format!("{} <{}>", commit.committer.name, commit.committer.email);
+        let matches_any = |patterns: &[Regex], text: &str| {
+            patterns.is_empty() || patterns.iter().any(|p| p.is_match(text))
+        };
+        if !matches_any(&self.authors, &author) || !matches_any(&self.committers, &committer) {
+            return false;
+        }
 
-        let parents = commit::get_parents_hashes(&commit_hash).map_err(Error::CommitError)?;
-        // Simple linear log, ignore multiple parents
-        commit_hash = match parents.get(0) {
-            Some(h) => h.to_string(),
-            None => break,
+        if self.greps.is_empty() {
+            return true;
+        }
+        // Patterns are matched against each line of the message
+        let is_found = |p: &Regex| commit.message.lines().any(|l| p.is_match(l));
+        let found = match self.all_match {
+            true => self.greps.iter().all(is_found),
+            false => self.greps.iter().any(is_found),
         };
+        found != self.invert_grep
     }
+}
+
+fn log(args: &[String], opts: &Options) -> Result<(), Error> {
+    let (revs, paths) = revision::split_revs_and_paths(args).map_err(Error::RevisionError)?;
+    let mut starts = Vec::new();
+    for rev in &revs {
+        starts.push(revision::resolve(rev)?);
+    }
+    if opts.all {
+        for (_, hash) in refs::list_refs()? {
+            starts.push(hash);
+        }
+    }
+    if starts.is_empty() || (opts.all && revision::is_revision("HEAD")) {
+        starts.push(revision::resolve("HEAD")?);
+    }
+
+    let pathspec = Pathspec::new(&paths);
+    let mut commits = match (opts.follow, pathspec.is_empty()) {
+        (true, _) if paths.len() != 1 => return Err(Error::FollowNeedsOnePath),
+        (true, _) => revision::walk_follow(&starts, &paths[0])?,
+        (false, false) => revision::walk_paths(&starts, &pathspec)?,
+        (false, true) => revision::walk(&starts)?,
+    };
+    let filter = Filter::new(opts)?;
+    commits.retain(|c| filter.matches(c));
+    if let Some(max) = opts.max_count {
+        commits.truncate(max);
+    }
+
+    let decorations = match opts.decorate {
+        Decorate::No => decorations(Decorate::Short)?,
+        d => decorations(d)?,
+    };
+    let mut graph = Graph::new();
+    let nb_commits = commits.len();
+    for (i, commit) in commits.iter().enumerate() {
+        let names = decorations.get(&commit.hash);
+        let mut text = format_commit(commit, opts, names);
+        let changes = format_changes(commit, &opts.diff, &pathspec)?;
+        if !changes.is_empty() {
+            let separator = match opts.format {
+                Format::Oneline | Format::Custom(_, _) => "\n",
+                _ => "\n\n",
+            };
+            text = format!("{}{}{}", text, separator, changes.trim_end());
+        }
+        let is_last = i + 1 == nb_commits;
+        print_commit(&text, commit, opts, &mut graph, is_last);
+    }
+
     Ok(())
 }
+
+// Changes introduced by the commit, nothing shown for merges
+pub fn format_changes(
+    commit: &Commit,
+    opts: &diff::Options,
+    pathspec: &Pathspec,
+) -> Result<String, Error> {
+    if opts.is_empty() {
+        return Ok(String::new());
+    }
+    // Parents may have been rewritten by history simplification
+    let parents = commit::get_parents_hashes(&commit.hash).map_err(Error::CommitError)?;
+    if parents.len() > 1 {
+        return Ok(String::new());
+    }
+    let parent = match parents.first() {
+        Some(p) => p.to_string(),
+        None => String::new(),
+    };
+    diff::format_commit_changes(&parent, &commit.hash, pathspec, opts).map_err(Error::DiffError)
+}
+
+fn print_commit(text: &str, commit: &Commit, opts: &Options, graph: &mut Graph, is_last: bool) {
+    // format: separates entries, tformat: and oneline terminate them, the
+    // other formats keep a blank line between entries
+    let (has_blank_line, has_newline) = match opts.format {
+        Format::Oneline | Format::Custom(_, true) => (false, true),
+        Format::Custom(_, false) => (false, !is_last),
+        _ => (!is_last, true),
+    };
+
+    if !opts.graph {
+        match has_newline {
+            true => println!("{}", text),
+            false => print!("{}", text),
+        }
+        if has_blank_line {
+            println!();
+        }
+        return;
+    }
+
+    let (row, transitions, padding) = graph.update(commit);
+    let mut lines = text.lines();
+    println!("{} {}", row, lines.next().unwrap_or(""));
+    let mut transitions = transitions.into_iter();
+    for line in lines {
+        let prefix = transitions.next().unwrap_or(padding.to_string());
+        println!("{}", format!("{} {}", pad(&prefix, &row), line).trim_end());
+    }
+    for transition in transitions {
+        println!("{}", transition);
+    }
+    if has_blank_line {
+        println!("{}", padding);
+    }
+}
+
+fn pad(prefix: &str, row: &str) -> String {
+    let width = row.chars().count();
+    format!("{:width$}", prefix, width = width)
+}
+
+// Ref names pointing at each commit, HEAD first
+pub fn decorations(mode: Decorate) -> Result<HashMap<String, Vec<String>>, Error> {
+    let mut decorations: HashMap<String, Vec<String>> = HashMap::new();
+    let head_hash = refs::get_ref_hash("HEAD")?;
+    let head_branch = match refs::is_detached_head() {
+        true => String::new(),
+        false => format!("refs/heads/{}", refs::read_ref("HEAD")?),
+    };
+    if refs::is_detached_head() && !head_hash.is_empty() {
+        decorations.insert(head_hash.to_string(), vec![String::from("HEAD")]);
+    }
+
+    for (name, hash) in refs::list_refs()? {
+        let display = match mode {
+            Decorate::Full => name.to_string(),
+            _ => refs::short_name(&name).to_string(),
+        };
+        let display = match name.starts_with("refs/tags/") {
+            true => format!("tag: {}", display),
+            false => display,
+        };
+        // Annotated tags decorate the commit they point to
+        let hash = match name.starts_with("refs/tags/") {
+            true => tag::peel(&hash)?,
+            false => hash,
+        };
+        let names = decorations.entry(hash).or_default();
+        if name == head_branch {
+            names.insert(0, format!("HEAD -> {}", display));
+        } else {
+            names.push(display);
+        }
+    }
+
+    Ok(decorations)
+}
+
+pub fn format_commit(commit: &Commit, opts: &Options, names: Option<&Vec<String>>) -> String {
+    let hash = match opts.abbrev {
+        true => &commit.hash[..7],
+        false => &commit.hash[..],
+    };
+    let decor = match (names, opts.decorate) {
+        (Some(n), d) if d != Decorate::No && !n.is_empty() => format!(" ({})", n.join(", ")),
+        _ => String::new(),
+    };
+
+    if let Format::Custom(ref fmt, _) = opts.format {
+        return expand_format(fmt, commit, opts, names);
+    }
+    if opts.format == Format::Oneline {
+        return format!("{}{} {}", hash, decor, commit.subject());
+    }
+
+    let mut lines = vec![format!("commit {}{}", hash, decor)];
+    if opts.format == Format::Raw {
+        lines.extend(commit.header.lines().map(|l| l.to_string()));
+    } else if commit.parents.len() > 1 {
+        let parents: Vec<&str> = commit.parents.iter().map(|p| &p[..7]).collect();
+        lines.push(format!("Merge: {}", parents.join(" ")));
+    }
+
+    let author = format!("{} <{}>", commit.author.name, commit.author.email);
+    let committer = format!("{} <{}>", commit.committer.name, commit.committer.email);
+    let author_date = date::format(commit.author.time, &commit.author.tz, opts.date);
+    let commit_date = date::format(commit.committer.time, &commit.committer.tz, opts.date);
+    match opts.format {
+        Format::Short => lines.push(format!("Author: {}", author)),
+        Format::Medium => {
+            lines.push(format!("Author: {}", author));
+            lines.push(format!("Date:   {}", author_date));
+        }
+        Format::Full => {
+            lines.push(format!("Author: {}", author));
+            lines.push(format!("Commit: {}", committer));
+        }
+        Format::Fuller => {
+            lines.push(format!("Author:     {}", author));
+            lines.push(format!("AuthorDate: {}", author_date));
+            lines.push(format!("Commit:     {}", committer));
+            lines.push(format!("CommitDate: {}", commit_date));
+        }
+        _ => (),
+    }
+
+    lines.push(String::new());
+    let message = match opts.format {
+        Format::Short => commit.subject(),
+        _ => commit.message.trim_end().to_string(),
+    };
+    for line in message.lines() {
+        match line.is_empty() {
+            true => lines.push(String::new()),
+            false => lines.push(format!("    {}", line)),
+        }
+    }
+
+    lines.join("\n")
+}
+
+// git's --pretty=format: placeholders
+pub fn expand_format(
+    fmt: &str,
+    commit: &Commit,
+    opts: &Options,
+    names: Option<&Vec<String>>,
+) -> String {
+    let mut res = String::new();
+    let chars: Vec<char> = fmt.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '%' || i + 1 == chars.len() {
+            res.push(chars[i]);
+            i += 1;
+            continue;
+        }
+
+        let next: String = chars[i + 1..chars.len().min(i + 9)].iter().collect();
+        let (value, len) = expand_placeholder(&next, commit, opts, names);
+        match len {
+            0 => {
+                res.push('%');
+                i += 1;
+            }
+            n => {
+                res.push_str(&value);
+                i += 1 + n;
+            }
+        }
+    }
+
+    res
+}
+
+// Returns the expanded value and the number of characters consumed
+fn expand_placeholder(
+    spec: &str,
+    commit: &Commit,
+    opts: &Options,
+    names: Option<&Vec<String>>,
+) -> (String, usize) {
+    let no_names = Vec::new();
+    let names = names.unwrap_or(&no_names);
+    let parents: Vec<&str> = commit.parents.iter().map(|p| p.as_str()).collect();
+    let short_parents: Vec<&str> = parents.iter().map(|p| &p[..7]).collect();
+
+    for &(color, code) in [
+        ("Cred", "\x1b[31m"),
+        ("Cgreen", "\x1b[32m"),
+        ("Cblue", "\x1b[34m"),
+        ("Creset", "\x1b[m"),
+    ]
+    .iter()
+    {
+        if spec.starts_with(color) {
+            return (code.to_string(), color.len());
+        }
+    }
+
+    if spec.starts_with('x') {
+        let digits: String = spec.chars().skip(1).take(2).collect();
+        if digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            let byte = u8::from_str_radix(&digits, 16).unwrap();
+            return ((byte as char).to_string(), 3);
+        }
+    }
+
+    let mut chars = spec.chars();
+    let first = chars.next().unwrap_or(' ');
+    let value = match first {
+        'H' => commit.hash.to_string(),
+        'h' => commit.hash[..7].to_string(),
+        'T' => commit.tree.to_string(),
+        't' => commit.tree[..7].to_string(),
+        'P' => parents.join(" "),
+        'p' => short_parents.join(" "),
+        's' => commit.subject(),
+        'f' => sanitize_subject(&commit.subject()),
+        'b' => commit.body(),
+        'B' => commit.message.to_string(),
+        'd' => match names.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", names.join(", ")),
+        },
+        'D' => names.join(", "),
+        'n' => String::from("\n"),
+        '%' => String::from("%"),
+        'a' | 'c' => {
+            let sign = match first {
+                'a' => &commit.author,
+                _ => &commit.committer,
+            };
+            let value = match chars.next() {
+                Some('n') => sign.name.to_string(),
+                Some('e') => sign.email.to_string(),
+                Some('d') => date::format(sign.time, &sign.tz, opts.date),
+                Some('D') => date::format(sign.time, &sign.tz, date::Mode::Rfc),
+                Some('r') => date::format(sign.time, &sign.tz, date::Mode::Relative),
+                Some('t') => date::format(sign.time, &sign.tz, date::Mode::Unix),
+                Some('i') => date::format(sign.time, &sign.tz, date::Mode::Iso),
+                Some('I') => date::format(sign.time, &sign.tz, date::Mode::IsoStrict),
+                Some('s') => date::format(sign.time, &sign.tz, date::Mode::Short),
+                _ => return (String::new(), 0),
+            };
+            return (value, 2);
+        }
+        _ => return (String::new(), 0),
+    };
+
+    (value, 1)
+}
+
+fn sanitize_subject(subject: &str) -> String {
+    let mut res = String::new();
+    for c in subject.chars() {
+        match c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            true => res.push(c),
+            false => {
+                if !res.ends_with('-') {
+                    res.push('-');
+                }
+            }
+        }
+    }
+    res.trim_matches(|c| c == '-' || c == '.').to_string()
+}
+
+// ASCII history graph, one column per line of development
+pub struct Graph {
+    columns: Vec<String>,
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            columns: Vec::new(),
+        }
+    }
+
+    // Returns the commit row, the rows drawn while the columns are rearranged
+    // and the padding to use until the next commit
+    pub fn update(&mut self, commit: &Commit) -> (String, Vec<String>, String) {
+        let idx = match self.columns.iter().position(|c| c == &commit.hash) {
+            Some(i) => i,
+            None => {
+                self.columns.push(commit.hash.to_string());
+                self.columns.len() - 1
+            }
+        };
+
+        let nb_cols = self.columns.len();
+        let row = draw(
+            &(0..nb_cols)
+                .map(|i| (2 * i, if i == idx { '*' } else { '|' }))
+                .collect::<Vec<_>>(),
+        );
+
+        let mut transitions = Vec::new();
+        let mut marks = Vec::new();
+        if commit.parents.is_empty() {
+            self.columns.remove(idx);
+            if idx < self.columns.len() {
+                for i in 0..nb_cols {
+                    if i < idx {
+                        marks.push((2 * i, '|'));
+                    } else if i > idx {
+                        marks.push((2 * i - 1, '/'));
+                    }
+                }
+                transitions.push(draw(&marks));
+            }
+        } else {
+            self.columns[idx] = commit.parents[0].to_string();
+            let mut inserted = 0;
+            for parent in &commit.parents[1..] {
+                if !self.columns.contains(parent) {
+                    inserted += 1;
+                    self.columns.insert(idx + inserted, parent.to_string());
+                }
+            }
+
+            if inserted > 0 {
+                for i in 0..nb_cols {
+                    if i <= idx {
+                        marks.push((2 * i, '|'));
+                    } else {
+                        marks.push((2 * (i + inserted) - 1, '\\'));
+                    }
+                }
+                for k in 1..=inserted {
+                    marks.push((2 * (idx + k) - 1, '\\'));
+                }
+                transitions.push(draw(&marks));
+            }
+
+            // Two lines of development meeting on the same parent
+            let first = &commit.parents[0];
+            let dup = self
+                .columns
+                .iter()
+                .enumerate()
+                .find(|&(i, c)| i != idx && c == first)
+                .map(|(i, _)| i);
+            if let Some(j) = dup {
+                let removed = idx.max(j);
+                let len = self.columns.len();
+                self.columns.remove(removed);
+                let mut marks = Vec::new();
+                for i in 0..len {
+                    if i < removed {
+                        marks.push((2 * i, '|'));
+                    } else {
+                        marks.push((2 * i - 1, '/'));
+                    }
+                }
+                transitions.push(draw(&marks));
+            }
+        }
+
+        let padding = draw(
+            &(0..self.columns.len())
+                .map(|i| (2 * i, '|'))
+                .collect::<Vec<_>>(),
+        );
+        (row, transitions, padding)
+    }
+}
+
+fn draw(marks: &[(usize, char)]) -> String {
+    let width = marks.iter().map(|&(pos, _)| pos + 1).max().unwrap_or(0);
+    let mut line = vec![' '; width];
+    for &(pos, c) in marks {
+        line[pos] = c;
+    }
+    line.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use builtin::commit::{Commit, Signature};
+    use builtin::log::{expand_format, Format, Graph, Options};
+
+    fn commit(hash: &str, parents: &[&str]) -> Commit {
+        let sign = Signature::parse("John Doe <john.doe@something.com> 1112911993 -0700");
+        Commit {
+            hash: hash.repeat(40),
+            tree: "f".repeat(40),
+            parents: parents.iter().map(|p| p.repeat(40)).collect(),
+            author: sign.unwrap(),
+            committer: Signature::parse("Jane <jane@doe.com> 0 +0000").unwrap(),
+            header: String::new(),
+            message: String::from("subject line\n\nbody\n"),
+        }
+    }
+
+    #[test]
+    fn placeholders() {
+        let mut opts = Options::new();
+        opts.format = Format::Custom(String::new(), true);
+        let c = commit("a", &["b", "c"]);
+        let names = vec![String::from("HEAD -> master")];
+        assert_eq!(
+            "aaaaaaa bbbbbbb ccccccc",
+            expand_format("%h %p", &c, &opts, None)
+        );
+        assert_eq!(
+            "John Doe <john.doe@something.com> Jane",
+            expand_format("%an <%ae> %cn", &c, &opts, None)
+        );
+        assert_eq!(
+            "subject line|body\n| (HEAD -> master)%",
+            expand_format("%s|%b|%d%%", &c, &opts, Some(&names))
+        );
+        assert_eq!(
+            "Thu Apr 7 15:13:13 2005 -0700%z",
+            expand_format("%ad%z", &c, &opts, None)
+        );
+    }
+
+    #[test]
+    fn hex_placeholders() {
+        let opts = Options::new();
+        let c = commit("a", &[]);
+        assert_eq!("A\t", expand_format("%x41%x09", &c, &opts, None));
+        assert_eq!("%x€1 %x4", expand_format("%x€1 %x4", &c, &opts, None));
+        assert_eq!("%x4€", expand_format("%x4€", &c, &opts, None));
+    }
+
+    #[test]
+    fn graph_merge() {
+        let mut graph = Graph::new();
+        let (row, transitions, padding) = graph.update(&commit("m", &["a", "b"]));
+        assert_eq!(
+            ("*", "|\\", "| |"),
+            (&row[..], &transitions[0][..], &padding[..])
+        );
+
+        let (row, transitions, _) = graph.update(&commit("b", &["a"]));
+        assert_eq!(("| *", "|/"), (&row[..], &transitions[0][..]));
+
+        let (row, transitions, padding) = graph.update(&commit("a", &[]));
+        assert_eq!(("*", 0, ""), (&row[..], transitions.len(), &padding[..]));
+    }
+}
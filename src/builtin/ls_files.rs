@@ -1,29 +1,113 @@
+use std::collections::HashSet;
+use std::io;
+
 use cli;
+use ignore::Ignore;
 use index;
+use work_dir;
+
+#[derive(Debug)]
+pub enum Error {
+    IndexError(index::Error),
+    IoError(io::Error),
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
 
 pub fn cmd_ls_files(flags: &[String]) {
-    let accepted_flags = ["--stage", "-s"];
+    let accepted_flags = [
+        "--stage",
+        "-s",
+        "--cached",
+        "-c",
+        "--others",
+        "-o",
+        "--ignored",
+        "-i",
+        "--exclude-standard",
+        "--exclude=",
+    ];
     if cli::has_known_flags(flags, &accepted_flags) {
         let stage = cli::has_flag(&flags, "--stage", "-s");
-        if let Err(why) = ls_files(stage) {
+        let others = cli::has_flag(&flags, "--others", "-o");
+        let ignored = cli::has_flag(&flags, "--ignored", "-i");
+        let cached = cli::has_flag(&flags, "--cached", "-c") || !others;
+
+        let mut ignore = Ignore::new();
+        if cli::has_flag(&flags, "--exclude-standard", "") {
+            ignore = match Ignore::standard() {
+                Ok(ignore) => ignore,
+                Err(why) => {
+                    println!("Could not read ignore files: {:?}", why);
+                    return;
+                }
+            };
+        }
+        for flag in flags {
+            if let Some(pattern) = flag.strip_prefix("--exclude=") {
+                ignore.add_pattern(pattern);
+            }
+        }
+
+        if let Err(why) = ls_files(stage, cached, others, ignored, &mut ignore) {
             println!("Could not print index files: {:?}", why);
         }
     }
 }
 
-fn ls_files(stage: bool) -> Result<(), index::Error> {
+// Files of the index (--cached) and untracked files (--others), only the
+// ignored ones with --ignored
+fn ls_files(
+    stage: bool,
+    cached: bool,
+    others: bool,
+    ignored: bool,
+    ignore: &mut Ignore,
+) -> Result<(), Error> {
     let entries = index::read_entries()?;
-    for entry in entries {
-        if stage {
-            let stage_nb = (entry.flags >> 12) & 3;
-            println!(
-                "{:6o} {} {}\t{}",
-                entry.mode, entry.hash, stage_nb, entry.path
-            );
-        } else {
-            println!("{}", entry.path);
+    if cached {
+        for entry in &entries {
+            if ignored && !is_ignored(ignore, &entry.path)? {
+                continue;
+            }
+            if stage {
+                let stage_nb = (entry.flags >> 12) & 3;
+                println!(
+                    "{:6o} {} {}\t{}",
+                    entry.mode, entry.hash, stage_nb, entry.path
+                );
+            } else {
+                println!("{}", entry.path);
+            }
+        }
+    }
+
+    if others {
+        let tracked: HashSet<String> = entries.into_iter().map(|e| e.path).collect();
+        let (untracked, ignored_files) = work_dir::get_untracked_files(&tracked, ignore)?;
+        let files = match ignored {
+            true => ignored_files,
+            false => untracked,
+        };
+        for file in files {
+            println!("{}", file);
         }
     }
 
     Ok(())
 }
+
+fn is_ignored(ignore: &mut Ignore, path: &str) -> io::Result<bool> {
+    ignore.read_parents(path)?;
+    Ok(ignore.is_ignored(path, false))
+}
@@ -1,8 +1,6 @@
-use std::fs;
 use std::io;
 
 use builtin::commit;
-use builtin::status;
 use object;
 use object::Object;
 use refs;
@@ -16,7 +14,6 @@ pub enum Error {
     ObjectError(object::Error),
     ReferenceNotACommit,
     WorkDirError(work_dir::Error),
-    WorkDirNotClean,
 }
 
 impl From<io::Error> for Error {
@@ -36,17 +33,20 @@ pub fn cmd_merge(args: &[String]) {
         println!("merge: command takes a 'ref' argument.");
     } else {
         let ref_name = &args[0];
-        if let Err(why) = merge(ref_name) {
-            println!("Could not merge: {:?}", why);
+        match merge(ref_name) {
+            Err(Error::WorkDirError(work_dir::Error::LocalChanges(changed, untracked))) => {
+                print!(
+                    "{}",
+                    work_dir::format_local_changes(&changed, &untracked, "merge")
+                );
+            }
+            Err(why) => println!("Could not merge: {:?}", why),
+            Ok(()) => (),
         }
     }
 }
 
 pub fn merge(ref_name: &str) -> Result<(), Error> {
-    if !status::is_clean_work_dir() {
-        return Err(Error::WorkDirNotClean);
-    }
-
     let cur_commit = refs::get_ref_hash("HEAD")?;
     let dst_commit = refs::get_ref_hash(&ref_name)?;
     if cur_commit == dst_commit {
@@ -61,25 +61,21 @@ pub fn merge(ref_name: &str) -> Result<(), Error> {
     let cur_branch = refs::read_ref("HEAD")?;
     let can_fast_forward = cur_commit.is_empty() || commit::is_ancestor(&dst_commit, &cur_commit);
     if can_fast_forward {
-        work_dir::update_from_commit(&dst_commit)?;
+        work_dir::update_from_commit(&dst_commit, work_dir::Checkout::Safe)?;
 
         refs::write_to_ref(&cur_branch, &dst_commit)?;
         println!("Fast-forward");
     } else {
-        work_dir::update_from_merge(&cur_commit, &dst_commit)?;
+        let conflicts = work_dir::update_from_merge(&cur_commit, &dst_commit)?;
 
         refs::write_to_ref("MERGE_HEAD", &dst_commit)?;
         let merge_msg = format!("Merge {} into {}", ref_name, cur_branch);
         println!("{}", merge_msg);
 
-        let mut has_conflicts = false;
-        for file in work_dir::get_all_files_path()? {
-            let data = fs::read_to_string(&file)?;
-            if data.contains("<<<<<<") {
-                println!("CONFLICT {}", file);
-                has_conflicts = true;
-            }
+        for file in &conflicts {
+            println!("CONFLICT {}", file);
         }
+        let has_conflicts = !conflicts.is_empty();
 
         if !has_conflicts {
             commit::commit(&merge_msg).map_err(Error::CommitError)?;
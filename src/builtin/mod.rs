@@ -1,7 +1,10 @@
 pub mod add;
 pub mod branch;
 pub mod cat_file;
+pub mod check_attr;
+pub mod check_ignore;
 pub mod checkout;
+pub mod clean;
 pub mod clone;
 pub mod commit;
 pub mod config;
@@ -12,9 +15,17 @@ pub mod init;
 pub mod log;
 pub mod ls_files;
 pub mod merge;
+pub mod mv;
 pub mod pull;
 pub mod push;
 pub mod read_tree;
 pub mod remote;
+pub mod reset;
+pub mod restore;
+pub mod rm;
+pub mod show;
+pub mod stash;
 pub mod status;
+pub mod switch;
+pub mod tag;
 pub mod write_tree;
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use cli;
+use index;
+
+#[derive(Debug)]
+pub enum Error {
+    // (reason, source, destination)
+    CannotMove(String, String, String),
+    IndexError(index::Error),
+    IoError(io::Error),
+    NotADirectory(String),
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+pub struct Options {
+    // Existing destinations are overwritten
+    pub force: bool,
+    // Sources which cannot be moved are skipped instead of failing
+    pub skip_errors: bool,
+    pub dry_run: bool,
+    pub verbose: bool,
+}
+
+pub fn cmd_mv(args: &[String], flags: &[String]) {
+    let accepted_flags = ["--force", "-f", "-k", "--dry-run", "-n", "--verbose", "-v"];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let opts = Options {
+        force: cli::has_flag(flags, "--force", "-f"),
+        skip_errors: flags.iter().any(|f| f == "-k"),
+        dry_run: cli::has_flag(flags, "--dry-run", "-n"),
+        verbose: cli::has_flag(flags, "--verbose", "-v"),
+    };
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--").cloned().collect();
+    if paths.len() < 2 {
+        println!("usage: gitrs mv [<options>] <source>... <destination>");
+        return;
+    }
+
+    let (sources, dest) = paths.split_at(paths.len() - 1);
+    match mv(sources, &dest[0], &opts) {
+        Err(Error::CannotMove(reason, src, dst)) => {
+            println!("fatal: {}, source={}, destination={}", reason, src, dst);
+        }
+        Err(Error::NotADirectory(dest)) => {
+            println!("fatal: destination '{}' is not a directory", dest);
+        }
+        Err(why) => println!("Could not move paths: {:?}", why),
+        Ok(()) => (),
+    }
+}
+
+// Moves files or directories in the working dir and in the index, where the
+// entries keep their stat. Several sources are moved into a directory.
+pub fn mv(sources: &[String], dest: &str, opts: &Options) -> Result<(), Error> {
+    let dest = dest.trim_end_matches('/');
+    let into_dir = Path::new(dest).is_dir();
+    if sources.len() > 1 && !into_dir {
+        return Err(Error::NotADirectory(dest.to_string()));
+    }
+    let mut entries: BTreeMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
+
+    let mut moves: Vec<(String, String)> = Vec::new();
+    for src in sources {
+        let src = src.trim_start_matches("./").trim_end_matches('/');
+        let dst = match into_dir {
+            true => {
+                let name = Path::new(src)
+                    .file_name()
+                    .map_or(src.into(), |n| n.to_string_lossy());
+                format!("{}/{}", dest, name)
+            }
+            false => dest.to_string(),
+        };
+        let reason = match moves.iter().any(|(_, d)| *d == dst) {
+            true => Some("multiple sources for the same target"),
+            false => check_move(src, &dst, &entries, opts.force),
+        };
+        match reason {
+            Some(_) if opts.skip_errors => (),
+            Some(reason) => {
+                return Err(Error::CannotMove(reason.to_string(), src.to_string(), dst))
+            }
+            None => moves.push((src.to_string(), dst)),
+        }
+    }
+
+    for (src, dst) in &moves {
+        if opts.dry_run || opts.verbose {
+            println!("Renaming {} to {}", src, dst);
+        }
+        if opts.dry_run {
+            continue;
+        }
+        fs::rename(src, dst)?;
+
+        // An overwritten destination is replaced, the entries of the source
+        // (or of the files under it) are moved
+        entries.remove(dst);
+        let prefix = format!("{}/", src);
+        let moved: Vec<String> = entries
+            .keys()
+            .filter(|p| *p == src || p.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for path in moved {
+            let entry = entries.remove(&path).unwrap();
+            let new_path = format!("{}{}", dst, &path[src.len()..]);
+            entries.insert(new_path.to_string(), entry.with_path(&new_path));
+        }
+    }
+    if !opts.dry_run {
+        index::write_entries(entries.into_iter().map(|(_, e)| e).collect())?;
+    }
+    Ok(())
+}
+
+// Why a file or directory cannot be moved, if it cannot
+fn check_move(
+    src: &str,
+    dst: &str,
+    entries: &BTreeMap<String, index::Entry>,
+    force: bool,
+) -> Option<&'static str> {
+    let src_meta = match fs::symlink_metadata(src) {
+        Ok(meta) => meta,
+        Err(_) => return Some("bad source"),
+    };
+    if src_meta.is_dir() && entries.get(src).map(|e| e.mode) != Some(index::MODE_GITLINK) {
+        if dst == src || dst.starts_with(&format!("{}/", src)) {
+            return Some("can not move directory into itself");
+        }
+        let prefix = format!("{}/", src);
+        if !entries.keys().any(|p| p.starts_with(&prefix)) {
+            return Some("source directory is empty");
+        }
+    } else if !entries.contains_key(src) {
+        return Some("not under version control");
+    }
+
+    if let Ok(dst_meta) = fs::symlink_metadata(dst) {
+        // Only files can be overwritten
+        if !force || src_meta.is_dir() || dst_meta.is_dir() {
+            return Some("destination exists");
+        }
+    }
+    match Path::new(dst).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+            Some("destination directory does not exist")
+        }
+        _ => None,
+    }
+}
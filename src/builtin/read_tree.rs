@@ -84,3 +84,22 @@ pub fn read_tree(hash_prefix: &str) -> Result<Vec<Entry>, Error> {
 
     Ok(tree)
 }
+
+// Every blob of the tree and its subtrees, with paths relative to the root
+pub fn flatten_tree(hash: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries = Vec::new();
+    for entry in read_tree(hash)? {
+        if entry.mode == 0o040000 {
+            for sub in flatten_tree(&entry.hash)? {
+                entries.push(Entry {
+                    mode: sub.mode,
+                    path: format!("{}/{}", entry.path, sub.path),
+                    hash: sub.hash,
+                });
+            }
+        } else {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
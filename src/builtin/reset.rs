@@ -0,0 +1,238 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use builtin::commit;
+use builtin::commit::Commit;
+use builtin::diff;
+use builtin::diff::Source;
+use cli;
+use index;
+use patch;
+use pathspec::Pathspec;
+use refs;
+use rename;
+use revision;
+use work_dir;
+use work_dir::Reset;
+
+#[derive(Debug)]
+pub enum Error {
+    CommitError(commit::Error),
+    DiffError(diff::Error),
+    IndexError(index::Error),
+    IoError(io::Error),
+    RevisionError(revision::Error),
+    WorkDirError(work_dir::Error),
+}
+
+impl From<commit::Error> for Error {
+    fn from(e: commit::Error) -> Error {
+        Error::CommitError(e)
+    }
+}
+
+impl From<diff::Error> for Error {
+    fn from(e: diff::Error) -> Error {
+        Error::DiffError(e)
+    }
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+impl From<revision::Error> for Error {
+    fn from(e: revision::Error) -> Error {
+        Error::RevisionError(e)
+    }
+}
+
+impl From<work_dir::Error> for Error {
+    fn from(e: work_dir::Error) -> Error {
+        Error::WorkDirError(e)
+    }
+}
+
+pub fn cmd_reset(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--soft", "--mixed", "--hard", "--merge", "--keep", "--patch", "-p", "--quiet", "-q",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let modes = [
+        ("--soft", Reset::Soft),
+        ("--mixed", Reset::Mixed),
+        ("--hard", Reset::Hard),
+        ("--merge", Reset::Merge),
+        ("--keep", Reset::Keep),
+    ];
+    let given: Vec<&(&str, Reset)> = modes
+        .iter()
+        .filter(|(flag, _)| flags.iter().any(|f| f == flag))
+        .collect();
+    if given.len() > 1 {
+        println!("reset: only one of --soft, --mixed, --hard, --merge and --keep can be used.");
+        return;
+    }
+    let patch = cli::has_flag(flags, "--patch", "-p");
+    let quiet = cli::has_flag(flags, "--quiet", "-q");
+
+    // [<commit>] [--] [<paths>], the commit being optional without "--"
+    let (mut revs, mut paths) = cli::split_paths(args);
+    let has_separator = args.iter().any(|a| a == "--");
+    if !has_separator && !revs.is_empty() && !revision::is_revision(&revs[0]) {
+        paths = revs.split_off(0);
+    } else if !has_separator && revs.len() > 1 {
+        paths = revs.split_off(1);
+    }
+    if revs.len() > 1 {
+        println!("reset: only one commit can be given.");
+        return;
+    }
+    let rev = revs.first().map(|r| r.as_str()).unwrap_or("HEAD");
+
+    let res = if patch || !paths.is_empty() {
+        match given.first() {
+            Some((flag, mode)) if *mode != Reset::Mixed => {
+                println!("fatal: Cannot do {} reset with paths.", &flag[2..]);
+                return;
+            }
+            _ => (),
+        }
+        let pathspec = Pathspec::new(&paths);
+        match patch {
+            true => reset_patch(rev, &pathspec),
+            false => reset_paths(rev, &pathspec),
+        }
+    } else {
+        let mode = given.first().map_or(Reset::Mixed, |(_, mode)| *mode);
+        reset(rev, mode)
+    };
+
+    match res {
+        Err(Error::WorkDirError(work_dir::Error::LocalChanges(changed, untracked))) => {
+            print!(
+                "{}",
+                work_dir::format_local_changes(&changed, &untracked, "reset")
+            );
+        }
+        Err(why) => println!("Could not reset: {:?}", why),
+        Ok(mode) => {
+            if !quiet {
+                if let Err(why) = print_summary(mode) {
+                    println!("Could not reset: {:?}", why);
+                }
+            }
+        }
+    }
+}
+
+// Commit of a revision, empty for HEAD before the first commit
+fn resolve_commit(rev: &str) -> Result<String, Error> {
+    if rev == "HEAD" {
+        return Ok(refs::get_ref_hash("HEAD")?);
+    }
+    Ok(revision::resolve(rev)?)
+}
+
+// Moves the current branch (or a detached HEAD) to a commit, ORIG_HEAD
+// keeping the previous one
+pub fn reset(rev: &str, mode: Reset) -> Result<Option<Reset>, Error> {
+    let commit = resolve_commit(rev)?;
+    if !commit.is_empty() {
+        // Fails when it is not a commit
+        Commit::new(&commit)?;
+    }
+    work_dir::reset_to_commit(&commit, mode)?;
+
+    let cur_commit = refs::get_ref_hash("HEAD")?;
+    let message = format!("reset: moving to {}", rev);
+    if !cur_commit.is_empty() {
+        refs::write_to_ref("ORIG_HEAD", &cur_commit)?;
+    }
+    if !commit.is_empty() {
+        match refs::is_detached_head() {
+            true => refs::write_to_ref("HEAD", &commit)?,
+            false => {
+                let branch = refs::read_ref("HEAD")?;
+                refs::write_to_ref(&branch, &commit)?;
+                refs::append_log(&branch, &cur_commit, &commit, &message)?;
+            }
+        }
+        refs::append_log("HEAD", &cur_commit, &commit, &message)?;
+    }
+
+    // A merge in progress is given up
+    let merge_head = Path::new(".git").join("MERGE_HEAD");
+    if mode != Reset::Soft && merge_head.exists() {
+        fs::remove_file(merge_head)?;
+    }
+    Ok(Some(mode))
+}
+
+// Sets the index entries matching the pathspec to their version in a commit
+fn reset_paths(rev: &str, pathspec: &Pathspec) -> Result<Option<Reset>, Error> {
+    let commit = resolve_commit(rev)?;
+    let overlay = false;
+    match work_dir::restore_files(Some(&commit), pathspec, (true, false), overlay) {
+        // Nothing to reset
+        Err(work_dir::Error::PathspecNotMatched(_)) => (),
+        res => {
+            res?;
+        }
+    }
+    Ok(Some(Reset::Mixed))
+}
+
+// Picks the hunks of the staged changes to take out of the index
+fn reset_patch(rev: &str, pathspec: &Pathspec) -> Result<Option<Reset>, Error> {
+    let commit = resolve_commit(rev)?;
+    let pairs = diff::diff_sources(
+        &Source::Tree(commit),
+        &Source::Index,
+        pathspec,
+        &rename::Options::new(),
+    )?;
+    let changes = patch::select_changes(&pairs, "Unstage", true)?;
+    index::write_contents(changes)?;
+    Ok(None)
+}
+
+// HEAD after a hard reset, the changes left unstaged otherwise
+fn print_summary(mode: Option<Reset>) -> Result<(), Error> {
+    match mode {
+        Some(Reset::Hard) => {
+            let head = refs::get_ref_hash("HEAD")?;
+            if !head.is_empty() {
+                let commit = Commit::new(&head)?;
+                println!("HEAD is now at {} {}", &head[..7], commit.subject());
+            }
+        }
+        Some(Reset::Mixed) => {
+            let pairs = diff::diff_sources(
+                &Source::Index,
+                &Source::WorkDir,
+                &Pathspec::new(&[]),
+                &rename::Options::new(),
+            )?;
+            if !pairs.is_empty() {
+                println!("Unstaged changes after reset:");
+            }
+            for pair in &pairs {
+                println!("{}\t{}", pair.status(), pair.path());
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use builtin::checkout;
+use cli;
+use pathspec::Pathspec;
+use revision;
+use work_dir;
+
+pub fn cmd_restore(args: &[String], flags: &[String]) {
+    let accepted_flags = ["--staged", "-S", "--worktree", "-W", "--source="];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let (_, paths) = cli::split_paths(args);
+    let paths = match args.iter().any(|a| a == "--") {
+        true => paths,
+        false => args.to_vec(),
+    };
+    if paths.is_empty() {
+        println!("restore: you must specify path(s) to restore.");
+        return;
+    }
+
+    let staged = cli::has_flag(flags, "--staged", "-S");
+    // The working dir is restored by default
+    let worktree = cli::has_flag(flags, "--worktree", "-W") || !staged;
+    let source = flags
+        .iter()
+        .filter_map(|f| f.strip_prefix("--source="))
+        .next_back();
+    // The index is restored from HEAD by default, the working dir from the index
+    let source = match (source, staged) {
+        (Some(rev), _) => Some(rev),
+        (None, true) => Some("HEAD"),
+        (None, false) => None,
+    };
+
+    let commit = match source.map(revision::resolve) {
+        Some(Ok(commit)) => Some(commit),
+        Some(Err(why)) => {
+            println!("Could not resolve source: {:?}", why);
+            return;
+        }
+        None => None,
+    };
+    let overlay = false;
+    if let Err(why) = work_dir::restore_files(
+        commit.as_ref().map(|c| c.as_str()),
+        &Pathspec::new(&paths),
+        (staged, worktree),
+        overlay,
+    ) {
+        checkout::print_error(checkout::Error::WorkDirError(why), "restore");
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+use std::io;
+
+use builtin::diff;
+use builtin::diff::Source;
+use cli;
+use index;
+use pathspec::Pathspec;
+use refs;
+use rename;
+use wildmatch;
+use work_dir;
+
+#[derive(Debug)]
+pub enum Error {
+    DiffError(diff::Error),
+    IndexError(index::Error),
+    IoError(io::Error),
+    // Files with changes both staged and in the working dir, with changes
+    // staged, and with changes in the working dir
+    LocalChanges(Vec<String>, Vec<String>, Vec<String>),
+    NotRecursive(String),
+    PathspecNotMatched(String),
+}
+
+impl From<diff::Error> for Error {
+    fn from(e: diff::Error) -> Error {
+        Error::DiffError(e)
+    }
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+pub struct Options {
+    // Files are only removed from the index
+    pub cached: bool,
+    pub recursive: bool,
+    // Files with changes are removed too
+    pub force: bool,
+    pub dry_run: bool,
+}
+
+pub fn cmd_rm(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--cached",
+        "-r",
+        "--force",
+        "-f",
+        "--dry-run",
+        "-n",
+        "--quiet",
+        "-q",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let opts = Options {
+        cached: flags.iter().any(|f| f == "--cached"),
+        recursive: flags.iter().any(|f| f == "-r"),
+        force: cli::has_flag(flags, "--force", "-f"),
+        dry_run: cli::has_flag(flags, "--dry-run", "-n"),
+    };
+    let quiet = cli::has_flag(flags, "--quiet", "-q");
+    let paths: Vec<String> = args.iter().filter(|a| *a != "--").cloned().collect();
+    if paths.is_empty() {
+        println!("No pathspec was given. Which files should I remove?");
+        return;
+    }
+
+    match rm(&paths, &opts) {
+        Err(Error::PathspecNotMatched(item)) => {
+            println!("fatal: pathspec '{}' did not match any files", item);
+        }
+        Err(Error::NotRecursive(item)) => {
+            println!("fatal: not removing '{}' recursively without -r", item);
+        }
+        Err(Error::LocalChanges(both, staged, local)) => {
+            print!("{}", format_local_changes(&both, &staged, &local));
+        }
+        Err(why) => println!("Could not remove paths: {:?}", why),
+        Ok(removed) => {
+            if !quiet {
+                for path in removed {
+                    println!("rm '{}'", path);
+                }
+            }
+        }
+    }
+}
+
+// Removes the tracked files matching the paths from the index and from the
+// working dir, unless they have changes which would be lost. Returns the
+// paths removed.
+pub fn rm(paths: &[String], opts: &Options) -> Result<Vec<String>, Error> {
+    let mut entries = index::read_entries()?;
+    let mut removed = Vec::new();
+    for arg in paths {
+        let matched: Vec<String> = entries
+            .iter()
+            .map(|e| e.path.to_string())
+            .filter(|p| Pathspec::new(&[arg.to_string()]).matches(p))
+            .collect();
+        if matched.is_empty() {
+            return Err(Error::PathspecNotMatched(arg.to_string()));
+        }
+        // Directories need -r
+        let item = arg.trim_start_matches("./").trim_end_matches('/');
+        if !opts.recursive
+            && !wildmatch::has_glob_chars(item)
+            && !matched.contains(&item.to_string())
+        {
+            return Err(Error::NotRecursive(item.to_string()));
+        }
+        removed.extend(matched);
+    }
+    removed.sort();
+    removed.dedup();
+
+    if !opts.force {
+        check_local_changes(&removed, opts.cached)?;
+    }
+    if opts.dry_run {
+        return Ok(removed);
+    }
+
+    let to_remove: HashSet<&String> = removed.iter().collect();
+    entries.retain(|e| !to_remove.contains(&e.path));
+    index::write_entries(entries)?;
+    if !opts.cached {
+        for path in &removed {
+            work_dir::remove_path(path)?;
+        }
+    }
+    Ok(removed)
+}
+
+// Fails when removing the files would lose changes: the ones staged, or in
+// the working dir, are only kept by the index with --cached
+fn check_local_changes(paths: &[String], cached: bool) -> Result<(), Error> {
+    let pathspec = Pathspec::new(paths);
+    let no_renames = rename::Options::new();
+    let head = Source::Tree(refs::get_ref_hash("HEAD")?);
+    let staged: HashSet<String> =
+        diff::diff_sources(&head, &Source::Index, &pathspec, &no_renames)?
+            .iter()
+            .map(|pair| pair.path().to_string())
+            .collect();
+    // A file already deleted has nothing to lose
+    let local: HashSet<String> =
+        diff::diff_sources(&Source::Index, &Source::WorkDir, &pathspec, &no_renames)?
+            .iter()
+            .filter(|pair| pair.new.is_some())
+            .map(|pair| pair.path().to_string())
+            .collect();
+
+    let mut both_changes = Vec::new();
+    let mut staged_changes = Vec::new();
+    let mut local_changes = Vec::new();
+    for path in paths {
+        match (staged.contains(path), local.contains(path)) {
+            (true, true) => both_changes.push(path.to_string()),
+            (true, false) if !cached => staged_changes.push(path.to_string()),
+            (false, true) if !cached => local_changes.push(path.to_string()),
+            _ => (),
+        }
+    }
+    if both_changes.is_empty() && staged_changes.is_empty() && local_changes.is_empty() {
+        return Ok(());
+    }
+    Err(Error::LocalChanges(
+        both_changes,
+        staged_changes,
+        local_changes,
+    ))
+}
+
+fn format_local_changes(both: &[String], staged: &[String], local: &[String]) -> String {
+    let mut res = String::new();
+    let lists = [
+        (
+            both,
+            "staged content different from both the\nfile and the HEAD",
+            "(use -f to force removal)",
+        ),
+        (
+            staged,
+            "changes staged in the index",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+        (
+            local,
+            "local modifications",
+            "(use --cached to keep the file, or -f to force removal)",
+        ),
+    ];
+    for (paths, what, hint) in lists.iter() {
+        if paths.is_empty() {
+            continue;
+        }
+        let subject = match paths.len() {
+            1 => "file has",
+            _ => "files have",
+        };
+        res.push_str(&format!("error: the following {} {}:\n", subject, what));
+        for path in paths.iter() {
+            res.push_str(&format!("    {}\n", path));
+        }
+        res.push_str(&format!("{}\n", hint));
+    }
+    res
+}
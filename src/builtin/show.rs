@@ -0,0 +1,127 @@
+use std::io;
+use std::io::Write;
+
+use builtin::commit;
+use builtin::commit::Commit;
+use builtin::diff;
+use builtin::log;
+use builtin::read_tree;
+use builtin::tag;
+use builtin::tag::Tag;
+use cli;
+use date;
+use object;
+use object::Object;
+use pathspec::Pathspec;
+use revision;
+
+#[derive(Debug)]
+pub enum Error {
+    CommitError(commit::Error),
+    IoError(io::Error),
+    LogError(log::Error),
+    ObjectError(object::Error),
+    RevisionError(revision::Error),
+    TagError(tag::Error),
+    TreeError(read_tree::Error),
+    UnknownObjectType(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+impl From<log::Error> for Error {
+    fn from(e: log::Error) -> Error {
+        Error::LogError(e)
+    }
+}
+
+pub fn cmd_show(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--pretty",
+        "--pretty=",
+        "--format=",
+        "--oneline",
+        "--abbrev-commit",
+        "--no-abbrev-commit",
+        "--date=",
+        "--decorate",
+        "--decorate=",
+        "--no-decorate",
+    ];
+    let accepted_flags = [&accepted_flags[..], &diff::FLAGS[..]].concat();
+    if cli::has_known_flags(flags, &accepted_flags) {
+        let res = log::Options::parse(flags)
+            .map_err(Error::LogError)
+            .and_then(|opts| show(args, opts));
+        if let Err(why) = res {
+            println!("Could not show object: {:?}", why);
+        }
+    }
+}
+
+fn show(args: &[String], mut opts: log::Options) -> Result<(), Error> {
+    if opts.diff.is_empty() && !opts.diff.no_patch {
+        opts.diff.patch = true;
+    }
+
+    let default_rev = [String::from("HEAD")];
+    let revs = match args.is_empty() {
+        true => &default_rev[..],
+        false => args,
+    };
+
+    let decorations = log::decorations(log::Decorate::Short)?;
+    for (i, rev) in revs.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+
+        let mut hash = revision::resolve_object(rev).map_err(Error::RevisionError)?;
+        let mut object = Object::new(&hash).map_err(Error::ObjectError)?;
+        // Tags are shown before the object they point to
+        while object.obj_type == "tag" {
+            let tag = Tag::new(&hash).map_err(Error::TagError)?;
+            println!("tag {}", tag.name);
+            if let Some(tagger) = tag.tagger {
+                let time = date::format(tagger.time, &tagger.tz, opts.date);
+                println!("Tagger: {} <{}>", tagger.name, tagger.email);
+                println!("Date:   {}", time);
+            }
+            println!("\n{}", tag.message);
+
+            hash = tag.object;
+            object = Object::new(&hash).map_err(Error::ObjectError)?;
+        }
+        match object.obj_type.as_str() {
+            "commit" => {
+                let commit = Commit::new(&hash).map_err(Error::CommitError)?;
+                let text = log::format_commit(&commit, &opts, decorations.get(&hash));
+                println!("{}", text);
+
+                let changes = log::format_changes(&commit, &opts.diff, &Pathspec::new(&[]))?;
+                if !changes.is_empty() {
+                    if opts.format != log::Format::Oneline {
+                        println!();
+                    }
+                    print!("{}", changes);
+                }
+            }
+            "tree" => {
+                println!("tree {}\n", rev);
+                for entry in read_tree::read_tree(&hash).map_err(Error::TreeError)? {
+                    match entry.mode == 0o040000 {
+                        true => println!("{}/", entry.path),
+                        false => println!("{}", entry.path),
+                    }
+                }
+            }
+            "blob" => io::stdout().write_all(&object.data)?,
+            tp => return Err(Error::UnknownObjectType(tp.to_string())),
+        }
+    }
+
+    Ok(())
+}
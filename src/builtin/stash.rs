@@ -0,0 +1,548 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+
+use builtin::checkout;
+use builtin::checkout::Head;
+use builtin::commit;
+use builtin::commit::Commit;
+use builtin::diff;
+use builtin::diff::Source;
+use builtin::hash_object;
+use builtin::write_tree;
+use cli;
+use convert::Converter;
+use ignore::Ignore;
+use index;
+use patch;
+use pathspec::Pathspec;
+use refs;
+use rename;
+use work_dir;
+
+#[derive(Debug)]
+pub enum Error {
+    CheckoutError(checkout::Error),
+    CommitError(commit::Error),
+    DiffError(diff::Error),
+    IndexError(index::Error),
+    IoError(io::Error),
+    // The staged changes of the stash do not apply to the index
+    IndexConflicts,
+    InvalidReference(String),
+    NoChangesSelected,
+    NoInitialCommit,
+    NoLocalChanges,
+    NoStashEntries,
+    TreeError(write_tree::Error),
+    UntrackedFilesExist(Vec<String>),
+    WorkDirError(work_dir::Error),
+}
+
+impl From<checkout::Error> for Error {
+    fn from(e: checkout::Error) -> Error {
+        Error::CheckoutError(e)
+    }
+}
+
+impl From<commit::Error> for Error {
+    fn from(e: commit::Error) -> Error {
+        Error::CommitError(e)
+    }
+}
+
+impl From<diff::Error> for Error {
+    fn from(e: diff::Error) -> Error {
+        Error::DiffError(e)
+    }
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+
+impl From<write_tree::Error> for Error {
+    fn from(e: write_tree::Error) -> Error {
+        Error::TreeError(e)
+    }
+}
+
+impl From<work_dir::Error> for Error {
+    fn from(e: work_dir::Error) -> Error {
+        Error::WorkDirError(e)
+    }
+}
+
+pub struct Options {
+    pub message: Option<String>,
+    pub include_untracked: bool,
+    // The staged changes are left in the index and the working dir
+    pub keep_index: bool,
+    pub patch: bool,
+}
+
+const STASH: &str = "refs/stash";
+
+pub fn cmd_stash(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--message",
+        "-m",
+        "--include-untracked",
+        "-u",
+        "--keep-index",
+        "-k",
+        "--index",
+        "--quiet",
+        "-q",
+    ];
+    let accepted_flags = [&accepted_flags[..], &diff::FLAGS[..]].concat();
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let commands = [
+        "push", "pop", "apply", "list", "show", "drop", "clear", "branch",
+    ];
+    let (command, args) = match args.first() {
+        Some(c) if commands.contains(&c.as_str()) => (c.as_str(), &args[1..]),
+        _ => ("push", args),
+    };
+    let quiet = cli::has_flag(flags, "--quiet", "-q");
+    let restore_index = flags.iter().any(|f| f == "--index");
+
+    let res = match command {
+        "push" => {
+            let mut args = args.to_vec();
+            let opts = Options {
+                message: match cli::has_flag(flags, "--message", "-m") && !args.is_empty() {
+                    true => Some(args.remove(0)),
+                    false => None,
+                },
+                include_untracked: cli::has_flag(flags, "--include-untracked", "-u"),
+                keep_index: cli::has_flag(flags, "--keep-index", "-k"),
+                patch: cli::has_flag(flags, "--patch", "-p"),
+            };
+            let paths: Vec<String> = args.into_iter().filter(|a| a != "--").collect();
+            push(&Pathspec::new(&paths), &opts).map(|message| {
+                if !quiet {
+                    println!("Saved working directory and index state {}", message);
+                }
+            })
+        }
+        "pop" | "apply" => stash_index(args.first()).and_then(|n| {
+            let conflicts = apply(n, restore_index)?;
+            for path in &conflicts {
+                println!("CONFLICT (content): Merge conflict in {}", path);
+            }
+            match (command, conflicts.is_empty()) {
+                ("pop", true) => drop_and_print(n),
+                ("pop", false) => {
+                    println!("The stash entry is kept in case you need it again.");
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }),
+        "list" => read_stashes().map(|stashes| {
+            for (i, entry) in stashes.iter().enumerate() {
+                println!("stash@{{{}}}: {}", i, entry.message);
+            }
+        }),
+        "show" => stash_index(args.first()).and_then(|n| {
+            let mut opts = diff::Options::new();
+            // Flags of the diff, the other ones are left out
+            for flag in flags {
+                opts.parse_flag(flag);
+            }
+            if opts.is_empty() {
+                opts.stat = true;
+            }
+            print!("{}", show(n, &opts)?);
+            Ok(())
+        }),
+        "drop" => stash_index(args.first()).and_then(drop_and_print),
+        "clear" => clear(),
+        _ => match args.first() {
+            Some(name) => stash_index(args.get(1)).and_then(|n| branch(name, n)),
+            None => {
+                println!("No branch name specified");
+                return;
+            }
+        },
+    };
+
+    match res {
+        Err(Error::NoLocalChanges) => println!("No local changes to save"),
+        Err(Error::NoChangesSelected) => println!("No changes selected"),
+        Err(Error::NoInitialCommit) => println!("You do not have the initial commit yet"),
+        Err(Error::NoStashEntries) => println!("No stash entries found."),
+        Err(Error::InvalidReference(name)) => println!("error: {} is not a valid reference", name),
+        Err(Error::IndexConflicts) => println!("Conflicts in index. Try without --index."),
+        Err(Error::UntrackedFilesExist(paths)) => {
+            for path in paths {
+                println!("{} already exists, no checkout", path);
+            }
+            println!("error: could not restore untracked files from stash");
+        }
+        Err(Error::WorkDirError(work_dir::Error::LocalChanges(changed, untracked))) => {
+            print!(
+                "{}",
+                work_dir::format_local_changes(&changed, &untracked, "merge")
+            );
+        }
+        Err(Error::CheckoutError(why)) => checkout::print_error(why, "checkout"),
+        Err(why) => println!("Could not stash: {:?}", why),
+        Ok(()) => (),
+    }
+}
+
+// Position of a stash in the stack given as "stash@{<n>}" or "<n>", the
+// latest one by default
+fn stash_index(arg: Option<&String>) -> Result<usize, Error> {
+    let arg = match arg {
+        Some(arg) => arg,
+        None => return Ok(0),
+    };
+    let n = arg
+        .strip_prefix("stash@{")
+        .and_then(|n| n.strip_suffix('}'))
+        .unwrap_or(arg);
+    n.parse::<usize>()
+        .map_err(|_| Error::InvalidReference(arg.to_string()))
+}
+
+// Entries of the stash log, latest first
+fn read_stashes() -> Result<Vec<refs::LogEntry>, Error> {
+    let mut stashes = refs::read_log(STASH)?;
+    stashes.reverse();
+    Ok(stashes)
+}
+
+// Commit of the nth stash
+fn read_stash(n: usize) -> Result<Commit, Error> {
+    let stashes = read_stashes()?;
+    if stashes.is_empty() {
+        return Err(Error::NoStashEntries);
+    }
+    match stashes.get(n) {
+        Some(entry) => Ok(Commit::new(&entry.new)?),
+        None => Err(Error::InvalidReference(format!("stash@{{{}}}", n))),
+    }
+}
+
+// Writes the tree of files given by path with their (hash, mode)
+fn write_files(files: &BTreeMap<String, (String, u32)>) -> Result<String, Error> {
+    let files: Vec<(&str, u32, &str)> = files
+        .iter()
+        .map(|(path, (hash, mode))| (path.as_str(), *mode, hash.as_str()))
+        .collect();
+    Ok(write_tree::write_subtree(&files)?)
+}
+
+// Saves the local changes of the files matching the pathspec as a stash,
+// then reverts them. Like git, a stash is a commit of the working dir whose
+// parents are HEAD, a commit of the index, and a commit of the untracked
+// files when there are some. Returns the message of the stash.
+pub fn push(pathspec: &Pathspec, opts: &Options) -> Result<String, Error> {
+    index::refresh()?;
+    let head = refs::get_ref_hash("HEAD")?;
+    if head.is_empty() {
+        return Err(Error::NoInitialCommit);
+    }
+    let branch = match refs::is_detached_head() {
+        true => String::from("(no branch)"),
+        false => refs::read_ref("HEAD")?,
+    };
+    let summary = format!(
+        "{}: {} {}",
+        branch,
+        &head[..7],
+        Commit::new(&head)?.subject()
+    );
+    let converter = Converter::load()?;
+    let no_renames = rename::Options::new();
+
+    // Index, for the files matching the pathspec
+    let head_files: BTreeMap<String, (String, u32)> = work_dir::read_commit_tree(&head)?
+        .into_iter()
+        .map(|e| (e.path, (e.hash, e.mode)))
+        .collect();
+    let mut index_files: BTreeMap<String, (String, u32)> = head_files
+        .iter()
+        .filter(|(path, _)| !pathspec.matches(path))
+        .map(|(path, file)| (path.to_string(), file.clone()))
+        .collect();
+    let entries = index::read_entries()?;
+    for entry in entries.iter().filter(|e| !e.is_intent_to_add()) {
+        if pathspec.matches(&entry.path) {
+            let file = (entry.hash.to_string(), entry.mode);
+            index_files.insert(entry.path.to_string(), file);
+        }
+    }
+    let index_tree = write_files(&index_files)?;
+
+    // Working dir: the index with the changes of the tracked files, or HEAD
+    // with the changes picked (which are then left to be reverted)
+    let mut work_files = index_files.clone();
+    let mut left = Vec::new();
+    if opts.patch {
+        work_files = head_files.clone();
+        let head_source = Source::Tree(head.to_string());
+        let pairs = diff::diff_sources(&head_source, &Source::WorkDir, pathspec, &no_renames)?;
+        for (path, picked, others) in patch::split_changes(&pairs, "Stash")? {
+            match picked {
+                Some((data, mode)) => {
+                    let hash = hash_object::hash_object(&data, "blob", true)?;
+                    work_files.insert(path.to_string(), (hash, mode));
+                }
+                None => {
+                    work_files.remove(&path);
+                }
+            }
+            left.push((path, others));
+        }
+        if left.is_empty() {
+            return Err(Error::NoChangesSelected);
+        }
+    } else {
+        let pairs = diff::diff_sources(&Source::Index, &Source::WorkDir, pathspec, &no_renames)?;
+        for pair in pairs {
+            let path = pair.path().to_string();
+            match pair.new {
+                Some(blob) => {
+                    let hash = hash_object::hash_object(&blob.data, "blob", true)?;
+                    work_files.insert(path, (hash, blob.mode));
+                }
+                None => {
+                    work_files.remove(&path);
+                }
+            }
+        }
+    }
+    let work_tree = write_files(&work_files)?;
+
+    let mut untracked = Vec::new();
+    if opts.include_untracked {
+        let tracked: HashSet<String> = entries.iter().map(|e| e.path.to_string()).collect();
+        let mut ignore = Ignore::standard()?;
+        let (files, _) = work_dir::get_untracked_files(&tracked, &mut ignore)?;
+        // Other repositories are left alone
+        untracked = files
+            .into_iter()
+            .filter(|p| !p.ends_with('/') && pathspec.matches(p))
+            .collect();
+    }
+
+    if index_files == head_files && work_files == index_files && untracked.is_empty() {
+        return Err(Error::NoLocalChanges);
+    }
+
+    let message = match opts.message {
+        Some(ref message) => format!("On {}: {}", branch, message),
+        None => format!("WIP on {}", summary),
+    };
+    let index_message = format!("index on {}", summary);
+    let index_commit = commit::commit_tree(&index_tree, &[head.to_string()], &index_message)?;
+    let mut parents = vec![head.to_string(), index_commit.to_string()];
+    if !untracked.is_empty() {
+        let mut files = BTreeMap::new();
+        for path in &untracked {
+            let meta = fs::symlink_metadata(path)?;
+            let mode = index::mode_of(&meta, None, converter.trust_executable_bit());
+            let data = index::read_blob_data(path, mode, &converter)?;
+            let hash = hash_object::hash_object(&data, "blob", true)?;
+            files.insert(path.to_string(), (hash, mode));
+        }
+        let untracked_message = format!("untracked files on {}", summary);
+        let tree = write_files(&files)?;
+        parents.push(commit::commit_tree(&tree, &[], &untracked_message)?);
+    }
+    let work_commit = commit::commit_tree(&work_tree, &parents, &message)?;
+
+    let previous = refs::read_ref(STASH)?;
+    refs::write_to_ref(STASH, &work_commit)?;
+    refs::append_log(STASH, &previous, &work_commit, &message)?;
+
+    // The changes saved are reverted
+    if opts.patch {
+        for (path, file) in left {
+            match file {
+                Some((data, mode)) => {
+                    let hash = hash_object::hash_object(&data, "blob", true)?;
+                    work_dir::write_blob(&path, &hash, mode, &converter)?;
+                }
+                None => work_dir::remove_path(&path)?,
+            }
+        }
+        index::refresh()?;
+    } else {
+        let overlay = false;
+        let both = (true, true);
+        match work_dir::restore_files(Some(&head), pathspec, both, overlay) {
+            // Only untracked files were matched
+            Err(work_dir::Error::PathspecNotMatched(_)) => (),
+            res => {
+                res?;
+            }
+        }
+        if opts.keep_index {
+            work_dir::restore_files(Some(&index_commit), pathspec, both, overlay)?;
+        }
+    }
+    for path in &untracked {
+        work_dir::remove_path(path)?;
+    }
+    Ok(message)
+}
+
+// Merges the changes of the nth stash into the working dir, they are staged
+// again with `restore_index`. Returns the paths left with conflicts.
+pub fn apply(n: usize, restore_index: bool) -> Result<Vec<String>, Error> {
+    let stash = read_stash(n)?;
+    if stash.parents.len() < 2 {
+        return Err(Error::InvalidReference(format!("stash@{{{}}}", n)));
+    }
+    let base = &stash.parents[0];
+    let index_commit = &stash.parents[1];
+    let converter = Converter::load()?;
+    let all = Pathspec::new(&[]);
+    let no_renames = rename::Options::new();
+
+    // Untracked files of the stash are not overwritten
+    let untracked = match stash.parents.get(2) {
+        Some(commit) => work_dir::read_commit_tree(commit)?,
+        None => Vec::new(),
+    };
+    let existing: Vec<String> = untracked
+        .iter()
+        .filter(|f| fs::symlink_metadata(&f.path).is_ok())
+        .map(|f| f.path.to_string())
+        .collect();
+    if !existing.is_empty() {
+        return Err(Error::UntrackedFilesExist(existing));
+    }
+
+    // The staged changes apply to files of the index which are as they were
+    // when stashed (or which have them already)
+    index::refresh()?;
+    let entries: BTreeMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
+    let staged = match restore_index {
+        true => {
+            let base_source = Source::Tree(base.to_string());
+            let index_source = Source::Tree(index_commit.to_string());
+            diff::diff_sources(&base_source, &index_source, &all, &no_renames)?
+        }
+        false => Vec::new(),
+    };
+    for pair in &staged {
+        let entry = entries.get(pair.path()).map(|e| &e.hash);
+        let is_applied = entry == pair.new.as_ref().map(|b| &b.hash);
+        if entry != pair.old.as_ref().map(|b| &b.hash) && !is_applied {
+            return Err(Error::IndexConflicts);
+        }
+    }
+
+    // The index is merged into as a commit of its own
+    let head = refs::get_ref_hash("HEAD")?;
+    let tree = write_tree::write_tree()?;
+    let ours = commit::commit_tree(&tree, &[head], "index")?;
+    let labels = ("Updated upstream", "Stashed changes");
+    let conflicts = work_dir::merge_from_base(base, (&ours, &stash.hash), labels)?;
+
+    // The changes are left unstaged, except for new files, and for the
+    // staged ones with `restore_index`
+    let mut new_entries = entries.clone();
+    let base_source = Source::Tree(base.to_string());
+    let stash_source = Source::Tree(stash.hash.to_string());
+    for pair in diff::diff_sources(&base_source, &stash_source, &all, &no_renames)? {
+        let path = pair.path();
+        if pair.old.is_none() && !entries.contains_key(path) && fs::symlink_metadata(path).is_ok() {
+            let entry = index::Entry::new(path, pair.new.as_ref().map(|b| b.mode), &converter)?;
+            new_entries.insert(path.to_string(), entry);
+        }
+    }
+    for pair in staged {
+        let path = pair.path().to_string();
+        match pair.new {
+            Some(blob) => {
+                let entry = index::Entry::without_stat(&path, blob.mode, &blob.hash);
+                new_entries.insert(path, entry);
+            }
+            None => {
+                new_entries.remove(&path);
+            }
+        }
+    }
+    index::write_entries(new_entries.into_iter().map(|(_, e)| e).collect())?;
+    index::refresh()?;
+
+    for file in untracked {
+        work_dir::write_blob(&file.path, &file.hash, file.mode, &converter)?;
+    }
+    Ok(conflicts)
+}
+
+// Changes of the nth stash since the commit it was made on
+fn show(n: usize, opts: &diff::Options) -> Result<String, Error> {
+    let stash = read_stash(n)?;
+    let base = stash.parents.get(0).map_or("", |p| p.as_str());
+    Ok(diff::format_commit_changes(
+        base,
+        &stash.hash,
+        &Pathspec::new(&[]),
+        opts,
+    )?)
+}
+
+// Removes the nth stash from the stack. Returns its commit.
+pub fn drop_stash(n: usize) -> Result<String, Error> {
+    let stash = read_stash(n)?;
+    refs::delete_log_entry(STASH, n)?;
+    match read_stashes()?.first() {
+        Some(entry) => refs::write_to_ref(STASH, &entry.new)?,
+        None => refs::delete_ref(STASH)?,
+    }
+    Ok(stash.hash)
+}
+
+// Drops the nth stash, telling which commit it was
+fn drop_and_print(n: usize) -> Result<(), Error> {
+    let hash = drop_stash(n)?;
+    println!("Dropped {}@{{{}}} ({})", STASH, n, hash);
+    Ok(())
+}
+
+pub fn clear() -> Result<(), Error> {
+    if !refs::read_ref(STASH)?.is_empty() {
+        refs::delete_ref(STASH)?;
+    }
+    Ok(())
+}
+
+// Creates a branch from the commit the nth stash was made on, where it is
+// applied and dropped
+pub fn branch(name: &str, n: usize) -> Result<(), Error> {
+    let stash = read_stash(n)?;
+    let base = stash.parents.get(0).map_or("", |p| p.as_str());
+    let reset = false;
+    checkout::switch_to(Head::NewBranch(name, reset), base, work_dir::Checkout::Safe)?;
+    let restore_index = true;
+    let conflicts = apply(n, restore_index)?;
+    for path in &conflicts {
+        println!("CONFLICT (content): Merge conflict in {}", path);
+    }
+    if conflicts.is_empty() {
+        drop_and_print(n)?;
+    }
+    Ok(())
+}
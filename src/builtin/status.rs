@@ -1,14 +1,37 @@
-use std::fs;
+use std::collections::{BTreeMap, HashSet};
 use std::io;
+use std::io::Write;
 
-use builtin::hash_object;
+use builtin::config;
+use builtin::diff;
+use builtin::diff::Source;
+use cli;
+use ignore::Ignore;
 use index;
+use pathspec::Pathspec;
+use refs;
+use rename;
+use revision;
 use work_dir;
 
 #[derive(Debug)]
 pub enum Error {
+    DiffError(diff::Error),
     IndexError(index::Error),
     IoError(io::Error),
+    RevisionError(revision::Error),
+}
+
+impl From<diff::Error> for Error {
+    fn from(e: diff::Error) -> Error {
+        Error::DiffError(e)
+    }
+}
+
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
 }
 
 impl From<io::Error> for Error {
@@ -17,57 +40,492 @@ impl From<io::Error> for Error {
     }
 }
 
-enum State {
-    Modified,
-    New,
-    Deleted,
+impl From<revision::Error> for Error {
+    fn from(e: revision::Error) -> Error {
+        Error::RevisionError(e)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
+}
+
+// Which untracked files are listed (-u<mode>)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Untracked {
+    No,
+    // Directories without tracked files are shown as "dir/"
+    Normal,
+    All,
+}
+
+// A tracked file which differs between HEAD, the index and the working dir
+#[derive(Debug)]
+pub struct Entry {
+    pub path: String,
+    // Path in HEAD of a file renamed in the index
+    pub orig_path: Option<String>,
+    pub score: Option<u32>,
+    // ' ', 'M', 'A', 'D' or 'R' for HEAD against the index (staged) and for
+    // the index against the working dir (unstaged)
+    pub staged: char,
+    pub unstaged: char,
+    // (mode, hash) in HEAD and in the index, mode in the working dir
+    pub head: Option<(u32, String)>,
+    pub index: Option<(u32, String)>,
+    pub work_mode: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Status {
+    // None when HEAD is detached
+    pub branch: Option<String>,
+    // Empty when there are no commits yet
+    pub head: String,
+    // (name, ahead, behind)
+    pub upstream: Option<(String, usize, usize)>,
+    pub entries: Vec<Entry>,
+    pub untracked: Vec<String>,
 }
 
-pub fn cmd_status() {
-    match status() {
-        Ok(changes) => {
-            for (state, path) in changes {
-                let s = match state {
-                    State::Modified => "modified",
-                    State::New => "new",
-                    State::Deleted => "deleted",
-                };
-                println!("{}: {}", s, path);
+pub fn cmd_status(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "-s",
+        "--short",
+        "-b",
+        "--branch",
+        "--long",
+        "--porcelain",
+        "--porcelain=",
+        "-z",
+        "-u",
+        "-uno",
+        "-unormal",
+        "-uall",
+        "--untracked-files",
+        "--untracked-files=",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+
+    let mut format = Format::Long;
+    let mut show_branch = false;
+    let mut nul = false;
+    let mut untracked = Untracked::Normal;
+    for flag in flags {
+        match flag.as_str() {
+            "-s" | "--short" => format = Format::Short,
+            "-b" | "--branch" => show_branch = true,
+            "--long" => format = Format::Long,
+            "--porcelain" | "--porcelain=v1" => format = Format::PorcelainV1,
+            "--porcelain=v2" => format = Format::PorcelainV2,
+            "-z" => nul = true,
+            "-uno" | "--untracked-files=no" => untracked = Untracked::No,
+            "-unormal" | "--untracked-files=normal" => untracked = Untracked::Normal,
+            "-u" | "-uall" | "--untracked-files" | "--untracked-files=all" => {
+                untracked = Untracked::All
+            }
+            _ if flag.starts_with("--untracked-files=") => {
+                println!("fatal: Invalid untracked files mode '{}'", &flag[18..]);
+                return;
+            }
+            _ => {
+                println!("status: unknown porcelain version: {}", flag);
+                return;
             }
         }
-        Err(why) => println!("Could not retrieve status: {:?}", why),
+    }
+    // -z implies the porcelain format unless another one is given
+    if nul && format == Format::Long && !flags.iter().any(|f| f == "--long") {
+        format = Format::PorcelainV1;
+    }
+
+    let (args, paths) = cli::split_paths(args);
+    let pathspec = Pathspec::new(&[&args[..], &paths[..]].concat());
+    let status = match status(&pathspec, untracked) {
+        Ok(status) => status,
+        Err(why) => {
+            println!("Could not retrieve status: {:?}", why);
+            return;
+        }
+    };
+
+    let res = match format {
+        Format::Long => format_long(&status),
+        Format::Short | Format::PorcelainV1 => format_short(&status, show_branch, nul),
+        Format::PorcelainV2 => format_porcelain_v2(&status, show_branch, nul),
     };
+    let _ = io::stdout().write_all(res.as_bytes());
 }
 
-fn status() -> Result<Vec<(State, String)>, Error> {
-    let mut status = Vec::new();
-    let index = index::read_entries().map_err(Error::IndexError)?;
-    let files = work_dir::get_all_files_path()?;
-    for file in &files {
-        match index.iter().find(|e| file == &e.path) {
-            Some(e) => {
-                let file_content = fs::read(&file)?;
-                let hash = hash_object::hash_object(&file_content, "blob", false)?;
-                if e.hash != hash {
-                    status.push((State::Modified, file.to_string()));
-                }
+pub fn status(pathspec: &Pathspec, show_untracked: Untracked) -> Result<Status, Error> {
+    // Files whose stat data is still valid are not read again
+    index::refresh()?;
+
+    let head = refs::get_ref_hash("HEAD")?;
+    let staged = diff::diff_sources(
+        &Source::Tree(head.to_string()),
+        &Source::Index,
+        pathspec,
+        &rename_options(),
+    )?;
+    let unstaged = diff::diff_sources(
+        &Source::Index,
+        &Source::WorkDir,
+        pathspec,
+        &rename::Options::new(),
+    )?;
+
+    // Entries by path in the index
+    let mut entries: BTreeMap<String, Entry> = BTreeMap::new();
+    for pair in &staged {
+        let mut entry = new_entry(pair.path());
+        entry.staged = pair.status();
+        entry.head = pair.old.as_ref().map(|b| (b.mode, b.hash.to_string()));
+        entry.index = pair.new.as_ref().map(|b| (b.mode, b.hash.to_string()));
+        entry.work_mode = entry.index.as_ref().map(|i| i.0);
+        if pair.score.is_some() {
+            entry.orig_path = pair.old.as_ref().map(|b| b.path.to_string());
+            entry.score = pair.score;
+        }
+        entries.insert(pair.path().to_string(), entry);
+    }
+    for pair in &unstaged {
+        let index = pair.old.as_ref().map(|b| (b.mode, b.hash.to_string()));
+        let entry = entries.entry(pair.path().to_string()).or_insert_with(|| {
+            let mut entry = new_entry(pair.path());
+            entry.head = index.clone();
+            entry.index = index.clone();
+            entry
+        });
+        entry.unstaged = pair.status();
+        entry.work_mode = pair.new.as_ref().map(|b| b.mode);
+    }
+
+    let tracked: HashSet<String> = index::read_entries()?.into_iter().map(|e| e.path).collect();
+    let mut ignore = Ignore::standard()?;
+    let untracked = match show_untracked {
+        Untracked::No => Vec::new(),
+        _ => {
+            let (untracked, _) = work_dir::get_untracked_files(&tracked, &mut ignore)?;
+            let untracked: Vec<String> = untracked
+                .into_iter()
+                .filter(|f| pathspec.matches(f))
+                .collect();
+            match show_untracked {
+                Untracked::Normal => collapse_untracked_dirs(untracked, &tracked),
+                _ => untracked,
             }
-            None => status.push((State::New, file.to_string())),
+        }
+    };
+
+    let branch = match refs::is_detached_head() {
+        true => None,
+        false => Some(refs::read_ref("HEAD")?),
+    };
+    let upstream = match &branch {
+        Some(branch) if !head.is_empty() => upstream(branch, &head)?,
+        _ => None,
+    };
+
+    Ok(Status {
+        branch,
+        head,
+        upstream,
+        entries: entries.into_iter().map(|(_, e)| e).collect(),
+        untracked,
+    })
+}
+
+fn new_entry(path: &str) -> Entry {
+    Entry {
+        path: path.to_string(),
+        orig_path: None,
+        score: None,
+        staged: ' ',
+        unstaged: ' ',
+        head: None,
+        index: None,
+        work_mode: None,
+    }
+}
+
+// Files are replaced by their topmost directory holding no tracked file,
+// written "dir/"
+fn collapse_untracked_dirs(untracked: Vec<String>, tracked: &HashSet<String>) -> Vec<String> {
+    let mut tracked_dirs = HashSet::new();
+    for path in tracked {
+        for (i, _) in path.match_indices('/') {
+            tracked_dirs.insert(&path[..i]);
+        }
+    }
+
+    let mut res: Vec<String> = Vec::new();
+    for path in untracked {
+        let trimmed = path.trim_end_matches('/');
+        let untracked_dir = trimmed
+            .match_indices('/')
+            .map(|(i, _)| &trimmed[..i])
+            .find(|dir| !tracked_dirs.contains(dir));
+        let path = match untracked_dir {
+            Some(dir) => format!("{}/", dir),
+            None => path.to_string(),
         };
+        // Files are sorted, those of a directory follow each other
+        if res.last() != Some(&path) {
+            res.push(path);
+        }
     }
+    res
+}
 
-    for entry in &index {
-        if files.iter().all(|x| x != &entry.path) {
-            status.push((State::Deleted, entry.path.to_string()));
+// Renames are detected between HEAD and the index unless status.renames is
+// false
+fn rename_options() -> rename::Options {
+    let mut opts = rename::Options::from_config();
+    opts.copies = false;
+    if let Ok(config) = config::Config::new() {
+        if let Some(renames) = config.get_bool("status.renames") {
+            opts.renames = renames;
         }
     }
+    opts
+}
+
+// branch.<name>.remote and branch.<name>.merge give the upstream branch,
+// returned with the number of commits only found on each side
+fn upstream(branch: &str, head: &str) -> Result<Option<(String, usize, usize)>, Error> {
+    let config = config::Config::new()?;
+    let remote = config.get_value(&format!("branch.{}.remote", branch));
+    let merge = config.get_value(&format!("branch.{}.merge", branch));
+    let (remote, merge) = match (remote, merge) {
+        (Some(r), Some(m)) => (r, m.trim_start_matches("refs/heads/")),
+        _ => return Ok(None),
+    };
+    let (name, full_name) = match remote {
+        "." => (merge.to_string(), format!("refs/heads/{}", merge)),
+        r => (
+            format!("{}/{}", r, merge),
+            format!("refs/remotes/{}/{}", r, merge),
+        ),
+    };
+
+    let upstream = match revision::resolve(&full_name) {
+        Ok(hash) => hash,
+        // The upstream branch is gone
+        Err(_) => return Ok(Some((name, 0, 0))),
+    };
+    let ours: HashSet<String> = revision::walk(&[head.to_string()])?
+        .into_iter()
+        .map(|c| c.hash)
+        .collect();
+    let theirs: HashSet<String> = revision::walk(&[upstream])?
+        .into_iter()
+        .map(|c| c.hash)
+        .collect();
+    let ahead = ours.difference(&theirs).count();
+    let behind = theirs.difference(&ours).count();
+    Ok(Some((name, ahead, behind)))
+}
 
-    Ok(status)
+fn plural(n: usize) -> &'static str {
+    match n {
+        1 => "commit",
+        _ => "commits",
+    }
 }
 
-pub fn is_clean_work_dir() -> bool {
-    match status() {
-        Ok(changes) => changes.is_empty(),
-        Err(_) => false,
+fn format_long(status: &Status) -> String {
+    let mut res = match &status.branch {
+        Some(branch) => format!("On branch {}\n", branch),
+        None => format!("HEAD detached at {}\n", &status.head[..7]),
+    };
+    if let Some((name, ahead, behind)) = &status.upstream {
+        let line = match (*ahead, *behind) {
+            (0, 0) => format!("Your branch is up to date with '{}'.\n", name),
+            (a, 0) => format!(
+                "Your branch is ahead of '{}' by {} {}.\n",
+                name,
+                a,
+                plural(a)
+            ),
+            (0, b) => format!(
+                "Your branch is behind '{}' by {} {}, and can be fast-forwarded.\n",
+                name,
+                b,
+                plural(b)
+            ),
+            (a, b) => format!(
+                "Your branch and '{}' have diverged,\nand have {} and {} different commits each, respectively.\n",
+                name, a, b
+            ),
+        };
+        res.push_str(&line);
+    }
+    if status.head.is_empty() {
+        res.push_str("\nNo commits yet\n");
+    }
+
+    let describe = |state: char| match state {
+        'A' => "new file:",
+        'D' => "deleted:",
+        'R' => "renamed:",
+        _ => "modified:",
+    };
+    let staged: Vec<&Entry> = status.entries.iter().filter(|e| e.staged != ' ').collect();
+    if !staged.is_empty() {
+        res.push_str("\nChanges to be committed:\n");
+        for entry in &staged {
+            let path = match &entry.orig_path {
+                Some(orig) => format!("{} -> {}", orig, entry.path),
+                None => entry.path.to_string(),
+            };
+            res.push_str(&format!("\t{:<12}{}\n", describe(entry.staged), path));
+        }
+    }
+    let unstaged: Vec<&Entry> = status
+        .entries
+        .iter()
+        .filter(|e| e.unstaged != ' ')
+        .collect();
+    if !unstaged.is_empty() {
+        res.push_str("\nChanges not staged for commit:\n");
+        for entry in &unstaged {
+            res.push_str(&format!(
+                "\t{:<12}{}\n",
+                describe(entry.unstaged),
+                entry.path
+            ));
+        }
+    }
+    if !status.untracked.is_empty() {
+        res.push_str("\nUntracked files:\n");
+        for path in &status.untracked {
+            res.push_str(&format!("\t{}\n", path));
+        }
+    }
+
+    if staged.is_empty() {
+        res.push('\n');
+        res.push_str(match (unstaged.is_empty(), status.untracked.is_empty()) {
+            (false, _) => "no changes added to commit\n",
+            (true, false) => "nothing added to commit but untracked files present\n",
+            (true, true) => "nothing to commit, working tree clean\n",
+        });
+    }
+    res
+}
+
+// "## <branch>...<upstream> [ahead <n>, behind <m>]"
+fn format_branch_line(status: &Status) -> String {
+    let mut res = String::from("## ");
+    match &status.branch {
+        Some(branch) if status.head.is_empty() => {
+            res.push_str(&format!("No commits yet on {}", branch))
+        }
+        Some(branch) => res.push_str(branch),
+        None => res.push_str("HEAD (no branch)"),
+    }
+    if let Some((name, ahead, behind)) = &status.upstream {
+        res.push_str(&format!("...{}", name));
+        let mut counts = Vec::new();
+        if *ahead > 0 {
+            counts.push(format!("ahead {}", ahead));
+        }
+        if *behind > 0 {
+            counts.push(format!("behind {}", behind));
+        }
+        if !counts.is_empty() {
+            res.push_str(&format!(" [{}]", counts.join(", ")));
+        }
+    }
+    res
+}
+
+// "XY <path>", "XY <orig> -> <path>" and "?? <path>". With -z, lines end
+// with a NUL and renames are written "XY <path>\0<orig>".
+fn format_short(status: &Status, show_branch: bool, nul: bool) -> String {
+    let end = if nul { '\0' } else { '\n' };
+    let mut res = String::new();
+    if show_branch {
+        res.push_str(&format_branch_line(status));
+        res.push(end);
+    }
+    for entry in &status.entries {
+        res.push_str(&format!("{}{} ", entry.staged, entry.unstaged));
+        match (&entry.orig_path, nul) {
+            (Some(orig), true) => res.push_str(&format!("{}\0{}", entry.path, orig)),
+            (Some(orig), false) => res.push_str(&format!("{} -> {}", orig, entry.path)),
+            (None, _) => res.push_str(&entry.path),
+        }
+        res.push(end);
+    }
+    for path in &status.untracked {
+        res.push_str(&format!("?? {}{}", path, end));
+    }
+    res
+}
+
+// "1 XY N... <mH> <mI> <mW> <hH> <hI> <path>" for changed entries,
+// "2 ... R<score> <path>\t<orig>" for renamed ones and "? <path>"
+fn format_porcelain_v2(status: &Status, show_branch: bool, nul: bool) -> String {
+    let end = if nul { '\0' } else { '\n' };
+    let mut res = String::new();
+    if show_branch {
+        let oid = match status.head.as_str() {
+            "" => "(initial)",
+            h => h,
+        };
+        res.push_str(&format!("# branch.oid {}{}", oid, end));
+        let head = status.branch.as_ref().map(|b| b.as_str());
+        res.push_str(&format!(
+            "# branch.head {}{}",
+            head.unwrap_or("(detached)"),
+            end
+        ));
+        if let Some((name, ahead, behind)) = &status.upstream {
+            res.push_str(&format!("# branch.upstream {}{}", name, end));
+            res.push_str(&format!("# branch.ab +{} -{}{}", ahead, behind, end));
+        }
+    }
+
+    let mode = |m: Option<u32>| format!("{:06o}", m.unwrap_or(0));
+    let hash = |h: &Option<(u32, String)>| match h {
+        Some((_, hash)) => hash.to_string(),
+        None => "0".repeat(40),
+    };
+    for entry in &status.entries {
+        let xy: String = [entry.staged, entry.unstaged]
+            .iter()
+            .map(|&c| if c == ' ' { '.' } else { c })
+            .collect();
+        let fields = format!(
+            "{} N... {} {} {} {} {}",
+            xy,
+            mode(entry.head.as_ref().map(|h| h.0)),
+            mode(entry.index.as_ref().map(|i| i.0)),
+            mode(entry.work_mode),
+            hash(&entry.head),
+            hash(&entry.index)
+        );
+        match (&entry.orig_path, entry.score) {
+            (Some(orig), Some(score)) => {
+                let sep = if nul { '\0' } else { '\t' };
+                res.push_str(&format!(
+                    "2 {} R{} {}{}{}{}",
+                    fields, score, entry.path, sep, orig, end
+                ));
+            }
+            _ => res.push_str(&format!("1 {} {}{}", fields, entry.path, end)),
+        }
+    }
+    for path in &status.untracked {
+        res.push_str(&format!("? {}{}", path, end));
     }
+    res
 }
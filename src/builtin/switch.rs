@@ -0,0 +1,64 @@
+use builtin::checkout;
+use builtin::checkout::Head;
+use cli;
+use refs;
+use revision;
+
+pub fn cmd_switch(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--create",
+        "-c",
+        "--force-create",
+        "-C",
+        "--detach",
+        "-d",
+        "--orphan",
+        "--discard-changes",
+        "--force",
+        "-f",
+        "--merge",
+        "-m",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+    let mode = match checkout::checkout_mode(flags) {
+        Some(mode) => mode,
+        None => return,
+    };
+
+    let name = match args.get(0) {
+        Some(name) => name,
+        None => {
+            println!("switch: missing branch or commit argument.");
+            return;
+        }
+    };
+    let create = cli::has_flag(flags, "--create", "-c");
+    let force_create = cli::has_flag(flags, "--force-create", "-C");
+    let res = if cli::has_flag(flags, "--orphan", "--orphan") {
+        // Starts from an empty tree
+        checkout::switch_to(Head::Orphan(name), "", mode)
+    } else if create || force_create {
+        let start = args.get(1).map(|s| s.as_str()).unwrap_or("HEAD");
+        revision::resolve(start)
+            .map_err(checkout::Error::RevisionError)
+            .and_then(|commit| {
+                checkout::switch_to(Head::NewBranch(name, force_create), &commit, mode)
+            })
+    } else if cli::has_flag(flags, "--detach", "-d") {
+        checkout::checkout_rev(name, true, mode)
+    } else if name == "-" || refs::is_branch(name) {
+        checkout::checkout_rev(name, false, mode)
+    } else {
+        Err(checkout::Error::ExpectedBranch(name.to_string()))
+    };
+
+    match res {
+        Err(checkout::Error::ExpectedBranch(name)) => {
+            println!("fatal: a branch is expected, got '{}'", name);
+        }
+        Err(why) => checkout::print_error(why, "switch"),
+        Ok(_) => (),
+    }
+}
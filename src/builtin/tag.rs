@@ -0,0 +1,398 @@
+use std::cmp::Ordering;
+use std::io;
+
+use builtin::commit;
+use builtin::commit::{Commit, Signature};
+use builtin::config;
+use builtin::hash_object;
+use cli;
+use date;
+use object;
+use object::Object;
+use refs;
+use revision;
+use wildmatch;
+
+#[derive(Debug)]
+pub enum Error {
+    CommitError(commit::Error),
+    InvalidSort(String),
+    InvalidTag,
+    InvalidTagName(String),
+    IoError(io::Error),
+    MissingMessage,
+    NotATagObject,
+    ObjectError(object::Error),
+    // Boxed as revision errors hold tag errors, from peeling
+    RevisionError(Box<revision::Error>),
+    TagExists(String),
+    TagNotFound(String),
+    UserConfigIncomplete,
+}
+
+impl From<commit::Error> for Error {
+    fn from(e: commit::Error) -> Error {
+        Error::CommitError(e)
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+impl From<object::Error> for Error {
+    fn from(e: object::Error) -> Error {
+        Error::ObjectError(e)
+    }
+}
+impl From<revision::Error> for Error {
+    fn from(e: revision::Error) -> Error {
+        Error::RevisionError(Box::new(e))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tag {
+    pub object: String,
+    pub obj_type: String,
+    pub name: String,
+    pub tagger: Option<Signature>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn new(hash: &str) -> Result<Tag, Error> {
+        let object = Object::new(hash)?;
+        if object.obj_type != "tag" {
+            return Err(Error::NotATagObject);
+        }
+        let data = String::from_utf8_lossy(&object.data).to_string();
+
+        let (header, message) = match data.find("\n\n") {
+            Some(i) => (&data[..i], &data[i + 2..]),
+            None => (data.as_str(), ""),
+        };
+
+        let mut target = String::new();
+        let mut obj_type = String::new();
+        let mut name = String::new();
+        let mut tagger = None;
+        for line in header.lines() {
+            let (key, value) = match line.find(' ') {
+                Some(i) => (&line[..i], &line[i + 1..]),
+                None => continue,
+            };
+            match key {
+                "object" => target = value.to_string(),
+                "type" => obj_type = value.to_string(),
+                "tag" => name = value.to_string(),
+                "tagger" => tagger = Some(Signature::parse(value)?),
+                _ => (),
+            }
+        }
+
+        if target.len() != 40 || obj_type.is_empty() {
+            return Err(Error::InvalidTag);
+        }
+
+        Ok(Tag {
+            object: target,
+            obj_type,
+            name,
+            tagger,
+            message: message.to_string(),
+        })
+    }
+}
+
+// The object a tag points to, following tags of tags. Other objects are
+// their own target.
+pub fn peel(hash: &str) -> Result<String, Error> {
+    let mut hash = hash.to_string();
+    if Object::new(&hash)?.obj_type != "tag" {
+        return Ok(hash);
+    }
+    loop {
+        let tag = Tag::new(&hash)?;
+        hash = tag.object;
+        if tag.obj_type != "tag" {
+            return Ok(hash);
+        }
+    }
+}
+
+pub fn cmd_tag(args: &[String], flags: &[String]) {
+    let accepted_flags = [
+        "--annotate",
+        "-a",
+        "--message=",
+        "-m",
+        "--force",
+        "-f",
+        "--delete",
+        "-d",
+        "--list",
+        "-l",
+        "-n",
+        "--sort=",
+    ];
+    if !cli::has_known_flags(flags, &accepted_flags) {
+        return;
+    }
+
+    // With -m, the message comes first: tag -m <msg> <name> [<rev>]
+    let mut args = args.to_vec();
+    let message = match flags.iter().find(|f| f.starts_with("--message=")) {
+        Some(f) => Some(f["--message=".len()..].to_string()),
+        None if flags.iter().any(|f| f == "-m") && !args.is_empty() => Some(args.remove(0)),
+        None => None,
+    };
+    let lines = flags
+        .iter()
+        .find(|f| f.starts_with("-n"))
+        .map(|f| f[2..].parse::<usize>().unwrap_or(1));
+    let sort = flags
+        .iter()
+        .find(|f| f.starts_with("--sort="))
+        .map_or("refname", |f| &f["--sort=".len()..]);
+
+    let res = if cli::has_flag(flags, "--delete", "-d") {
+        delete_tags(&args)
+    } else if cli::has_flag(flags, "--list", "-l") || lines.is_some() || args.is_empty() {
+        list_tags(&args, lines, sort)
+    } else {
+        let annotate = cli::has_flag(flags, "--annotate", "-a") || message.is_some();
+        let message = match (annotate, message) {
+            (true, None) => Err(Error::MissingMessage),
+            (_, message) => Ok(message),
+        };
+        let rev = args.get(1).map_or("HEAD", |r| r.as_str());
+        let force = cli::has_flag(flags, "--force", "-f");
+        message.and_then(|m| tag(&args[0], rev, m.as_ref().map(|m| m.as_str()), force))
+    };
+
+    match res {
+        Err(Error::InvalidSort(key)) => println!("fatal: unsupported sort specification '{}'", key),
+        Err(Error::InvalidTagName(name)) => println!("fatal: '{}' is not a valid tag name.", name),
+        Err(Error::MissingMessage) => println!("fatal: no tag message given, use -m <msg>"),
+        Err(Error::TagExists(name)) => println!("fatal: tag '{}' already exists", name),
+        // Already reported for each missing tag
+        Err(Error::TagNotFound(_)) => (),
+        Err(why) => println!("Could not use tag: {:?}", why),
+        Ok(()) => (),
+    }
+}
+
+// Points refs/tags/<name> to a revision, or to a new tag object of it when
+// there is a message
+pub fn tag(name: &str, rev: &str, message: Option<&str>, force: bool) -> Result<(), Error> {
+    if !is_valid_name(name) {
+        return Err(Error::InvalidTagName(name.to_string()));
+    }
+    let ref_name = format!("refs/tags/{}", name);
+    let old = refs::read_ref(&ref_name)?;
+    if !old.is_empty() && !force {
+        return Err(Error::TagExists(name.to_string()));
+    }
+
+    let target = revision::resolve_object(rev)?;
+    let hash = match message {
+        Some(message) => write_tag(&target, name, message)?,
+        None => target,
+    };
+    refs::write_to_ref(&ref_name, &hash)?;
+
+    if !old.is_empty() && old != hash {
+        println!("Updated tag '{}' (was {})", name, &old[..7]);
+    }
+    Ok(())
+}
+
+// Writes a tag object of an object, tagged by the user now
+pub fn write_tag(target: &str, name: &str, message: &str) -> Result<String, Error> {
+    let user = config::Config::new()?;
+    if user.name.is_empty() || user.email.is_empty() {
+        return Err(Error::UserConfigIncomplete);
+    }
+    let obj_type = Object::new(target)?.obj_type;
+
+    let message = message.trim();
+    let message = match message.is_empty() {
+        true => String::new(),
+        false => format!("{}\n", message),
+    };
+    let content = format!(
+        "object {}\n\
+         type {}\n\
+         tag {}\n\
+         tagger {} <{}> {} +0200\n\n\
+         {}",
+        target,
+        obj_type,
+        name,
+        user.name,
+        user.email,
+        date::now(),
+        message
+    );
+
+    let write = true;
+    let hash = hash_object::hash_object(content.as_bytes(), "tag", write)?;
+    Ok(hash)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name.contains(|c: char| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c))
+}
+
+pub fn delete_tags(names: &[String]) -> Result<(), Error> {
+    let mut missing = None;
+    for name in names {
+        let ref_name = format!("refs/tags/{}", name);
+        let hash = refs::read_ref(&ref_name)?;
+        if hash.is_empty() {
+            println!("error: tag '{}' not found.", name);
+            missing = Some(name.to_string());
+            continue;
+        }
+
+        refs::delete_ref(&ref_name)?;
+        println!("Deleted tag '{}' (was {})", name, &hash[..7]);
+    }
+
+    match missing {
+        Some(name) => Err(Error::TagNotFound(name)),
+        None => Ok(()),
+    }
+}
+
+// Tags matching any of the patterns (all of them without patterns), with the
+// first `lines` lines of their message
+pub fn list_tags(patterns: &[String], lines: Option<usize>, sort: &str) -> Result<(), Error> {
+    let mut tags: Vec<(String, String)> = refs::list_refs()?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("refs/tags/"))
+        .map(|(name, hash)| (refs::short_name(&name).to_string(), hash))
+        .filter(|(name, _)| {
+            patterns.is_empty()
+                || patterns
+                    .iter()
+                    .any(|p| wildmatch::wildmatch(p, name, wildmatch::Flags::default()))
+        })
+        .collect();
+
+    let (reverse, key) = match sort.starts_with('-') {
+        true => (true, &sort[1..]),
+        false => (false, sort),
+    };
+    match key {
+        "refname" => tags.sort(),
+        "version:refname" | "v:refname" => tags.sort_by(|a, b| compare_versions(&a.0, &b.0)),
+        _ => return Err(Error::InvalidSort(sort.to_string())),
+    }
+    if reverse {
+        tags.reverse();
+    }
+
+    for (name, hash) in tags {
+        let nb_lines = match lines {
+            Some(n) if n > 0 => n,
+            _ => {
+                println!("{}", name);
+                continue;
+            }
+        };
+
+        let message = annotation(&hash)?;
+        let mut message_lines = message.lines().take(nb_lines);
+        let first = message_lines.next().unwrap_or("");
+        println!("{:<15} {}", name, first);
+        for line in message_lines {
+            println!("    {}", line);
+        }
+    }
+    Ok(())
+}
+
+// Message of a tag object, or of the commit a lightweight tag points to
+fn annotation(hash: &str) -> Result<String, Error> {
+    match Object::new(hash)?.obj_type.as_str() {
+        "tag" => Ok(Tag::new(hash)?.message),
+        "commit" => Ok(Commit::new(hash)?.message),
+        _ => Ok(String::new()),
+    }
+}
+
+// Compares names as versions, runs of digits being compared as numbers
+// ("v1.9" comes before "v1.10")
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if !a[i].is_ascii_digit() || !b[j].is_ascii_digit() {
+            if a[i] != b[j] {
+                return a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let end_a = i + a[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let end_b = j + b[j..].iter().take_while(|c| c.is_ascii_digit()).count();
+        let trim = |digits: &[char]| -> String {
+            let number: String = digits.iter().collect();
+            number.trim_start_matches('0').to_string()
+        };
+        let (num_a, num_b) = (trim(&a[i..end_a]), trim(&b[j..end_b]));
+        let ord = num_a
+            .len()
+            .cmp(&num_b.len())
+            .then_with(|| num_a.cmp(&num_b));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+        i = end_a;
+        j = end_b;
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_compare_numbers() {
+        assert_eq!(compare_versions("v1.9", "v1.10"), Ordering::Less);
+        assert_eq!(compare_versions("v2.0", "v1.10"), Ordering::Greater);
+        assert_eq!(compare_versions("v1.0", "v1.0"), Ordering::Equal);
+        assert_eq!(compare_versions("v1.01", "v1.1"), Ordering::Equal);
+    }
+
+    #[test]
+    fn versions_compare_text() {
+        assert_eq!(compare_versions("v1.0", "v1.0.1"), Ordering::Less);
+        assert_eq!(compare_versions("a10", "b2"), Ordering::Less);
+        assert_eq!(compare_versions("release", "v1"), Ordering::Less);
+    }
+
+    #[test]
+    fn tag_names() {
+        assert!(is_valid_name("v1.0"));
+        assert!(is_valid_name("release/2.0"));
+        assert!(!is_valid_name("-v1"));
+        assert!(!is_valid_name("v1..2"));
+        assert!(!is_valid_name("v 1"));
+        assert!(!is_valid_name("v1.lock"));
+    }
+}
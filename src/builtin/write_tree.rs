@@ -18,11 +18,49 @@ pub fn cmd_write_tree() {
 }
 
 pub fn write_tree() -> Result<String, Error> {
-    let mut tree = Vec::new();
     let entries = index::read_entries().map_err(Error::IndexError)?;
-    for entry in entries {
-        let tree_entry = format!("{:o} {}\x00", entry.mode, entry.path);
-        let compressed_hash = match sha1::compress_hash(&entry.hash) {
+    // Files which are only going to be added are not part of the tree
+    let files: Vec<(&str, u32, &str)> = entries
+        .iter()
+        .filter(|e| !e.is_intent_to_add())
+        .map(|e| (e.path.as_str(), e.mode, e.hash.as_str()))
+        .collect();
+    write_subtree(&files)
+}
+
+// Writes the tree of a directory and the ones of its subdirectories, from
+// the (path, mode, hash) of its files sorted by path
+pub fn write_subtree(files: &[(&str, u32, &str)]) -> Result<String, Error> {
+    // (sort key, mode, name, hash), directories being sorted as "name/"
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < files.len() {
+        let (path, mode, hash) = files[i];
+        let slash = match path.find('/') {
+            Some(slash) => slash,
+            None => {
+                entries.push((path.to_string(), mode, path, hash.to_string()));
+                i += 1;
+                continue;
+            }
+        };
+
+        let dir = &path[..slash + 1];
+        let sub_files: Vec<(&str, u32, &str)> = files[i..]
+            .iter()
+            .take_while(|f| f.0.starts_with(dir))
+            .map(|&(path, mode, hash)| (&path[dir.len()..], mode, hash))
+            .collect();
+        i += sub_files.len();
+        let hash = write_subtree(&sub_files)?;
+        entries.push((dir.to_string(), index::MODE_TREE, &path[..slash], hash));
+    }
+    entries.sort();
+
+    let mut tree = Vec::new();
+    for (_, mode, name, hash) in entries {
+        let tree_entry = format!("{:o} {}\x00", mode, name);
+        let compressed_hash = match sha1::compress_hash(&hash) {
             Some(hash) => hash,
             None => continue,
         };
@@ -4,7 +4,14 @@ pub fn has_flag(args: &[String], long_fmt: &str, short_fmt: &str) -> bool {
 
 pub fn has_known_flags(flags: &[String], known_flags: &[&str]) -> bool {
     for flag in flags {
-        let is_known = known_flags.contains(&flag.as_str());
+        // "--flag=" accepts any value given to the flag, "-f" a number
+        // right after it ("-U5", "-M90%")
+        let is_number = |v: &str| v.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '%');
+        let is_known = known_flags.iter().any(|k| {
+            k == flag
+                || (k.ends_with('=') && flag.starts_with(k))
+                || (k.len() == 2 && flag.starts_with(k) && is_number(&flag[2..]))
+        });
         if !is_known {
             println!("unknown flag: {}", flag);
             return false;
@@ -13,15 +20,54 @@ pub fn has_known_flags(flags: &[String], known_flags: &[&str]) -> bool {
     return true;
 }
 
+// Joins the flags taking a value with the argument after them: "-n 5"
+// becomes "-n5" and "--max-count 5" "--max-count=5"
+pub fn join_flag_values(input: Vec<String>, flags_with_value: &[&str]) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut input = input.into_iter();
+    while let Some(opt) = input.next() {
+        if opt == "--" {
+            res.push(opt);
+            res.extend(input);
+            break;
+        }
+        if flags_with_value.contains(&opt.as_str()) {
+            if let Some(value) = input.next() {
+                let separator = if opt.starts_with("--") { "=" } else { "" };
+                res.push(format!("{}{}{}", opt, separator, value));
+                continue;
+            }
+        }
+        res.push(opt);
+    }
+    res
+}
+
 pub fn split_args_from_flags(input: Vec<String>) -> (Vec<String>, Vec<String>) {
     let mut args = Vec::new();
     let mut flags = Vec::new();
+    let mut only_args = false;
     for opt in input {
-        if opt.starts_with("-") {
-            flags.push(opt);
+        // Everything after "--" is a path, keep the separator in the args.
+        // A lone "-" is an argument (the previous branch).
+        if opt.starts_with("-") && opt != "-" && !only_args {
+            only_args = opt == "--";
+            if only_args {
+                args.push(opt);
+            } else {
+                flags.push(opt);
+            }
         } else {
             args.push(opt);
         }
     }
     (args, flags)
 }
+
+// [<revision>...] -- [<path>...]
+pub fn split_paths(args: &[String]) -> (Vec<String>, Vec<String>) {
+    match args.iter().position(|a| a == "--") {
+        Some(i) => (args[..i].to_vec(), args[i + 1..].to_vec()),
+        None => (args.to_vec(), Vec::new()),
+    }
+}
@@ -0,0 +1,408 @@
+// Conversions between the content of a file in the working dir and the one
+// of its blob: line endings are normalized to LF in the repo for text files
+// (`text`, `text=auto`, `eol` attributes, core.autocrlf) and written with
+// the line endings configured by `eol` and core.eol on checkout. Files can
+// also go through `filter` drivers, `ident` expansion and a
+// `working-tree-encoding`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+
+use attributes::{Attributes, Value};
+use builtin::config;
+use builtin::diff;
+use builtin::hash_object;
+use filter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AutoCrlf {
+    False,
+    True,
+    // Normalize on add only
+    Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, PartialEq)]
+enum Text {
+    // Never converted
+    Binary,
+    Text,
+    // Converted unless the content looks binary
+    Auto,
+}
+
+#[derive(Debug)]
+pub struct Converter {
+    attributes: Attributes,
+    config: config::Config,
+    autocrlf: AutoCrlf,
+    eol: Eol,
+    // Long-running filters by driver name, None when it failed to start
+    processes: RefCell<HashMap<String, Option<filter::Process>>>,
+}
+
+impl Converter {
+    pub fn load() -> io::Result<Converter> {
+        let config = config::Config::new()?;
+        let autocrlf = match config.get_value("core.autocrlf") {
+            Some("input") => AutoCrlf::Input,
+            Some(_) if config.get_bool("core.autocrlf") == Some(true) => AutoCrlf::True,
+            _ => AutoCrlf::False,
+        };
+        let eol = match config.get_value("core.eol") {
+            Some("crlf") => Eol::Crlf,
+            _ => Eol::Lf,
+        };
+        Ok(Converter {
+            attributes: Attributes::new(),
+            config,
+            autocrlf,
+            eol,
+            processes: RefCell::new(HashMap::new()),
+        })
+    }
+
+    // core.fileMode, false on file systems where the executable bit of files
+    // is meaningless
+    pub fn trust_executable_bit(&self) -> bool {
+        self.config.get_bool("core.fileMode") != Some(false)
+    }
+
+    fn text(&self, path: &str) -> Text {
+        match self.attributes.get(path, "text") {
+            Some(Value::Set) => Text::Text,
+            Some(Value::Unset) => Text::Binary,
+            Some(Value::Value(ref v)) if v == "auto" => Text::Auto,
+            // Setting eol makes a file text
+            _ if self.attributes.get(path, "eol").is_some() => Text::Text,
+            _ if self.autocrlf != AutoCrlf::False => Text::Auto,
+            _ => Text::Binary,
+        }
+    }
+
+    fn is_text(&self, path: &str, data: &[u8]) -> bool {
+        match self.text(path) {
+            Text::Text => true,
+            Text::Auto => !diff::is_binary(data),
+            Text::Binary => false,
+        }
+    }
+
+    // Line endings of the files in the working dir
+    fn eol(&self, path: &str) -> Eol {
+        match self.attributes.get(path, "eol") {
+            Some(Value::Value(ref v)) if v == "crlf" => Eol::Crlf,
+            Some(Value::Value(ref v)) if v == "lf" => Eol::Lf,
+            _ => match self.autocrlf {
+                AutoCrlf::True => Eol::Crlf,
+                AutoCrlf::Input => Eol::Lf,
+                AutoCrlf::False => self.eol,
+            },
+        }
+    }
+
+    // Content of the blob of a file of the working dir
+    pub fn to_git(&self, path: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut data = self.apply_filter(path, "clean", data)?;
+        if let Some(Value::Value(encoding)) = self.attributes.get(path, "working-tree-encoding") {
+            data = decode(&data, &encoding)?;
+        }
+        if self.is_text(path, &data) && data.contains(&b'\r') {
+            data = crlf_to_lf(&data);
+        }
+        if self.attributes.is_set(path, "ident") {
+            data = collapse_ident(&data);
+        }
+        Ok(data)
+    }
+
+    // Content written in the working dir for a blob
+    pub fn to_work_tree(&self, path: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut data = data;
+        if self.attributes.is_set(path, "ident") {
+            let hash = hash_object::hash_object(&data, "blob", false)?;
+            data = expand_ident(&data, &hash);
+        }
+        if self.eol(path) == Eol::Crlf && self.is_text(path, &data) {
+            data = lf_to_crlf(&data);
+        }
+        if let Some(Value::Value(encoding)) = self.attributes.get(path, "working-tree-encoding") {
+            data = encode(&data, &encoding)?;
+        }
+        self.apply_filter(path, "smudge", data)
+    }
+
+    // Runs the "clean" or "smudge" command of the filter driver of a path.
+    // Failures are ignored unless filter.<driver>.required is set.
+    fn apply_filter(&self, path: &str, command: &str, data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let driver = match self.attributes.get(path, "filter") {
+            Some(Value::Value(driver)) => driver,
+            _ => return Ok(data),
+        };
+        let required = self.config.get_bool(&format!("filter.{}.required", driver)) == Some(true);
+
+        let res = match self.config.get_value(&format!("filter.{}.process", driver)) {
+            Some(process) => self.run_process(&driver, process, command, path, &data),
+            None => match self
+                .config
+                .get_value(&format!("filter.{}.{}", driver, command))
+            {
+                Some(cmd) => filter::run_command(cmd, path, &data).map(Some),
+                None => Ok(None),
+            },
+        };
+        match res {
+            Ok(Some(filtered)) => Ok(filtered),
+            Ok(None) if !required => Ok(data),
+            Err(_) if !required => Ok(data),
+            Ok(None) => Err(io::Error::other(format!(
+                "{}: {} filter {} failed",
+                path, command, driver
+            ))),
+            Err(e) => Err(e),
+        }
+    }
+
+    // The process of a driver is started the first time it is needed
+    fn run_process(
+        &self,
+        driver: &str,
+        command_line: &str,
+        command: &str,
+        path: &str,
+        data: &[u8],
+    ) -> io::Result<Option<Vec<u8>>> {
+        let mut processes = self.processes.borrow_mut();
+        if !processes.contains_key(driver) {
+            let process = filter::Process::start(command_line).ok();
+            processes.insert(driver.to_string(), process);
+        }
+        match processes.get_mut(driver).unwrap() {
+            Some(process) if process.can(command) => process.filter(command, path, data),
+            _ => Ok(None),
+        }
+    }
+}
+
+// "$Id: <anything>$" becomes "$Id$"
+fn collapse_ident(data: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i..].starts_with(b"$Id:") {
+            let end = data[i + 4..]
+                .iter()
+                .position(|&c| c == b'$' || c == b'\n')
+                .map(|e| i + 4 + e);
+            if let Some(end) = end.filter(|&e| data[e] == b'$') {
+                res.extend_from_slice(b"$Id$");
+                i = end + 1;
+                continue;
+            }
+        }
+        res.push(data[i]);
+        i += 1;
+    }
+    res
+}
+
+// "$Id$" becomes "$Id: <blob hash> $"
+fn expand_ident(data: &[u8], hash: &str) -> Vec<u8> {
+    let expanded = format!("$Id: {} $", hash);
+    let mut res = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i..].starts_with(b"$Id$") {
+            res.extend_from_slice(expanded.as_bytes());
+            i += 4;
+            continue;
+        }
+        res.push(data[i]);
+        i += 1;
+    }
+    res
+}
+
+fn unsupported_encoding(encoding: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("cannot convert from or to {}", encoding),
+    )
+}
+
+// Working dir content to UTF-8
+fn decode(data: &[u8], encoding: &str) -> io::Result<Vec<u8>> {
+    let encoding = encoding.to_uppercase();
+    let utf16 = |data: &[u8], little_endian: bool| -> io::Result<Vec<u8>> {
+        if !data.len().is_multiple_of(2) {
+            return Err(unsupported_encoding(&encoding));
+        }
+        let units: Vec<u16> = data
+            .chunks(2)
+            .map(|c| match little_endian {
+                true => u16::from_le_bytes([c[0], c[1]]),
+                false => u16::from_be_bytes([c[0], c[1]]),
+            })
+            .collect();
+        String::from_utf16(&units)
+            .map(|s| s.into_bytes())
+            .map_err(|_| unsupported_encoding(&encoding))
+    };
+
+    match encoding.as_str() {
+        "UTF-8" | "UTF8" => Ok(data.to_vec()),
+        "UTF-16LE" => utf16(data, true),
+        "UTF-16BE" => utf16(data, false),
+        // Big endian unless there is a byte order mark
+        "UTF-16" => match data {
+            [0xff, 0xfe, rest @ ..] => utf16(rest, true),
+            [0xfe, 0xff, rest @ ..] => utf16(rest, false),
+            _ => utf16(data, false),
+        },
+        "ISO-8859-1" | "LATIN1" => Ok(data
+            .iter()
+            .map(|&c| c as char)
+            .collect::<String>()
+            .into_bytes()),
+        _ => Err(unsupported_encoding(&encoding)),
+    }
+}
+
+// UTF-8 content to the working dir encoding
+fn encode(data: &[u8], encoding: &str) -> io::Result<Vec<u8>> {
+    let encoding = encoding.to_uppercase();
+    let text = String::from_utf8(data.to_vec()).map_err(|_| unsupported_encoding(&encoding))?;
+    let utf16 = |little_endian: bool| -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|u| match little_endian {
+                true => u.to_le_bytes(),
+                false => u.to_be_bytes(),
+            })
+            .collect()
+    };
+
+    match encoding.as_str() {
+        "UTF-8" | "UTF8" => Ok(data.to_vec()),
+        "UTF-16LE" => Ok(utf16(true)),
+        "UTF-16BE" => Ok(utf16(false)),
+        // With a little endian byte order mark
+        "UTF-16" => Ok([&[0xff, 0xfe][..], &utf16(true)].concat()),
+        "ISO-8859-1" | "LATIN1" => text
+            .chars()
+            .map(|c| match c as u32 {
+                n if n < 256 => Ok(n as u8),
+                _ => Err(unsupported_encoding(&encoding)),
+            })
+            .collect(),
+        _ => Err(unsupported_encoding(&encoding)),
+    }
+}
+
+fn crlf_to_lf(data: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(data.len());
+    for (i, &c) in data.iter().enumerate() {
+        if c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        res.push(c);
+    }
+    res
+}
+
+// LFs already preceded by a CR are left as is
+fn lf_to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(data.len());
+    for (i, &c) in data.iter().enumerate() {
+        if c == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            res.push(b'\r');
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use attributes::Attributes;
+    use builtin::config::Config;
+    use convert::{
+        collapse_ident, crlf_to_lf, decode, encode, expand_ident, lf_to_crlf, AutoCrlf, Converter,
+        Eol,
+    };
+
+    fn converter(attributes: &str, autocrlf: AutoCrlf) -> Converter {
+        Converter {
+            attributes: Attributes::from_data(attributes),
+            config: Config {
+                name: String::new(),
+                email: String::new(),
+                remotes: Vec::new(),
+                values: Vec::new(),
+            },
+            autocrlf,
+            eol: Eol::Lf,
+            processes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(crlf_to_lf(b"a\r\nb\rc\r\n"), b"a\nb\rc\n");
+        assert_eq!(lf_to_crlf(b"a\nb\r\nc"), b"a\r\nb\r\nc");
+    }
+
+    #[test]
+    fn attributes_and_autocrlf() {
+        let conv = converter("*.txt text\n*.bat eol=crlf\n*.bin -text\n", AutoCrlf::False);
+        assert_eq!(conv.to_git("a.txt", b"a\r\n".to_vec()).unwrap(), b"a\n");
+        assert_eq!(conv.to_work_tree("a.txt", b"a\n".to_vec()).unwrap(), b"a\n");
+        assert_eq!(
+            conv.to_work_tree("a.bat", b"a\n".to_vec()).unwrap(),
+            b"a\r\n"
+        );
+        assert_eq!(conv.to_git("a.bin", b"a\r\n".to_vec()).unwrap(), b"a\r\n");
+        assert_eq!(conv.to_git("a.c", b"a\r\n".to_vec()).unwrap(), b"a\r\n");
+
+        let conv = converter("*.bin -text\n", AutoCrlf::True);
+        assert_eq!(conv.to_git("a.c", b"a\r\n".to_vec()).unwrap(), b"a\n");
+        assert_eq!(conv.to_work_tree("a.c", b"a\n".to_vec()).unwrap(), b"a\r\n");
+        assert_eq!(conv.to_work_tree("a.bin", b"a\n".to_vec()).unwrap(), b"a\n");
+        // Binary content is left as is with text=auto
+        assert_eq!(conv.to_git("a.png", b"\0\r\n".to_vec()).unwrap(), b"\0\r\n");
+
+        let conv = converter("", AutoCrlf::Input);
+        assert_eq!(conv.to_git("a.c", b"a\r\n".to_vec()).unwrap(), b"a\n");
+        assert_eq!(conv.to_work_tree("a.c", b"a\n".to_vec()).unwrap(), b"a\n");
+    }
+
+    #[test]
+    fn ident() {
+        let hash = "a".repeat(40);
+        let expanded = expand_ident(b"// $Id$\n$Id", &hash);
+        assert_eq!(expanded, format!("// $Id: {} $\n$Id", hash).as_bytes());
+        assert_eq!(collapse_ident(&expanded), b"// $Id$\n$Id");
+        assert_eq!(collapse_ident(b"$Id: a\nb$"), b"$Id: a\nb$");
+    }
+
+    #[test]
+    fn encodings() {
+        let utf16 = encode("h\u{e9}".as_bytes(), "UTF-16").unwrap();
+        assert_eq!(utf16, [0xff, 0xfe, b'h', 0, 0xe9, 0]);
+        assert_eq!(decode(&utf16, "utf-16").unwrap(), "h\u{e9}".as_bytes());
+        assert_eq!(decode(&[0, b'h'], "UTF-16BE").unwrap(), b"h");
+        assert_eq!(
+            encode("h\u{e9}".as_bytes(), "latin1").unwrap(),
+            [b'h', 0xe9]
+        );
+        assert!(encode(b"a", "EBCDIC").is_err());
+    }
+}
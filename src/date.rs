@@ -0,0 +1,292 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Default,
+    Iso,
+    IsoStrict,
+    Raw,
+    Relative,
+    Rfc,
+    Short,
+    Unix,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "default" => Some(Mode::Default),
+            "iso" | "iso8601" => Some(Mode::Iso),
+            "iso-strict" | "iso8601-strict" => Some(Mode::IsoStrict),
+            "raw" => Some(Mode::Raw),
+            "relative" => Some(Mode::Relative),
+            "rfc" | "rfc2822" => Some(Mode::Rfc),
+            "short" => Some(Mode::Short),
+            "unix" => Some(Mode::Unix),
+            _ => None,
+        }
+    }
+}
+
+struct Civil {
+    year: i64,
+    month: usize,
+    day: i64,
+    hour: i64,
+    min: i64,
+    sec: i64,
+    week_day: usize,
+}
+
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
+
+// "+0200" -> 7200
+pub fn tz_offset(tz: &str) -> i64 {
+    if tz.len() != 5 {
+        return 0;
+    }
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let hours = tz[1..3].parse::<i64>().unwrap_or(0);
+    let mins = tz[3..5].parse::<i64>().unwrap_or(0);
+    sign * (hours * 3600 + mins * 60)
+}
+
+pub fn format(time: i64, tz: &str, mode: Mode) -> String {
+    let c = to_civil(time + tz_offset(tz));
+    let month = MONTHS[c.month - 1];
+    let day = DAYS[c.week_day];
+    match mode {
+        Mode::Default => format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            day, month, c.day, c.hour, c.min, c.sec, c.year, tz
+        ),
+        Mode::Iso => format!(
+            "{}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+            c.year, c.month, c.day, c.hour, c.min, c.sec, tz
+        ),
+        Mode::IsoStrict => {
+            let zone = match tz == "+0000" {
+                true => "Z".to_string(),
+                false => format!("{}:{}", &tz[..3], &tz[3..]),
+            };
+            format!(
+                "{}-{:02}-{:02}T{:02}:{:02}:{:02}{}",
+                c.year, c.month, c.day, c.hour, c.min, c.sec, zone
+            )
+        }
+        Mode::Raw => format!("{} {}", time, tz),
+        Mode::Relative => relative(now() - time),
+        Mode::Rfc => format!(
+            "{}, {} {} {} {:02}:{:02}:{:02} {}",
+            day, c.day, month, c.year, c.hour, c.min, c.sec, tz
+        ),
+        Mode::Short => format!("{}-{:02}-{:02}", c.year, c.month, c.day),
+        Mode::Unix => time.to_string(),
+    }
+}
+
+fn relative(diff: i64) -> String {
+    if diff < 0 {
+        return "in the future".to_string();
+    }
+
+    let plural = |n: i64, unit: &str| match n {
+        1 => format!("1 {} ago", unit),
+        n => format!("{} {}s ago", n, unit),
+    };
+    if diff < 90 {
+        return plural(diff, "second");
+    }
+    let diff = (diff + 30) / 60;
+    if diff < 90 {
+        return plural(diff, "minute");
+    }
+    let diff = (diff + 30) / 60;
+    if diff < 36 {
+        return plural(diff, "hour");
+    }
+    let diff = (diff + 12) / 24;
+    if diff < 14 {
+        return plural(diff, "day");
+    }
+    if diff < 70 {
+        return plural((diff + 3) / 7, "week");
+    }
+    if diff < 365 {
+        return plural((diff + 15) / 30, "month");
+    }
+    plural((diff + 183) / 365, "year")
+}
+
+// Absolute ("2018-07-14", "2018-07-14 12:30:00 +0200", "@1531571400") or
+// relative ("2 weeks ago", "3.days", "yesterday") dates
+pub fn parse(input: &str) -> Option<i64> {
+    let input = input.trim();
+    if let Some(secs) = input.strip_prefix('@') {
+        return secs.parse::<i64>().ok();
+    }
+    if let Ok(secs) = input.parse::<i64>() {
+        if input.len() > 8 {
+            return Some(secs);
+        }
+    }
+
+    match input {
+        "now" => return Some(now()),
+        "today" => return Some(now() - now().rem_euclid(86400)),
+        "yesterday" => return Some(now() - 86400),
+        _ => (),
+    }
+
+    parse_absolute(input).or_else(|| parse_relative(input))
+}
+
+fn parse_absolute(input: &str) -> Option<i64> {
+    let input = input.replacen('T', " ", 1);
+    let mut parts = input.split_whitespace();
+
+    let mut ymd = parts.next()?.split('-').map(|x| x.parse::<i64>());
+    let year = ymd.next()?.ok()?;
+    let month = ymd.next()?.ok()?;
+    let day = ymd.next()?.ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let mut secs = 0;
+    let mut tz = "+0000";
+    for part in parts {
+        if part.contains(':') {
+            let mut hms = part.split(':').map(|x| x.parse::<i64>());
+            let h = hms.next()?.ok()?;
+            let m = hms.next().unwrap_or(Ok(0)).ok()?;
+            let s = hms.next().unwrap_or(Ok(0)).ok()?;
+            secs = h * 3600 + m * 60 + s;
+        } else if part.len() == 5 && (part.starts_with('+') || part.starts_with('-')) {
+            tz = part;
+        } else {
+            return None;
+        }
+    }
+
+    Some(from_civil(year, month, day) * 86400 + secs - tz_offset(tz))
+}
+
+fn parse_relative(input: &str) -> Option<i64> {
+    let input = input.replace('.', " ");
+    let mut words = input.split_whitespace();
+    let count = words.next()?.parse::<i64>().ok()?;
+    let unit = words.next()?.trim_end_matches('s');
+    match words.next() {
+        None | Some("ago") => (),
+        _ => return None,
+    }
+
+    let unit_secs = match unit {
+        "second" | "sec" => 1,
+        "minute" | "min" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        "month" => 30 * 86400,
+        "year" => 365 * 86400,
+        _ => return None,
+    };
+    Some(now() - count * unit_secs)
+}
+
+// (year, month, day) to days since epoch
+fn from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// Days since epoch to (year, month, day), see
+// http://howardhinnant.github.io/date_algorithms.html
+fn to_civil(time: i64) -> Civil {
+    let days = time.div_euclid(86400);
+    let secs = time.rem_euclid(86400);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    Civil {
+        year,
+        month: month as usize,
+        day,
+        hour: secs / 3600,
+        min: (secs % 3600) / 60,
+        sec: secs % 60,
+        week_day: days.rem_euclid(7) as usize,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use date::{format, now, parse, tz_offset, Mode};
+
+    #[test]
+    fn offsets() {
+        assert_eq!(7200, tz_offset("+0200"));
+        assert_eq!(-25200, tz_offset("-0700"));
+        assert_eq!(0, tz_offset("+0000"));
+    }
+
+    #[test]
+    fn formats() {
+        let time = 1112911993;
+        let tz = "-0700";
+        assert_eq!(
+            "Thu Apr 7 15:13:13 2005 -0700",
+            format(time, tz, Mode::Default)
+        );
+        assert_eq!("2005-04-07 15:13:13 -0700", format(time, tz, Mode::Iso));
+        assert_eq!(
+            "2005-04-07T15:13:13-07:00",
+            format(time, tz, Mode::IsoStrict)
+        );
+        assert_eq!(
+            "Thu, 7 Apr 2005 15:13:13 -0700",
+            format(time, tz, Mode::Rfc)
+        );
+        assert_eq!("2005-04-07", format(time, tz, Mode::Short));
+        assert_eq!("1112911993 -0700", format(time, tz, Mode::Raw));
+        assert_eq!(
+            "Thu Jan 1 00:00:00 1970 +0000",
+            format(0, "+0000", Mode::Default)
+        );
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Some(1112911993), parse("2005-04-07 15:13:13 -0700"));
+        assert_eq!(Some(1112911993), parse("2005-04-07T22:13:13"));
+        assert_eq!(Some(1112832000), parse("2005-04-07"));
+        assert_eq!(Some(1112911993), parse("@1112911993"));
+        assert_eq!(Some(now() - 2 * 7 * 86400), parse("2.weeks.ago"));
+        assert_eq!(Some(now() - 3 * 86400), parse("3 days ago"));
+        assert_eq!(None, parse("someday"));
+    }
+}
@@ -0,0 +1,211 @@
+// External filter drivers: `filter.<name>.clean` and `.smudge` commands run
+// once per file, and `filter.<name>.process` commands kept running for the
+// whole command, talking the pkt-line based long-running filter protocol
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+
+// Largest content of a pkt-line
+const MAX_PACKET_DATA: usize = 65516;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Runs `sh -c <command>` with the content on stdin, "%f" being replaced by
+// the quoted path of the file
+pub fn run_command(command: &str, path: &str, data: &[u8]) -> io::Result<Vec<u8>> {
+    let quoted = format!("'{}'", path.replace('\'', "'\\''"));
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command.replace("%f", &quoted))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Written from another thread so that a filter writing its output before
+    // reading all of its input does not block
+    let mut stdin = child.stdin.take().unwrap();
+    let input = data.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    // A filter may not read its whole input
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(invalid_data(&format!("filter '{}' failed", command)));
+    }
+    Ok(output.stdout)
+}
+
+pub fn write_packet(out: &mut dyn Write, data: &[u8]) -> io::Result<()> {
+    out.write_all(format!("{:04x}", data.len() + 4).as_bytes())?;
+    out.write_all(data)
+}
+
+pub fn write_flush(out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(b"0000")
+}
+
+// None for a flush packet
+pub fn read_packet(input: &mut dyn Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = String::from_utf8_lossy(&len).to_string();
+    let len = usize::from_str_radix(&len, 16).map_err(|_| invalid_data("invalid pkt-line"))?;
+    if len == 0 {
+        return Ok(None);
+    }
+    if len < 4 {
+        return Err(invalid_data("invalid pkt-line"));
+    }
+
+    let mut data = vec![0u8; len - 4];
+    input.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+// Text packets up to the next flush, without their trailing newline
+fn read_lines(input: &mut dyn Read) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    while let Some(packet) = read_packet(input)? {
+        let line = String::from_utf8_lossy(&packet);
+        lines.push(line.trim_end_matches('\n').to_string());
+    }
+    Ok(lines)
+}
+
+fn write_lines(out: &mut dyn Write, lines: &[String]) -> io::Result<()> {
+    for line in lines {
+        write_packet(out, format!("{}\n", line).as_bytes())?;
+    }
+    write_flush(out)
+}
+
+// Last "status=<status>" of a list of lines
+fn status_of(lines: &[String]) -> Option<&str> {
+    lines.iter().rev().find_map(|l| l.strip_prefix("status="))
+}
+
+#[derive(Debug)]
+pub struct Process {
+    child: Child,
+    // Closed when the filter has to exit
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    // Commands supported by the filter ("clean", "smudge")
+    capabilities: Vec<String>,
+}
+
+impl Process {
+    // Starts the filter and does the version and capabilities handshake
+    pub fn start(command: &str) -> io::Result<Process> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+        let hello = vec!["git-filter-client".to_string(), "version=2".to_string()];
+        write_lines(&mut stdin, &hello)?;
+        let reply = read_lines(&mut stdout)?;
+        if reply.first().map(|l| l.as_str()) != Some("git-filter-server")
+            || !reply.iter().any(|l| l == "version=2")
+        {
+            return Err(invalid_data("unsupported filter protocol"));
+        }
+
+        let wanted = vec![
+            "capability=clean".to_string(),
+            "capability=smudge".to_string(),
+        ];
+        write_lines(&mut stdin, &wanted)?;
+        let capabilities = read_lines(&mut stdout)?
+            .iter()
+            .filter_map(|l| l.strip_prefix("capability=").map(|c| c.to_string()))
+            .collect();
+
+        Ok(Process {
+            child,
+            stdin: Some(stdin),
+            stdout,
+            capabilities,
+        })
+    }
+
+    pub fn can(&self, command: &str) -> bool {
+        self.capabilities.iter().any(|c| c == command)
+    }
+
+    // Sends "command=<command>" for a file, None when the filter does not
+    // want to change it
+    pub fn filter(
+        &mut self,
+        command: &str,
+        path: &str,
+        data: &[u8],
+    ) -> io::Result<Option<Vec<u8>>> {
+        let header = vec![format!("command={}", command), format!("pathname={}", path)];
+        let stdin = self.stdin.as_mut().unwrap();
+        write_lines(stdin, &header)?;
+        for chunk in data.chunks(MAX_PACKET_DATA) {
+            write_packet(stdin, chunk)?;
+        }
+        write_flush(stdin)?;
+        stdin.flush()?;
+
+        let status = read_lines(&mut self.stdout)?;
+        match status_of(&status) {
+            Some("success") => (),
+            Some("abort") => {
+                self.capabilities.retain(|c| c != command);
+                return Ok(None);
+            }
+            _ => return Err(invalid_data(&format!("filter failed on {}", path))),
+        }
+
+        let mut res = Vec::new();
+        while let Some(packet) = read_packet(&mut self.stdout)? {
+            res.extend_from_slice(&packet);
+        }
+        // The status can change after the content was sent, an empty list
+        // keeps the previous one
+        let status = read_lines(&mut self.stdout)?;
+        match status_of(&status) {
+            None | Some("success") => Ok(Some(res)),
+            _ => Err(invalid_data(&format!("filter failed on {}", path))),
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // Closing stdin tells the filter to exit
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use filter::{read_packet, write_flush, write_packet};
+
+    #[test]
+    fn pkt_lines() {
+        let mut out = Vec::new();
+        write_packet(&mut out, b"version=2\n").unwrap();
+        write_flush(&mut out).unwrap();
+        assert_eq!(out, b"000eversion=2\n0000");
+
+        let mut input = &out[..];
+        assert_eq!(
+            read_packet(&mut input).unwrap(),
+            Some(b"version=2\n".to_vec())
+        );
+        assert_eq!(read_packet(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,274 @@
+// gitignore: patterns from core.excludesFile, .git/info/exclude and the
+// .gitignore file of each directory, a later match taking precedence
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use builtin::config;
+use wildmatch;
+use wildmatch::Flags;
+
+#[derive(Debug)]
+pub struct Pattern {
+    // File and line the pattern comes from, for check-ignore -v
+    pub source: String,
+    pub line: usize,
+    // Pattern as written in the file
+    pub text: String,
+    // Directory of the .gitignore file, empty for the top one
+    base: String,
+    pattern: String,
+    // "!pattern" re-includes the paths excluded by previous patterns
+    pub negated: bool,
+    // "pattern/" only matches directories
+    dir_only: bool,
+    // Patterns with a '/' are relative to their directory, the other ones
+    // match the name of a file at any depth
+    anchored: bool,
+}
+
+#[derive(Debug)]
+pub struct Ignore {
+    // Lowest precedence first
+    patterns: Vec<Pattern>,
+    // Patterns given on the command line, which take precedence over files
+    command_line: Vec<Pattern>,
+    // Directories whose .gitignore was read
+    read_dirs: HashSet<String>,
+}
+
+impl Ignore {
+    // Ignores nothing
+    pub fn new() -> Ignore {
+        Ignore {
+            patterns: Vec::new(),
+            command_line: Vec::new(),
+            read_dirs: HashSet::new(),
+        }
+    }
+
+    // core.excludesFile and .git/info/exclude, the .gitignore files are read
+    // along the paths checked
+    pub fn standard() -> io::Result<Ignore> {
+        let mut ignore = Ignore::new();
+        if let Some(path) = excludes_file()? {
+            if path.is_file() {
+                let source = path.to_string_lossy().to_string();
+                ignore.add_file(&path, &source, "")?;
+            }
+        }
+
+        let exclude = Path::new(".git").join("info").join("exclude");
+        if exclude.is_file() {
+            ignore.add_file(&exclude, ".git/info/exclude", "")?;
+        }
+        Ok(ignore)
+    }
+
+    // --exclude=<pattern>
+    pub fn add_pattern(&mut self, pattern: &str) {
+        if let Some(pattern) = parse(pattern, "", "<command line>", 0) {
+            self.command_line.push(pattern);
+        }
+    }
+
+    fn add_file(&mut self, path: &Path, source: &str, base: &str) -> io::Result<()> {
+        let data = fs::read_to_string(path)?;
+        for (i, line) in data.lines().enumerate() {
+            if let Some(pattern) = parse(line, base, source, i + 1) {
+                self.patterns.push(pattern);
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the .gitignore of a directory ("" for the top one), once
+    pub fn read_dir(&mut self, dir: &str) -> io::Result<()> {
+        if !self.read_dirs.insert(dir.to_string()) {
+            return Ok(());
+        }
+        let path = Path::new(".").join(dir).join(".gitignore");
+        if path.is_file() {
+            let source = match dir.is_empty() {
+                true => ".gitignore".to_string(),
+                false => format!("{}/.gitignore", dir),
+            };
+            self.add_file(&path, &source, dir)?;
+        }
+        Ok(())
+    }
+
+    // Reads the .gitignore files of the directories leading to a path
+    pub fn read_parents(&mut self, path: &str) -> io::Result<()> {
+        self.read_dir("")?;
+        for (i, _) in path.match_indices('/') {
+            self.read_dir(&path[..i])?;
+        }
+        Ok(())
+    }
+
+    // Pattern deciding whether a path is ignored, which is either the last
+    // one matching it or the one excluding one of its parent directories
+    pub fn find(&self, path: &str, is_dir: bool) -> Option<&Pattern> {
+        for (i, _) in path.match_indices('/') {
+            match self.last_match(&path[..i], true) {
+                Some(p) if !p.negated => return Some(p),
+                _ => (),
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        match self.find(path, is_dir) {
+            Some(p) => !p.negated,
+            None => false,
+        }
+    }
+
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<&Pattern> {
+        let in_command_line = self
+            .command_line
+            .iter()
+            .rev()
+            .find(|p| matches(p, path, is_dir));
+        in_command_line.or_else(|| {
+            self.patterns
+                .iter()
+                .rev()
+                .find(|p| matches(p, path, is_dir))
+        })
+    }
+}
+
+// core.excludesFile, $XDG_CONFIG_HOME/git/ignore by default
+fn excludes_file() -> io::Result<Option<PathBuf>> {
+    let config = config::Config::new()?;
+    let home = env::var("HOME").ok();
+    if let Some(path) = config.get_value("core.excludesFile") {
+        return Ok(match (path.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => Some(Path::new(&home).join(rest)),
+            _ => Some(PathBuf::from(path)),
+        });
+    }
+
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match home {
+            Some(home) => Path::new(&home).join(".config"),
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(config_home.join("git").join("ignore")))
+}
+
+fn parse(line: &str, base: &str, source: &str, line_nb: usize) -> Option<Pattern> {
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    // Trailing spaces are ignored unless escaped
+    let mut pattern = line.trim_end_matches(' ').to_string();
+    if pattern.ends_with('\\') && line.len() > pattern.len() {
+        pattern.push(' ');
+    }
+    // "\!" and "\#" start patterns with a literal '!' or '#'
+    let negated = pattern.starts_with('!');
+    if negated || pattern.starts_with("\\!") || pattern.starts_with("\\#") {
+        pattern.remove(0);
+    }
+
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    if pattern.is_empty() {
+        return None;
+    }
+    let anchored = pattern.contains('/');
+
+    Some(Pattern {
+        source: source.to_string(),
+        line: line_nb,
+        text: line.to_string(),
+        base: base.to_string(),
+        pattern: pattern.trim_start_matches('/').to_string(),
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+fn matches(pattern: &Pattern, path: &str, is_dir: bool) -> bool {
+    if pattern.dir_only && !is_dir {
+        return false;
+    }
+    let path = match pattern.base.is_empty() {
+        true => path,
+        false => match path.strip_prefix(&pattern.base) {
+            Some(rest) if rest.starts_with('/') => &rest[1..],
+            _ => return false,
+        },
+    };
+
+    let flags = Flags {
+        pathname: true,
+        casefold: false,
+    };
+    match pattern.anchored {
+        true => wildmatch::wildmatch(&pattern.pattern, path, flags),
+        false => {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch::wildmatch(&pattern.pattern, name, flags)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ignore::{parse, Ignore};
+
+    fn ignore(data: &str, base: &str) -> Ignore {
+        let mut ignore = Ignore::new();
+        for line in data.lines() {
+            ignore.patterns.extend(parse(line, base, ".gitignore", 0));
+        }
+        ignore
+    }
+
+    #[test]
+    fn patterns() {
+        let ignore = ignore(
+            "# build\n*.o\n!keep.o\ntarget/\n/root.txt\ndoc/**/*.pdf\n\\#hash\n",
+            "",
+        );
+        assert!(ignore.is_ignored("a.o", false));
+        assert!(ignore.is_ignored("src/b.o", false));
+        assert!(!ignore.is_ignored("src/keep.o", false));
+        assert!(ignore.is_ignored("target", true));
+        assert!(!ignore.is_ignored("target", false));
+        assert!(ignore.is_ignored("sub/target/debug/app", false));
+        assert!(ignore.is_ignored("root.txt", false));
+        assert!(!ignore.is_ignored("sub/root.txt", false));
+        assert!(ignore.is_ignored("doc/a/b/c.pdf", false));
+        assert!(ignore.is_ignored("doc/c.pdf", false));
+        assert!(ignore.is_ignored("#hash", false));
+    }
+
+    #[test]
+    fn excluded_parent_and_nested_files() {
+        let mut ignore = ignore("build/\n!build/keep.txt\n", "");
+        // A file cannot be re-included when its directory is excluded
+        assert!(ignore.is_ignored("build/keep.txt", false));
+
+        ignore
+            .patterns
+            .extend(parse("*.log", "sub", "sub/.gitignore", 1));
+        ignore
+            .patterns
+            .extend(parse("!debug.log", "sub/inner", "", 1));
+        assert!(ignore.is_ignored("sub/a.log", false));
+        assert!(!ignore.is_ignored("sub/inner/debug.log", false));
+        assert!(!ignore.is_ignored("a.log", false));
+    }
+}
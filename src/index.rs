@@ -3,9 +3,11 @@ use std::io;
 use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bits::big_endian;
 use builtin::hash_object;
+use convert::Converter;
 use sha1;
 
 #[derive(Debug)]
@@ -24,6 +26,20 @@ impl From<io::Error> for Error {
     }
 }
 
+// Canonical modes of the entries of the index and of trees
+pub const MODE_FILE: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+pub const MODE_GITLINK: u32 = 0o160000;
+pub const MODE_TREE: u32 = 0o040000;
+
+// Flags of an entry: the extended ones follow it (index version 3)
+const FLAG_EXTENDED: u16 = 0x4000;
+pub const FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+// Blob recorded for a file which is going to be added
+const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
 #[derive(Debug, Clone)]
 pub struct Entry {
     pub ctime_sec: u32,
@@ -38,35 +54,188 @@ pub struct Entry {
     pub size: u32,
     pub hash: String,
     pub flags: u16,
+    pub extended_flags: u16,
     pub path: String,
 }
 
 impl Entry {
-    pub fn new(path: &str) -> Result<Entry, Error> {
-        let data = fs::read(&path)?;
-        let meta = fs::metadata(&path)?;
+    // `known_mode` is the mode the file had so far, whose executable bit is
+    // kept when core.fileMode is false
+    pub fn new(path: &str, known_mode: Option<u32>, converter: &Converter) -> Result<Entry, Error> {
+        let meta = fs::symlink_metadata(&path)?;
+        let mode = mode_of(&meta, known_mode, converter.trust_executable_bit());
 
-        let write = true;
-        let hash = hash_object::hash_object(&data, "blob", write)?;
+        // A submodule is recorded by the commit checked out in it
+        let hash = match mode {
+            MODE_GITLINK => gitlink_hash(path)?,
+            _ => {
+                let data = read_blob_data(path, mode, converter)?;
+                let write = true;
+                hash_object::hash_object(&data, "blob", write)?
+            }
+        };
+        Ok(Entry::from_stat(path, &meta, mode, &hash))
+    }
 
-        Ok(Entry {
+    // Entry of a file whose blob is known, like after a checkout
+    pub fn with_hash(path: &str, mode: u32, hash: &str) -> Result<Entry, Error> {
+        let meta = fs::symlink_metadata(&path)?;
+        Ok(Entry::from_stat(path, &meta, mode, hash))
+    }
+
+    // Entry whose file has to be compared by content
+    pub fn without_stat(path: &str, mode: u32, hash: &str) -> Entry {
+        Entry {
+            ctime_sec: 0,
+            ctime_nan: 0,
+            mtime_sec: 0,
+            mtime_nan: 0,
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash: hash.to_string(),
+            flags: path.len() as u16,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    // Entry of an untracked file (git add -N), which is not committed but
+    // shows up in the diffs of the working dir
+    pub fn intent_to_add(path: &str, mode: u32) -> Entry {
+        let mut entry = Entry::without_stat(path, mode, EMPTY_BLOB);
+        entry.extended_flags = FLAG_INTENT_TO_ADD;
+        entry
+    }
+
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags & FLAG_INTENT_TO_ADD != 0
+    }
+
+    // Same entry, stat included, for the file moved to another path
+    pub fn with_path(&self, path: &str) -> Entry {
+        let mut entry = self.clone();
+        entry.flags = (self.flags & 0xf000) | path.len() as u16;
+        entry.path = path.to_string();
+        entry
+    }
+
+    fn from_stat(path: &str, meta: &fs::Metadata, mode: u32, hash: &str) -> Entry {
+        Entry {
             ctime_sec: meta.ctime() as u32,
             ctime_nan: meta.ctime_nsec() as u32,
             mtime_sec: meta.mtime() as u32,
             mtime_nan: meta.mtime_nsec() as u32,
             dev: meta.dev() as u32,
             ino: meta.ino() as u32,
-            mode: meta.mode(),
+            mode,
             uid: meta.uid(),
             gid: meta.gid(),
             size: meta.size() as u32,
-            hash: hash,
+            hash: hash.to_string(),
             flags: path.len() as u16,
+            extended_flags: 0,
             path: path.to_string(),
-        })
+        }
+    }
+
+    // Whether the file looks unchanged since the entry was written, without
+    // reading it. Files modified in the same second the index was written
+    // ("racy" entries) have to be compared by content.
+    pub fn is_up_to_date(&self, meta: &fs::Metadata, index_mtime: Option<u32>) -> bool {
+        let racy = match index_mtime {
+            Some(index_mtime) => self.mtime_sec >= index_mtime,
+            None => true,
+        };
+        !racy
+            && self.ctime_sec == meta.ctime() as u32
+            && self.ctime_nan == meta.ctime_nsec() as u32
+            && self.mtime_sec == meta.mtime() as u32
+            && self.mtime_nan == meta.mtime_nsec() as u32
+            && self.ino == meta.ino() as u32
+            // A change of the executable bit changes the ctime
+            && file_type(self.mode) == file_type(mode_of(meta, None, true))
+            && self.uid == meta.uid()
+            && self.gid == meta.gid()
+            && self.size == meta.size() as u32
+    }
+
+    // Used when the content of the file is known to match the entry
+    pub fn refresh_stat(&mut self, meta: &fs::Metadata) {
+        self.ctime_sec = meta.ctime() as u32;
+        self.ctime_nan = meta.ctime_nsec() as u32;
+        self.mtime_sec = meta.mtime() as u32;
+        self.mtime_nan = meta.mtime_nsec() as u32;
+        self.dev = meta.dev() as u32;
+        self.ino = meta.ino() as u32;
+        self.uid = meta.uid();
+        self.gid = meta.gid();
+        self.size = meta.size() as u32;
     }
 }
 
+fn file_type(mode: u32) -> u32 {
+    mode & 0o170000
+}
+
+// Mode recorded for a file of the working dir, from its symlink_metadata.
+// When the executable bit cannot be trusted, the one of `known_mode` is used.
+pub fn mode_of(meta: &fs::Metadata, known_mode: Option<u32>, trust_executable_bit: bool) -> u32 {
+    let file_type = meta.file_type();
+    if file_type.is_symlink() {
+        return MODE_SYMLINK;
+    }
+    if file_type.is_dir() {
+        return MODE_GITLINK;
+    }
+    match (trust_executable_bit, known_mode) {
+        (false, Some(MODE_EXECUTABLE)) => MODE_EXECUTABLE,
+        (false, _) => MODE_FILE,
+        (true, _) if meta.mode() & 0o111 != 0 => MODE_EXECUTABLE,
+        (true, _) => MODE_FILE,
+    }
+}
+
+// Content of the blob of a file of the working dir, which is the target of
+// a symbolic link
+pub fn read_blob_data(path: &str, mode: u32, converter: &Converter) -> io::Result<Vec<u8>> {
+    match mode {
+        MODE_SYMLINK => Ok(fs::read_link(path)?
+            .to_string_lossy()
+            .to_string()
+            .into_bytes()),
+        _ => converter.to_git(path, fs::read(path)?),
+    }
+}
+
+// Commit checked out in a submodule
+pub fn gitlink_hash(path: &str) -> io::Result<String> {
+    let git_dir = Path::new(path).join(".git");
+    let head = fs::read_to_string(git_dir.join("HEAD"))?;
+    let head = head.trim();
+    match head.strip_prefix("ref: ") {
+        Some(name) => Ok(fs::read_to_string(git_dir.join(name))?.trim().to_string()),
+        None => Ok(head.to_string()),
+    }
+}
+
+// Size of an entry without its path, the extended flags taking 2 more bytes
+fn entry_size(flags: u16) -> usize {
+    match flags & FLAG_EXTENDED {
+        0 => 62,
+        _ => 64,
+    }
+}
+
+// Last modification of the index file, in seconds
+pub fn modified_time() -> Option<u32> {
+    let meta = fs::metadata(Path::new(".git").join("index")).ok()?;
+    Some(meta.mtime() as u32)
+}
+
 pub fn read_entries() -> Result<Vec<Entry>, Error> {
     let mut entries = Vec::new();
 
@@ -81,7 +250,7 @@ pub fn read_entries() -> Result<Vec<Entry>, Error> {
         return Err(Error::InvalidHeaderSignature);
     }
     let version = big_endian::u8_slice_to_u32(&bytes[4..]);
-    if version != 2 {
+    if version != 2 && version != 3 {
         return Err(Error::InvalidIndexVersion);
     }
 
@@ -99,6 +268,11 @@ pub fn read_entries() -> Result<Vec<Entry>, Error> {
 
         let flags = big_endian::u8_slice_to_u16(&bytes[idx..]);
         idx += 2;
+        let mut extended_flags = 0;
+        if flags & FLAG_EXTENDED != 0 {
+            extended_flags = big_endian::u8_slice_to_u16(&bytes[idx..]);
+            idx += 2;
+        }
 
         let null_idx = match bytes[idx..].iter().position(|&x| x == 0) {
             Some(i) => i,
@@ -109,7 +283,7 @@ pub fn read_entries() -> Result<Vec<Entry>, Error> {
             .to_string();
         idx += null_idx;
 
-        let entry_len = 62 + path.len();
+        let entry_len = entry_size(flags) + path.len();
         let padding_len = ((entry_len + 8) / 8) * 8 - entry_len;
         idx += padding_len;
 
@@ -125,7 +299,8 @@ pub fn read_entries() -> Result<Vec<Entry>, Error> {
             gid: fields[8],
             size: fields[9],
             hash: hash,
-            flags: flags,
+            flags: flags & !FLAG_EXTENDED,
+            extended_flags,
             path: path,
         });
     }
@@ -139,9 +314,72 @@ pub fn read_entries() -> Result<Vec<Entry>, Error> {
     Ok(entries)
 }
 
+// Updates the stat data of the entries whose file changed on disk without
+// its content being different, so that they are not read again next time
+pub fn refresh() -> Result<(), Error> {
+    let mut entries = read_entries()?;
+    let index_mtime = modified_time();
+    let mut refreshed = false;
+    let mut converter = None;
+    for entry in &mut entries {
+        let meta = match fs::symlink_metadata(&entry.path) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if entry.mode == MODE_GITLINK || entry.is_up_to_date(&meta, index_mtime) {
+            continue;
+        }
+        if converter.is_none() {
+            converter = Some(Converter::load()?);
+        }
+        let converter = converter.as_ref().unwrap();
+        let mode = mode_of(&meta, Some(entry.mode), converter.trust_executable_bit());
+        if mode != entry.mode {
+            continue;
+        }
+        let data = read_blob_data(&entry.path, mode, converter)?;
+        if hash_object::hash_object(&data, "blob", false)? == entry.hash {
+            entry.refresh_stat(&meta);
+            refreshed = true;
+        }
+    }
+
+    if refreshed {
+        write_entries(entries)?;
+    }
+    Ok(())
+}
+
+// Sets the blob of some entries from their content and mode, removing the
+// ones without any. Files which have this content keep their stat data.
+pub fn write_contents(files: Vec<(String, Option<(Vec<u8>, u32)>)>) -> Result<(), Error> {
+    let mut entries = read_entries()?;
+    for (path, file) in files {
+        entries.retain(|e| e.path != path);
+        if let Some((data, mode)) = file {
+            let hash = hash_object::hash_object(&data, "blob", true)?;
+            entries.push(Entry::without_stat(&path, mode, &hash));
+        }
+    }
+    write_entries(entries)?;
+    refresh()
+}
+
 pub fn write_entries(mut entries: Vec<Entry>) -> Result<(), Error> {
     entries.sort_by(|a, b| a.path.cmp(&b.path));
 
+    // Entries modified in the same second as the index cannot be trusted
+    // later on, their size is reset so that their content gets compared
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0);
+    for entry in &mut entries {
+        if entry.mtime_sec >= now {
+            entry.size = 0;
+        }
+    }
+
     let mut compressed_entries = Vec::new();
     for entry in &entries {
         let fields = vec![
@@ -167,10 +405,17 @@ pub fn write_entries(mut entries: Vec<Entry>) -> Result<(), Error> {
             None => return Err(Error::InvalidHash),
         };
         bytes_entry.extend(&compressed_hash);
-        bytes_entry.extend(&big_endian::u16_to_u8(entry.flags));
+        let mut flags = entry.flags;
+        if entry.extended_flags != 0 {
+            flags |= FLAG_EXTENDED;
+        }
+        bytes_entry.extend(&big_endian::u16_to_u8(flags));
+        if entry.extended_flags != 0 {
+            bytes_entry.extend(&big_endian::u16_to_u8(entry.extended_flags));
+        }
         bytes_entry.extend(entry.path.as_bytes());
 
-        let entry_len = 62 + entry.path.len();
+        let entry_len = entry_size(flags) + entry.path.len();
         let padding_len = ((entry_len + 8) / 8) * 8 - entry_len;
         let padding = vec![0u8; padding_len];
         bytes_entry.extend(&padding);
@@ -178,8 +423,12 @@ pub fn write_entries(mut entries: Vec<Entry>) -> Result<(), Error> {
         compressed_entries.extend(&bytes_entry);
     }
 
-    // DIRC2
-    let mut header = vec![68, 73, 82, 67, 0, 0, 0, 2];
+    // DIRC2, or DIRC3 when extended flags are used
+    let version = match entries.iter().any(|e| e.extended_flags != 0) {
+        true => 3,
+        false => 2,
+    };
+    let mut header = vec![68, 73, 82, 67, 0, 0, 0, version];
     header.extend(&big_endian::u32_to_u8(entries.len() as u32));
 
     let mut data = Vec::new();
@@ -1,11 +1,23 @@
+mod attributes;
 mod bits;
 mod builtin;
 mod cli;
+mod convert;
+mod date;
+mod filter;
+mod ignore;
 mod index;
 mod object;
+mod patch;
+mod pathspec;
 mod refs;
+mod regex;
+mod rename;
+mod revision;
 mod sha1;
+mod wildmatch;
 mod work_dir;
+mod xdiff;
 mod zlib;
 
 use std::env;
@@ -18,6 +30,11 @@ fn main() {
         return;
     }
 
+    // Other commands use "-n" for --dry-run
+    let args = match args[1].as_str() {
+        "log" => cli::join_flag_values(args, &["-n", "--max-count"]),
+        _ => args,
+    };
     let (args, flags) = cli::split_args_from_flags(args);
     let cmd = &args[1];
     let args = &args[2..];
@@ -31,16 +48,27 @@ fn main() {
         "hash-object" => builtin::hash_object::cmd_hash_object(&args, &flags),
         "cat-file" => builtin::cat_file::cmd_cat_file(&args, &flags),
         "ls-files" => builtin::ls_files::cmd_ls_files(&flags),
-        "status" => builtin::status::cmd_status(),
-        "diff" => builtin::diff::cmd_diff(&args),
-        "add" => builtin::add::cmd_add(&args),
+        "check-ignore" => builtin::check_ignore::cmd_check_ignore(&args, &flags),
+        "check-attr" => builtin::check_attr::cmd_check_attr(&args, &flags),
+        "status" => builtin::status::cmd_status(&args, &flags),
+        "diff" => builtin::diff::cmd_diff(&args, &flags),
+        "add" => builtin::add::cmd_add(&args, &flags),
+        "rm" => builtin::rm::cmd_rm(&args, &flags),
+        "mv" => builtin::mv::cmd_mv(&args, &flags),
+        "clean" => builtin::clean::cmd_clean(&args, &flags),
         "write-tree" => builtin::write_tree::cmd_write_tree(),
         "read-tree" => builtin::read_tree::cmd_read_tree(&args),
         "commit" => builtin::commit::cmd_commit(&args, &flags),
         "config" => builtin::config::cmd_config(&args, &flags),
-        "log" => builtin::log::cmd_log(),
+        "log" => builtin::log::cmd_log(&args, &flags),
+        "show" => builtin::show::cmd_show(&args, &flags),
         "branch" => builtin::branch::cmd_branch(&args, &flags),
-        "checkout" => builtin::checkout::cmd_checkout(&args),
+        "checkout" => builtin::checkout::cmd_checkout(&args, &flags),
+        "switch" => builtin::switch::cmd_switch(&args, &flags),
+        "restore" => builtin::restore::cmd_restore(&args, &flags),
+        "reset" => builtin::reset::cmd_reset(&args, &flags),
+        "stash" => builtin::stash::cmd_stash(&args, &flags),
+        "tag" => builtin::tag::cmd_tag(&args, &flags),
         "merge" => builtin::merge::cmd_merge(&args),
         "remote" => builtin::remote::cmd_remote(&args),
         "push" => builtin::push::cmd_push(&args),
@@ -57,14 +85,23 @@ fn print_help() {
     println!("\tinit: create empty git repository");
     println!("\tconfig: get and set repo options");
     println!("\tadd: add content to the index");
+    println!("\trm: remove files from the working dir and the index");
+    println!("\tmv: move or rename files and directories");
+    println!("\tclean: remove untracked files from the working dir");
     println!("\tcommit: record changes to the repo");
     println!("\tstatus: show the working dir status");
-    println!("\tdiff: show changes between index and working dir");
+    println!("\tdiff: show changes between commits, index and working dir");
     println!("\tlog: show commit logs");
+    println!("\tshow: show commits, trees and blobs");
+    println!("\trestore: restore files of the index or working dir");
+    println!("\treset: reset HEAD, the index or the working dir");
+    println!("\tstash: put local changes aside and bring them back");
     println!("* branches:");
     println!("\tbranch: list or create branches");
-    println!("\tcheckout: switch branches");
+    println!("\tcheckout: switch branches or restore files");
+    println!("\tswitch: switch branches");
     println!("\tmerge: merge two branches together");
+    println!("\ttag: list, create or delete tags");
     println!("* remotes:");
     println!("\tclone: clone a git repo into a new dir");
     println!("\tfetch: retrieve refs and objects from remote");
@@ -75,6 +112,8 @@ fn print_help() {
     println!("\thash-object: compute object hash and create storage blob");
     println!("\tcat-file: show content, type, or size of stored objects");
     println!("\tls-files: show files in the index");
+    println!("\tcheck-ignore: debug gitignore files");
+    println!("\tcheck-attr: show gitattributes of paths");
     println!("\tread-tree: read tree info from object");
     println!("\twrite-tree: create tree object from index");
 }
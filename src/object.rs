@@ -6,10 +6,12 @@ use std::str;
 use bits::big_endian;
 use builtin::commit;
 use builtin::read_tree;
+use index;
 use zlib;
 
 #[derive(Debug)]
 pub enum Error {
+    AmbiguousHashPrefix,
     HashPrefixTooShort,
     HeaderMissingNullByte,
     HeaderMissingSize,
@@ -64,25 +66,55 @@ impl Object {
     }
 
     fn full_path(hash_prefix: &str) -> Result<PathBuf, Error> {
-        if hash_prefix.len() < 2 {
-            return Err(Error::HashPrefixTooShort);
-        }
+        let hash = expand_hash(hash_prefix)?;
+        Ok(Path::new(".git")
+            .join("objects")
+            .join(&hash[..2])
+            .join(&hash[2..]))
+    }
+}
 
-        let (dir, file) = hash_prefix.split_at(2);
-        let objects = Path::new(".git").join("objects").join(dir);
-        for f in fs::read_dir(objects)? {
-            let path = f?.path();
-            if let Some(f) = path.file_name() {
-                if f == file {
-                    return Ok(path.clone());
-                }
+pub fn expand_hash(hash_prefix: &str) -> Result<String, Error> {
+    if hash_prefix.len() < 2 {
+        return Err(Error::HashPrefixTooShort);
+    }
+
+    let (dir, file) = hash_prefix.split_at(2);
+    let objects = Path::new(".git").join("objects").join(dir);
+    if !objects.exists() {
+        return Err(Error::ObjectNotFound);
+    }
+
+    let mut found = None;
+    for f in fs::read_dir(objects)? {
+        let path = f?.path();
+        let name = match path.file_name().and_then(|f| f.to_str()) {
+            Some(f) => f.to_string(),
+            None => continue,
+        };
+        if name.starts_with(file) {
+            if found.is_some() {
+                return Err(Error::AmbiguousHashPrefix);
             }
+            found = Some(format!("{}{}", dir, name));
         }
+    }
 
-        Err(Error::ObjectNotFound)
+    match found {
+        Some(hash) => Ok(hash),
+        None => Err(Error::ObjectNotFound),
     }
 }
 
+pub fn exists(hash: &str) -> bool {
+    hash.len() == 40
+        && Path::new(".git")
+            .join("objects")
+            .join(&hash[..2])
+            .join(&hash[2..])
+            .exists()
+}
+
 pub fn find_objects_from_commit(commit: &str) -> Vec<String> {
     let mut objects = Vec::new();
     objects.push(commit.to_string());
@@ -106,10 +138,11 @@ pub fn find_objects_from_tree(tree: &str) -> Vec<String> {
 
     if let Ok(entries) = read_tree::read_tree(&tree) {
         for entry in entries {
-            if Path::new(&entry.path).is_dir() {
-                objects.extend(find_objects_from_tree(&entry.hash));
-            } else {
-                objects.push(entry.hash.to_string());
+            match entry.mode {
+                index::MODE_TREE => objects.extend(find_objects_from_tree(&entry.hash)),
+                // Submodule commits are not in the repository
+                index::MODE_GITLINK => (),
+                _ => objects.push(entry.hash.to_string()),
             }
         }
     }
@@ -0,0 +1,552 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use builtin::config;
+use builtin::diff;
+use builtin::diff::FilePair;
+use xdiff::{Algorithm, Edit};
+
+// Changes between two versions of a file, split into hunks which can be
+// picked one by one. Picked hunks are applied to the old version, or
+// reverted from the new one when `reverse`.
+pub struct Patch {
+    script: Vec<(Edit, Vec<u8>)>,
+    // Range of the script shown in each hunk, split hunks sharing their
+    // context lines
+    hunks: Vec<(usize, usize)>,
+    reverse: bool,
+}
+
+impl Patch {
+    pub fn new(old: &[u8], new: &[u8], algorithm: Algorithm, reverse: bool) -> Patch {
+        let script = diff::line_script(old, new, algorithm);
+        let hunks = diff::hunk_bounds(&script, 3);
+        Patch {
+            script: script.into_iter().map(|(e, l)| (e, l.to_vec())).collect(),
+            hunks,
+            reverse,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hunks.len()
+    }
+
+    pub fn format_hunk(&self, i: usize) -> Vec<u8> {
+        let script: Vec<(Edit, &[u8])> = self.script.iter().map(|(e, l)| (*e, &l[..])).collect();
+        let (start, end) = self.hunks[i];
+        diff::format_hunk(&script, start, end)
+    }
+
+    // Runs of changed lines of a hunk
+    fn changes(&self, i: usize) -> Vec<(usize, usize)> {
+        let (start, end) = self.hunks[i];
+        let mut changes: Vec<(usize, usize)> = Vec::new();
+        for k in start..end {
+            if self.script[k].0 == Edit::Equal {
+                continue;
+            }
+            match changes.last_mut() {
+                Some(last) if last.1 == k => last.1 = k + 1,
+                _ => changes.push((k, k + 1)),
+            }
+        }
+        changes
+    }
+
+    pub fn can_split(&self, i: usize) -> bool {
+        self.changes(i).len() > 1
+    }
+
+    // Splits a hunk at the unchanged lines between its changes, each part
+    // keeping the context lines around it. Returns the number of parts.
+    pub fn split(&mut self, i: usize) -> usize {
+        let changes = self.changes(i);
+        if changes.len() < 2 {
+            return 1;
+        }
+        let (start, end) = self.hunks[i];
+        let parts: Vec<(usize, usize)> = (0..changes.len())
+            .map(|j| {
+                let part_start = if j == 0 { start } else { changes[j - 1].1 };
+                let part_end = match changes.get(j + 1) {
+                    Some(next) => next.0,
+                    None => end,
+                };
+                (part_start, part_end)
+            })
+            .collect();
+        let nb_parts = parts.len();
+        self.hunks.splice(i..i + 1, parts);
+        nb_parts
+    }
+
+    // Replaces a hunk by an edited version of it, which is refused (false)
+    // when the side the hunk is applied to is not the same anymore
+    pub fn edit(&mut self, i: usize, text: &[u8]) -> bool {
+        let (start, end) = self.hunks[i];
+        let lines = match parse_hunk(text) {
+            Some(lines) => lines,
+            None => return false,
+        };
+        let kept = match self.reverse {
+            true => Edit::Delete,
+            false => Edit::Insert,
+        };
+        let side = |script: &[(Edit, Vec<u8>)]| -> Vec<Vec<u8>> {
+            script
+                .iter()
+                .filter(|(e, _)| *e != kept)
+                .map(|(_, l)| l.to_vec())
+                .collect()
+        };
+        if side(&lines) != side(&self.script[start..end]) {
+            return false;
+        }
+
+        let new_end = start + lines.len();
+        let delta = new_end as isize - end as isize;
+        let shift = |k: usize| (k as isize + delta) as usize;
+        self.script.splice(start..end, lines);
+        // Split hunks sharing context lines with the edited one lose them
+        for (j, hunk) in self.hunks.iter_mut().enumerate() {
+            if j == i {
+                *hunk = (start, new_end);
+            } else if j > i {
+                hunk.0 = if hunk.0 >= end {
+                    shift(hunk.0)
+                } else {
+                    new_end
+                };
+                hunk.1 = shift(hunk.1);
+            } else if hunk.1 > start {
+                hunk.1 = start;
+            }
+        }
+        true
+    }
+
+    // Content with the selected hunks applied, or reverted
+    pub fn apply(&self, selected: &[bool]) -> Vec<u8> {
+        let mut picked = vec![false; self.script.len()];
+        for (&(start, end), _) in self.hunks.iter().zip(selected).filter(|(_, &s)| s) {
+            let lines = self.script[start..end].iter();
+            for (picked, (edit, _)) in picked[start..end].iter_mut().zip(lines) {
+                *picked = *edit != Edit::Equal;
+            }
+        }
+
+        let mut res = Vec::new();
+        for (k, (edit, line)) in self.script.iter().enumerate() {
+            // Side of the diff this line is taken from
+            let new_side = picked[k] != self.reverse;
+            let keep = match edit {
+                Edit::Equal => true,
+                Edit::Insert => new_side,
+                Edit::Delete => !new_side,
+            };
+            if keep {
+                res.extend_from_slice(line);
+            }
+        }
+        res
+    }
+}
+
+// Lines of an edited hunk, None when it cannot be parsed. Comments and hunk
+// headers are skipped.
+fn parse_hunk(text: &[u8]) -> Option<Vec<(Edit, Vec<u8>)>> {
+    let mut lines: Vec<(Edit, Vec<u8>)> = Vec::new();
+    for line in text.split_inclusive(|&c| c == b'\n') {
+        let edit = match line.first() {
+            Some(b'#') => continue,
+            Some(b'@') => continue,
+            Some(b'\\') => {
+                if let Some(last) = lines.last_mut() {
+                    if last.1.ends_with(b"\n") {
+                        last.1.pop();
+                    }
+                }
+                continue;
+            }
+            Some(b' ') => Edit::Equal,
+            Some(b'-') => Edit::Delete,
+            Some(b'+') => Edit::Insert,
+            // Editors may strip the space of empty context lines
+            Some(b'\n') => {
+                lines.push((Edit::Equal, b"\n".to_vec()));
+                continue;
+            }
+            _ => return None,
+        };
+        lines.push((edit, line[1..].to_vec()));
+    }
+    Some(lines)
+}
+
+// Asks which hunks to pick, the answers being read from stdin
+pub struct Selector {
+    quit: bool,
+}
+
+impl Selector {
+    pub fn new() -> Selector {
+        Selector { quit: false }
+    }
+
+    // Whether the remaining files must be skipped
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    // The hunks picked for `action` ("Stage", "Unstage", ...), `header`
+    // being shown before the first one. Hunks may be split or edited.
+    pub fn select(
+        &mut self,
+        header: &str,
+        patch: &mut Patch,
+        action: &str,
+    ) -> io::Result<Vec<bool>> {
+        let mut selected: Vec<Option<bool>> = vec![None; patch.len()];
+        if !self.quit && patch.len() > 0 {
+            print!("{}", header);
+        }
+        let mut i = 0;
+        while i < patch.len() && !self.quit {
+            io::stdout().write_all(&patch.format_hunk(i))?;
+            let options = match patch.can_split(i) {
+                true => "y,n,q,a,d,s,e,?",
+                false => "y,n,q,a,d,e,?",
+            };
+            print!(
+                "({}/{}) {} this hunk [{}]? ",
+                i + 1,
+                patch.len(),
+                action,
+                options
+            );
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 {
+                println!();
+                self.quit = true;
+                break;
+            }
+            match answer.trim() {
+                "y" => selected[i] = Some(true),
+                "n" => selected[i] = Some(false),
+                "q" => self.quit = true,
+                "a" => {
+                    for s in &mut selected[i..] {
+                        *s = Some(true);
+                    }
+                    break;
+                }
+                "d" => break,
+                "s" if patch.can_split(i) => {
+                    let nb_parts = patch.split(i);
+                    selected.splice(i..i + 1, vec![None; nb_parts]);
+                    println!("Split into {} hunks.", nb_parts);
+                    continue;
+                }
+                "e" => match edit_hunk(patch, i)? {
+                    true => selected[i] = Some(true),
+                    false => {
+                        println!("Your edited hunk does not apply.");
+                        continue;
+                    }
+                },
+                _ => {
+                    print!("{}", help(action, options));
+                    continue;
+                }
+            }
+            i += 1;
+        }
+        Ok(selected.into_iter().map(|s| s == Some(true)).collect())
+    }
+
+    // Whether a change without hunks is picked, `change` describing it
+    // ("mode change", "deletion", ...)
+    pub fn select_whole(&mut self, header: &str, change: &str, action: &str) -> io::Result<bool> {
+        if self.quit {
+            return Ok(false);
+        }
+        print!("{}", header);
+        loop {
+            let options = "y,n,q,a,d,?";
+            print!("(1/1) {} {} [{}]? ", action, change, options);
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 {
+                println!();
+                self.quit = true;
+                return Ok(false);
+            }
+            match answer.trim() {
+                "y" | "a" => return Ok(true),
+                "n" | "d" => return Ok(false),
+                "q" => {
+                    self.quit = true;
+                    return Ok(false);
+                }
+                _ => print!("{}", help(action, options)),
+            }
+        }
+    }
+}
+
+// Help of the options offered
+fn help(action: &str, options: &str) -> String {
+    let action = action.to_lowercase();
+    let lines = [
+        format!("y - {} this hunk", action),
+        format!("n - do not {} this hunk", action),
+        format!(
+            "q - quit; do not {} this hunk or any of the remaining ones",
+            action
+        ),
+        format!("a - {} this hunk and all later hunks in the file", action),
+        format!(
+            "d - do not {} this hunk or any of the later hunks in the file",
+            action
+        ),
+        "s - split the current hunk into smaller hunks".to_string(),
+        "e - manually edit the current hunk".to_string(),
+        "? - print help".to_string(),
+    ];
+    let mut res = String::new();
+    for line in lines
+        .iter()
+        .filter(|l| options.split(',').any(|o| l.starts_with(o)))
+    {
+        res.push_str(line);
+        res.push('\n');
+    }
+    res
+}
+
+// GIT_EDITOR, core.editor, VISUAL, EDITOR and then vi
+fn editor() -> io::Result<String> {
+    if let Ok(editor) = env::var("GIT_EDITOR") {
+        return Ok(editor);
+    }
+    let config = config::Config::new()?;
+    if let Some(editor) = config.get_value("core.editor") {
+        return Ok(editor.to_string());
+    }
+    Ok(env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string()))
+}
+
+// Lets the user edit a hunk in their editor. Returns whether the edited
+// hunk was taken.
+fn edit_hunk(patch: &mut Patch, i: usize) -> io::Result<bool> {
+    let path = Path::new(".git").join("ADD_EDIT.patch");
+    let (removed, added) = match patch.reverse {
+        true => ("+", "-"),
+        false => ("-", "+"),
+    };
+    let mut text = b"# Manual hunk edit mode - see bottom for a quick guide.\n".to_vec();
+    text.extend_from_slice(&patch.format_hunk(i));
+    text.extend_from_slice(
+        format!(
+            "# ---\n\
+             # To remove '{}' lines, make them ' ' lines (context).\n\
+             # To remove '{}' lines, delete them.\n\
+             # Lines starting with # will be removed.\n",
+            removed, added
+        )
+        .as_bytes(),
+    );
+    fs::write(&path, &text)?;
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor()?))
+        .arg("editor")
+        .arg(&path)
+        .status()?;
+    let edited = fs::read(&path)?;
+    fs::remove_file(&path)?;
+    Ok(status.success() && patch.edit(i, &edited))
+}
+
+// Content and mode of a file, None when it does not exist
+pub type Content = Option<(Vec<u8>, u32)>;
+
+// Picks, for each pair of files, hunks of the changes from the old to the
+// new one. Returns the content (and mode) the side the hunks are applied to
+// gets for the files where some were picked, None when it is removed.
+pub fn select_changes(
+    pairs: &[FilePair],
+    action: &str,
+    reverse: bool,
+) -> io::Result<Vec<(String, Content)>> {
+    let mut res = Vec::new();
+    for (pair, patch, selected) in select_hunks(pairs, action, reverse)? {
+        res.push((
+            pair.path().to_string(),
+            apply_hunks(pair, &patch, &selected),
+        ));
+    }
+    Ok(res)
+}
+
+// Same as select_changes with the hunks applied to the old side, along with
+// the new side once they are reverted from it
+pub fn split_changes(
+    pairs: &[FilePair],
+    action: &str,
+) -> io::Result<Vec<(String, Content, Content)>> {
+    let mut res = Vec::new();
+    for (pair, patch, selected) in select_hunks(pairs, action, false)? {
+        // Hunks do not overlap: the new side without the picked ones is the
+        // old side with the other ones
+        let others: Vec<bool> = selected.iter().map(|&s| !s).collect();
+        res.push((
+            pair.path().to_string(),
+            apply_hunks(pair, &patch, &selected),
+            apply_hunks(pair, &patch, &others),
+        ));
+    }
+    Ok(res)
+}
+
+// Pairs of files where some hunks were picked, with their patch. Changes
+// without hunks (of mode, or of an empty file) are picked as a whole, as a
+// single selected entry.
+fn select_hunks<'a>(
+    pairs: &'a [FilePair],
+    action: &str,
+    reverse: bool,
+) -> io::Result<Vec<(&'a FilePair, Patch, Vec<bool>)>> {
+    let opts = diff::Options::new();
+    let mut selector = Selector::new();
+    let mut res = Vec::new();
+    for pair in pairs {
+        if selector.has_quit() {
+            break;
+        }
+        if pair.is_binary(&opts.attributes) {
+            continue;
+        }
+        let path = pair.path();
+        let old = pair.old.as_ref().map_or(&[][..], |b| &b.data);
+        let new = pair.new.as_ref().map_or(&[][..], |b| &b.data);
+        let mut patch = Patch::new(old, new, opts.algorithm, reverse);
+        let mut header = format!("diff --git a/{0} b/{0}\n", path);
+        let change = match (&pair.old, &pair.new) {
+            (Some(o), Some(n)) if o.mode != n.mode => {
+                header.push_str(&format!("old mode {:o}\nnew mode {:o}\n", o.mode, n.mode));
+                "mode change"
+            }
+            (Some(_), Some(_)) => "",
+            (None, n) => {
+                let mode = n.as_ref().map_or(0, |n| n.mode);
+                header.push_str(&format!("new file mode {:o}\n", mode));
+                "addition"
+            }
+            (Some(o), None) => {
+                header.push_str(&format!("deleted file mode {:o}\n", o.mode));
+                "deletion"
+            }
+        };
+
+        let selected = match patch.len() {
+            0 if change.is_empty() => continue,
+            0 => vec![selector.select_whole(&header, change, action)?],
+            _ => {
+                let name = |side: &Option<diff::Blob>, prefix: &str| match side {
+                    Some(_) => format!("{}/{}", prefix, path),
+                    None => "/dev/null".to_string(),
+                };
+                header.push_str(&format!(
+                    "--- {}\n+++ {}\n",
+                    name(&pair.old, "a"),
+                    name(&pair.new, "b")
+                ));
+                selector.select(&header, &mut patch, action)?
+            }
+        };
+        if selected.iter().any(|&s| s) {
+            res.push((pair, patch, selected));
+        }
+    }
+    Ok(res)
+}
+
+// Content (and mode) of the side the hunks are applied to, None when the
+// file is removed
+fn apply_hunks(pair: &FilePair, patch: &Patch, selected: &[bool]) -> Option<(Vec<u8>, u32)> {
+    let (target, other) = match patch.reverse {
+        true => (pair.new.as_ref(), pair.old.as_ref()),
+        false => (pair.old.as_ref(), pair.new.as_ref()),
+    };
+    // Once all of them are picked, the file is the one of the other side
+    let side = match (selected.iter().all(|&s| s), selected.iter().all(|&s| !s)) {
+        (true, _) => other,
+        (_, true) => target,
+        _ => target.or(other),
+    };
+    side.map(|b| (patch.apply(selected), b.mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use patch::Patch;
+    use xdiff::Algorithm;
+
+    const OLD: &[u8] = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+    const NEW: &[u8] = b"one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n";
+    const HALF: &[u8] = b"one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+
+    #[test]
+    fn apply_selected_hunks() {
+        let patch = Patch::new(OLD, NEW, Algorithm::Myers, false);
+        assert_eq!(patch.len(), 2);
+        assert_eq!(patch.apply(&[false, false]), OLD);
+        assert_eq!(patch.apply(&[true, true]), NEW);
+        assert_eq!(patch.apply(&[true, false]), HALF);
+    }
+
+    #[test]
+    fn revert_selected_hunks() {
+        let patch = Patch::new(OLD, NEW, Algorithm::Myers, true);
+        assert_eq!(patch.apply(&[false, false]), NEW);
+        assert_eq!(patch.apply(&[true, true]), OLD);
+        assert_eq!(patch.apply(&[false, true]), HALF);
+    }
+
+    #[test]
+    fn split_hunk() {
+        let new = b"one\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n";
+        let mut patch = Patch::new(OLD, new, Algorithm::Myers, false);
+        assert_eq!(patch.len(), 1);
+        assert!(patch.can_split(0));
+        assert_eq!(patch.split(0), 2);
+        assert_eq!(
+            patch.apply(&[false, true]),
+            &b"1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n"[..]
+        );
+    }
+
+    #[test]
+    fn edit_hunk() {
+        let mut patch = Patch::new(OLD, NEW, Algorithm::Myers, false);
+        assert!(patch.edit(0, b"@@ -1,4 +1,4 @@\n-1\n+uno\n 2\n 3\n 4\n"));
+        assert_eq!(
+            patch.apply(&[true, true]),
+            &b"uno\n2\n3\n4\n5\n6\n7\n8\n9\nten\n"[..]
+        );
+        // The old side must not change
+        assert!(!patch.edit(1, b" 7\n 8\n 9\n-11\n+ten\n"));
+    }
+}
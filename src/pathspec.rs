@@ -0,0 +1,140 @@
+use wildmatch;
+use wildmatch::Flags;
+
+// Paths given on the command line, restricting a command to some files.
+// Items can start with magic: ":(exclude)" (or ":!", ":^") and ":(icase)".
+#[derive(Debug, Clone)]
+pub struct Pathspec {
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    pattern: String,
+    exclude: bool,
+    icase: bool,
+}
+
+impl Pathspec {
+    pub fn new(args: &[String]) -> Pathspec {
+        let mut items = Vec::new();
+        for arg in args {
+            let (mut item, exclude, icase) = parse_magic(arg);
+            while let Some(rest) = item.strip_prefix("./") {
+                item = rest;
+            }
+            let item = item.trim_end_matches('/');
+            let item = match item {
+                "." => "",
+                i => i,
+            };
+            items.push(Item {
+                pattern: item.to_string(),
+                exclude,
+                icase,
+            });
+        }
+
+        Pathspec { items }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    // Paths matching any item (all of them when there are only exclusions)
+    // and none of the excluded ones
+    pub fn matches(&self, path: &str) -> bool {
+        let mut included = self.items.iter().filter(|i| !i.exclude).peekable();
+        let is_included = included.peek().is_none() || included.any(|i| item_matches(i, path));
+        is_included
+            && !self
+                .items
+                .iter()
+                .any(|i| i.exclude && item_matches(i, path))
+    }
+
+    // Items which match none of the paths, exclusions aside
+    pub fn unmatched(&self, paths: &[&str]) -> Vec<String> {
+        self.items
+            .iter()
+            .filter(|i| !i.exclude && !paths.iter().any(|path| item_matches(i, path)))
+            .map(|i| i.pattern.to_string())
+            .collect()
+    }
+
+    // Whether files under a directory can match, so that it has to be walked
+    pub fn may_match_under(&self, dir: &str) -> bool {
+        let prefix = format!("{}/", dir);
+        self.matches(dir)
+            || self.items.iter().any(|i| {
+                !i.exclude
+                    && (i.pattern.starts_with(&prefix) || wildmatch::has_glob_chars(&i.pattern))
+            })
+    }
+}
+
+// The pattern along with the exclude and icase magic
+fn parse_magic(arg: &str) -> (&str, bool, bool) {
+    if let Some(rest) = arg.strip_prefix(":(") {
+        if let Some(end) = rest.find(')') {
+            let words: Vec<&str> = rest[..end].split(',').map(|w| w.trim()).collect();
+            let exclude = words.contains(&"exclude");
+            let icase = words.contains(&"icase");
+            return (&rest[end + 1..], exclude, icase);
+        }
+    }
+    match arg.strip_prefix(":!").or_else(|| arg.strip_prefix(":^")) {
+        Some(rest) => (rest, true, false),
+        None => (arg, false, false),
+    }
+}
+
+fn item_matches(item: &Item, path: &str) -> bool {
+    let (pattern, path) = match item.icase {
+        true => (item.pattern.to_lowercase(), path.to_lowercase()),
+        false => (item.pattern.to_string(), path.to_string()),
+    };
+    if pattern.is_empty() || pattern == path {
+        return true;
+    }
+    if path.starts_with(&pattern) && path[pattern.len()..].starts_with('/') {
+        return true;
+    }
+    wildmatch::has_glob_chars(&pattern) && wildmatch::wildmatch(&pattern, &path, Flags::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use pathspec::Pathspec;
+
+    #[test]
+    fn matching() {
+        let spec = Pathspec::new(&[String::from("src/"), String::from("*.md")]);
+        assert!(spec.matches("src/zlib.rs"));
+        assert!(spec.matches("src/builtin/log.rs"));
+        assert!(!spec.matches("srcs/main.rs"));
+        assert!(spec.matches("README.md"));
+        assert!(spec.matches("doc/guide.md"));
+        assert!(!spec.matches("Cargo.toml"));
+
+        assert!(Pathspec::new(&[String::from(".")]).matches("anything"));
+        assert!(Pathspec::new(&[]).matches("anything"));
+    }
+
+    #[test]
+    fn magic() {
+        let spec = Pathspec::new(&[String::from("src"), String::from(":(exclude)*.md")]);
+        assert!(spec.matches("src/main.rs"));
+        assert!(!spec.matches("src/README.md"));
+        assert!(!spec.matches("Cargo.toml"));
+
+        let spec = Pathspec::new(&[String::from(":!src")]);
+        assert!(spec.matches("Cargo.toml"));
+        assert!(!spec.matches("src/main.rs"));
+
+        let spec = Pathspec::new(&[String::from(":(icase)readme.MD")]);
+        assert!(spec.matches("README.md"));
+        assert_eq!(spec.unmatched(&["Cargo.toml"]), vec!["readme.MD"]);
+    }
+}
@@ -1,7 +1,11 @@
 use std::fs;
 use std::io;
+use std::io::Write;
 use std::path::Path;
 
+use builtin::config;
+use date;
+
 pub fn read_ref(name: &str) -> io::Result<String> {
     let ref_name = full_ref_name(name);
     let ref_path = Path::new(".git").join(ref_name);
@@ -47,6 +51,11 @@ pub fn write_to_ref(name: &str, value: &str) -> io::Result<()> {
         false => format!("{}\n", value),
     };
 
+    // Names may contain directories, and repos created by older versions
+    // have no refs/tags/
+    if let Some(dir) = ref_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
     fs::write(ref_path, formated_value)?;
     Ok(())
 }
@@ -74,11 +83,141 @@ pub fn is_detached_head() -> bool {
 }
 
 fn full_ref_name(name: &str) -> String {
-    if name == "HEAD" || name == "FETCH_HEAD" || name == "MERGE_HEAD"
-        || name.starts_with("refs/heads/")
+    if name == "HEAD" || name == "FETCH_HEAD" || name == "MERGE_HEAD" || name == "ORIG_HEAD"
+        || name.starts_with("refs/")
     {
         name.to_string()
     } else {
         format!("refs/heads/{}", name)
     }
 }
+
+pub fn list_refs() -> io::Result<Vec<(String, String)>> {
+    let mut refs = Vec::new();
+    let mut dirs = vec![Path::new(".git").join("refs")];
+    while let Some(dir) = dirs.pop() {
+        if !dir.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let name = path.strip_prefix(".git").unwrap().to_str().unwrap();
+                let value = fs::read_to_string(&path)?;
+                refs.push((name.to_string(), value.trim().to_string()));
+            }
+        }
+    }
+
+    refs.sort();
+    Ok(refs)
+}
+
+pub fn short_name(full_name: &str) -> &str {
+    for prefix in ["refs/heads/", "refs/remotes/", "refs/tags/"].iter() {
+        if full_name.starts_with(prefix) {
+            return &full_name[prefix.len()..];
+        }
+    }
+    full_name
+}
+
+// HEAD pointing to a branch which may not exist yet
+pub fn write_head_to_branch(name: &str) -> io::Result<()> {
+    let head_path = Path::new(".git").join("HEAD");
+    fs::write(head_path, format!("ref: refs/heads/{}\n", name))
+}
+
+// Value a ref was changed to, and why
+#[derive(Debug)]
+pub struct LogEntry {
+    pub new: String,
+    pub message: String,
+}
+
+// Appends a change of a ref to its log, .git/logs/<full name>
+pub fn append_log(name: &str, old: &str, new: &str, message: &str) -> io::Result<()> {
+    let config = config::Config::new()?;
+    let null_hash = "0".repeat(40);
+    let old = if old.is_empty() { &null_hash } else { old };
+    let new = if new.is_empty() { &null_hash } else { new };
+    let line = format!(
+        "{} {} {} <{}> {} +0200\t{}\n",
+        old, new, config.name, config.email, date::now(), message
+    );
+
+    let log_path = Path::new(".git").join("logs").join(full_ref_name(name));
+    if let Some(dir) = log_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut log = fs::OpenOptions::new().create(true).append(true).open(log_path)?;
+    log.write_all(line.as_bytes())
+}
+
+// Entries of the log of a ref, oldest first
+pub fn read_log(name: &str) -> io::Result<Vec<LogEntry>> {
+    let log_path = Path::new(".git").join("logs").join(full_ref_name(name));
+    if !log_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for line in fs::read_to_string(log_path)?.lines() {
+        let (info, message) = match line.find('\t') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (line, ""),
+        };
+        // <old> <new> <committer>
+        let new = match info.split(' ').nth(1) {
+            Some(new) => new,
+            None => continue,
+        };
+        entries.push(LogEntry {
+            new: new.to_string(),
+            message: message.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+// Removes a ref along with its log
+pub fn delete_ref(name: &str) -> io::Result<()> {
+    let ref_name = full_ref_name(name);
+    fs::remove_file(Path::new(".git").join(&ref_name))?;
+    let log_path = Path::new(".git").join("logs").join(&ref_name);
+    if log_path.exists() {
+        fs::remove_file(log_path)?;
+    }
+    Ok(())
+}
+
+// Removes the nth most recent entry of the log of a ref
+pub fn delete_log_entry(name: &str, n: usize) -> io::Result<()> {
+    let log_path = Path::new(".git").join("logs").join(full_ref_name(name));
+    let log = fs::read_to_string(&log_path)?;
+    let mut lines: Vec<&str> = log.lines().collect();
+    if n < lines.len() {
+        let i = lines.len() - 1 - n;
+        lines.remove(i);
+    }
+    let mut log: String = lines.join("\n");
+    if !log.is_empty() {
+        log.push('\n');
+    }
+    fs::write(log_path, log)
+}
+
+// Branch (or commit) checked out before the current one, from the HEAD log
+pub fn previous_checkout() -> io::Result<Option<String>> {
+    let prefix = "checkout: moving from ";
+    for entry in read_log("HEAD")?.iter().rev() {
+        if let Some(rest) = entry.message.strip_prefix(prefix) {
+            if let Some(i) = rest.find(" to ") {
+                return Ok(Some(rest[..i].to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
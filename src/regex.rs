@@ -0,0 +1,520 @@
+// Small backtracking regular expression engine (POSIX extended syntax plus a
+// few perl shorthands: \d \w \s \b)
+
+#[derive(Debug)]
+pub enum Error {
+    NothingToRepeat,
+    TrailingBackslash,
+    UnbalancedBracket,
+    UnbalancedParenthesis,
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+    Alpha,
+    Alnum,
+    Upper,
+    Lower,
+    Punct,
+    Xdigit,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<ClassItem>, bool),
+    Start,
+    End,
+    WordBoundary,
+    Group(Vec<Vec<Node>>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    alternatives: Vec<Vec<Node>>,
+    icase: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, icase: bool) -> Result<Regex, Error> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut parser = Parser {
+            chars: &chars,
+            pos: 0,
+        };
+        let alternatives = parser.parse_alternatives()?;
+        if parser.pos < chars.len() {
+            return Err(Error::UnbalancedParenthesis);
+        }
+
+        Ok(Regex {
+            alternatives,
+            icase,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        (0..=chars.len()).any(|start| self.match_at(&chars, start).is_some())
+    }
+
+    // Byte ranges of every non-overlapping match
+    pub fn find_iter(&self, text: &str) -> Vec<(usize, usize)> {
+        let offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(text.len()))
+            .collect();
+        let chars: Vec<char> = text.chars().collect();
+
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start <= chars.len() {
+            match self.match_at(&chars, start) {
+                Some(end) if end > start => {
+                    matches.push((offsets[start], offsets[end]));
+                    start = end;
+                }
+                _ => start += 1,
+            }
+        }
+        matches
+    }
+
+    fn match_at(&self, text: &[char], start: usize) -> Option<usize> {
+        let mut end = None;
+        let found = self.alternatives.iter().any(|alt| {
+            self.match_seq(alt, text, start, &mut |i| {
+                end = Some(i);
+                true
+            })
+        });
+        match found {
+            true => end,
+            false => None,
+        }
+    }
+
+    fn match_seq(
+        &self,
+        nodes: &[Node],
+        text: &[char],
+        i: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        match nodes.split_first() {
+            None => k(i),
+            Some((node, rest)) => {
+                self.match_node(node, text, i, &mut |j| self.match_seq(rest, text, j, k))
+            }
+        }
+    }
+
+    fn match_node(
+        &self,
+        node: &Node,
+        text: &[char],
+        i: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        match *node {
+            Node::Start => i == 0 && k(i),
+            Node::End => i == text.len() && k(i),
+            Node::WordBoundary => {
+                let before = i > 0 && is_word(text[i - 1]);
+                let after = i < text.len() && is_word(text[i]);
+                before != after && k(i)
+            }
+            Node::Group(ref alternatives) => alternatives
+                .iter()
+                .any(|alt| self.match_seq(alt, text, i, k)),
+            Node::Repeat(ref node, min, max) => match is_single_char(node) {
+                true => self.match_simple_repeat(node, min, max, text, i, k),
+                false => self.match_repeat(node, min, max, text, i, 0, k),
+            },
+            _ => i < text.len() && self.match_char(node, text[i]) && k(i + 1),
+        }
+    }
+
+    // Iterative version for single characters, avoids deep recursion on
+    // long inputs
+    fn match_simple_repeat(
+        &self,
+        node: &Node,
+        min: usize,
+        max: Option<usize>,
+        text: &[char],
+        i: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        let max = max.unwrap_or(usize::MAX);
+        let mut count = 0;
+        while count < max && i + count < text.len() && self.match_char(node, text[i + count]) {
+            count += 1;
+        }
+        while count >= min {
+            if k(i + count) {
+                return true;
+            }
+            if count == 0 {
+                break;
+            }
+            count -= 1;
+        }
+        false
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn match_repeat(
+        &self,
+        node: &Node,
+        min: usize,
+        max: Option<usize>,
+        text: &[char],
+        i: usize,
+        count: usize,
+        k: &mut dyn FnMut(usize) -> bool,
+    ) -> bool {
+        if max.is_none_or(|m| count < m) {
+            let more = self.match_node(node, text, i, &mut |j| {
+                j != i && self.match_repeat(node, min, max, text, j, count + 1, k)
+            });
+            if more {
+                return true;
+            }
+        }
+        count >= min && k(i)
+    }
+
+    fn match_char(&self, node: &Node, c: char) -> bool {
+        match *node {
+            Node::Any => c != '\n',
+            Node::Char(e) => match self.icase {
+                true => lower(e) == lower(c),
+                false => e == c,
+            },
+            Node::Class(ref items, negated) => {
+                let found = items.iter().any(|item| {
+                    class_contains(item, c)
+                        || (self.icase
+                            && (class_contains(item, lower(c)) || class_contains(item, upper(c))))
+                });
+                found != negated
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_single_char(node: &Node) -> bool {
+    matches!(*node, Node::Char(_) | Node::Any | Node::Class(_, _))
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+fn class_contains(item: &ClassItem, c: char) -> bool {
+    match *item {
+        ClassItem::Char(e) => e == c,
+        ClassItem::Range(a, b) => a <= c && c <= b,
+        ClassItem::Digit => c.is_ascii_digit(),
+        ClassItem::Word => is_word(c),
+        ClassItem::Space => c.is_whitespace(),
+        ClassItem::Alpha => c.is_alphabetic(),
+        ClassItem::Alnum => c.is_alphanumeric(),
+        ClassItem::Upper => c.is_uppercase(),
+        ClassItem::Lower => c.is_lowercase(),
+        ClassItem::Punct => c.is_ascii_punctuation(),
+        ClassItem::Xdigit => c.is_ascii_hexdigit(),
+    }
+}
+
+struct Parser<'a> {
+    chars: &'a [char],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn parse_alternatives(&mut self) -> Result<Vec<Vec<Node>>, Error> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.parse_sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self) -> Result<Vec<Node>, Error> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            let node = match c {
+                '|' | ')' => break,
+                '(' => {
+                    self.pos += 1;
+                    let alternatives = self.parse_alternatives()?;
+                    if self.peek() != Some(')') {
+                        return Err(Error::UnbalancedParenthesis);
+                    }
+                    self.pos += 1;
+                    Node::Group(alternatives)
+                }
+                '*' | '+' | '?' | '{' if !nodes.is_empty() => {
+                    let node = nodes.pop().unwrap();
+                    let repeat = self.parse_repeat(node)?;
+                    nodes.push(repeat);
+                    continue;
+                }
+                '*' | '+' | '?' => return Err(Error::NothingToRepeat),
+                '.' => {
+                    self.pos += 1;
+                    Node::Any
+                }
+                '^' => {
+                    self.pos += 1;
+                    Node::Start
+                }
+                '$' => {
+                    self.pos += 1;
+                    Node::End
+                }
+                '[' => self.parse_class()?,
+                '\\' => self.parse_escape()?,
+                c => {
+                    self.pos += 1;
+                    Node::Char(c)
+                }
+            };
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+
+    fn parse_repeat(&mut self, node: Node) -> Result<Node, Error> {
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        let (min, max) = match c {
+            '*' => (0, None),
+            '+' => (1, None),
+            '?' => (0, Some(1)),
+            _ => {
+                // {n}, {n,}, {n,m}, literal '{' otherwise
+                let end = match self.chars[self.pos..].iter().position(|&c| c == '}') {
+                    Some(i) => self.pos + i,
+                    None => return Ok(Node::Group(vec![vec![node, Node::Char('{')]])),
+                };
+                let bounds: String = self.chars[self.pos..end].iter().collect();
+                let mut parts = bounds.splitn(2, ',');
+                let min = parts.next().and_then(|m| m.trim().parse::<usize>().ok());
+                let max = match parts.next() {
+                    Some(m) if m.trim().is_empty() => None,
+                    Some(m) => m.trim().parse::<usize>().ok(),
+                    None => min,
+                };
+                match min {
+                    Some(min) => {
+                        self.pos = end + 1;
+                        (min, max)
+                    }
+                    None => return Ok(Node::Group(vec![vec![node, Node::Char('{')]])),
+                }
+            }
+        };
+        // Lazy quantifiers behave like greedy ones when only testing matches
+        if self.peek() == Some('?') {
+            self.pos += 1;
+        }
+        Ok(Node::Repeat(Box::new(node), min, max))
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, Error> {
+        self.pos += 1;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(Error::TrailingBackslash),
+        };
+        self.pos += 1;
+        let node = match c {
+            'd' => Node::Class(vec![ClassItem::Digit], false),
+            'D' => Node::Class(vec![ClassItem::Digit], true),
+            'w' => Node::Class(vec![ClassItem::Word], false),
+            'W' => Node::Class(vec![ClassItem::Word], true),
+            's' => Node::Class(vec![ClassItem::Space], false),
+            'S' => Node::Class(vec![ClassItem::Space], true),
+            'b' => Node::WordBoundary,
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            c => Node::Char(c),
+        };
+        Ok(node)
+    }
+
+    fn parse_class(&mut self) -> Result<Node, Error> {
+        self.pos += 1;
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return Err(Error::UnbalancedBracket),
+            };
+            if c == ']' && !first {
+                self.pos += 1;
+                break;
+            }
+            first = false;
+
+            if c == '[' && self.chars.get(self.pos + 1) == Some(&':') {
+                // "[:" always starts a named class
+                let rest: String = self.chars[self.pos..].iter().collect();
+                let end = match rest[2..].find(":]") {
+                    Some(end) => end + 2,
+                    None => return Err(Error::UnbalancedBracket),
+                };
+                let item = match &rest[2..end] {
+                    "alpha" => ClassItem::Alpha,
+                    "digit" => ClassItem::Digit,
+                    "alnum" => ClassItem::Alnum,
+                    "space" => ClassItem::Space,
+                    "upper" => ClassItem::Upper,
+                    "lower" => ClassItem::Lower,
+                    "punct" => ClassItem::Punct,
+                    "xdigit" => ClassItem::Xdigit,
+                    _ => return Err(Error::UnbalancedBracket),
+                };
+                items.push(item);
+                self.pos += rest[..end + 2].chars().count();
+                continue;
+            }
+
+            let c = match c {
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('d') => {
+                            self.pos += 1;
+                            items.push(ClassItem::Digit);
+                            continue;
+                        }
+                        Some('w') => {
+                            self.pos += 1;
+                            items.push(ClassItem::Word);
+                            continue;
+                        }
+                        Some('s') => {
+                            self.pos += 1;
+                            items.push(ClassItem::Space);
+                            continue;
+                        }
+                        Some(c) => c,
+                        None => return Err(Error::TrailingBackslash),
+                    }
+                }
+                c => c,
+            };
+            self.pos += 1;
+
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&e| e != ']');
+            if is_range {
+                let end = self.chars[self.pos + 1];
+                self.pos += 2;
+                items.push(ClassItem::Range(c, end));
+            } else {
+                items.push(ClassItem::Char(c));
+            }
+        }
+
+        Ok(Node::Class(items, negated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    fn is_match(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern, false).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn literals() {
+        assert!(is_match("fix", "bugfix in zlib"));
+        assert!(!is_match("fix", "bug in zlib"));
+        assert!(is_match("^bug", "bugfix"));
+        assert!(!is_match("^fix", "bugfix"));
+        assert!(is_match("fix$", "bugfix"));
+        assert!(is_match("a\\.b", "a.b"));
+        assert!(!is_match("a\\.b", "axb"));
+    }
+
+    #[test]
+    fn repetitions() {
+        assert!(is_match("ab*c", "ac"));
+        assert!(is_match("ab+c", "abbbc"));
+        assert!(!is_match("ab+c", "ac"));
+        assert!(is_match("^a.?c$", "abc"));
+        assert!(is_match("^(ab){2}$", "abab"));
+        assert!(!is_match("^(ab){3,}$", "abab"));
+        assert!(is_match("^a{1,2}b$", "aab"));
+        assert!(is_match("^.*zlib.*$", "fix zlib decoder"));
+    }
+
+    #[test]
+    fn classes_and_groups() {
+        assert!(is_match("^[a-c]+$", "abcab"));
+        assert!(!is_match("^[^a-c]+$", "abd"));
+        assert!(is_match("[[:digit:]]", "v1"));
+        assert!(is_match("\\d\\s\\w", "1 a"));
+        assert!(is_match("^(alice|bob) <", "bob <bob@doe.com>"));
+        assert!(is_match("\\bzlib\\b", "the zlib module"));
+        assert!(!is_match("\\bzlib\\b", "libzlibs"));
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let re = Regex::new("^john [d-e]oe$", true).unwrap();
+        assert!(re.is_match("John DOE"));
+    }
+
+    #[test]
+    fn find_all() {
+        let re = Regex::new("[a-z]+", false).unwrap();
+        assert_eq!(vec![(0, 3), (4, 7)], re.find_iter("foo bar"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Regex::new("(ab", false).is_err());
+        assert!(Regex::new("[ab", false).is_err());
+        assert!(Regex::new("*a", false).is_err());
+        assert!(Regex::new("a\\", false).is_err());
+        assert!(Regex::new("[[:]", false).is_err());
+        assert!(Regex::new("[[:x", false).is_err());
+        assert!(Regex::new("[[::]]", false).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+// Rename and copy detection: files added on one side are matched with files
+// deleted (or, for copies, kept) on the other side, first on identical
+// content then on content similarity
+use std::collections::HashMap;
+
+use builtin::config;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub renames: bool,
+    pub copies: bool,
+    // Minimum similarity in percent
+    pub threshold: u32,
+    // Inexact detection is skipped above limit * limit pairs of files
+    pub limit: usize,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            renames: false,
+            copies: false,
+            threshold: 50,
+            limit: 1000,
+        }
+    }
+
+    // diff.renames (true, false or "copies") and diff.renameLimit
+    pub fn from_config() -> Options {
+        let mut opts = Options::new();
+        opts.renames = true;
+        if let Ok(config) = config::Config::new() {
+            match config.get_value("diff.renames") {
+                Some("copies") | Some("copy") => opts.copies = true,
+                Some(_) => opts.renames = config.get_bool("diff.renames").unwrap_or(true),
+                None => (),
+            }
+            let limit = config.get_value("diff.renameLimit");
+            if let Some(limit) = limit.and_then(|l| l.parse::<usize>().ok()) {
+                opts.limit = limit;
+            }
+        }
+        opts
+    }
+
+    // -M[<n>], --find-renames[=<n>], -C[<n>], --find-copies[=<n>],
+    // --no-renames and -l<n>. Returns false if the flag is not one of them.
+    pub fn parse_flag(&mut self, flag: &str) -> bool {
+        let (name, value) = match flag.find('=') {
+            Some(i) => (&flag[..i], Some(&flag[i + 1..])),
+            None if flag.len() > 2 && !flag.starts_with("--") => (&flag[..2], Some(&flag[2..])),
+            None => (flag, None),
+        };
+        match name {
+            "-M" | "--find-renames" => self.renames = true,
+            "-C" | "--find-copies" => {
+                self.renames = true;
+                self.copies = true;
+            }
+            "--no-renames" => {
+                self.renames = false;
+                self.copies = false;
+                return value.is_none();
+            }
+            "-l" => {
+                return match value.and_then(|v| v.parse::<usize>().ok()) {
+                    Some(limit) => {
+                        self.limit = limit;
+                        true
+                    }
+                    None => false,
+                };
+            }
+            _ => return false,
+        }
+
+        match value {
+            Some(value) => match parse_score(value) {
+                Some(score) => {
+                    self.threshold = score;
+                    true
+                }
+                None => false,
+            },
+            None => true,
+        }
+    }
+}
+
+// "90%", "90" (a percentage) or "0.9"
+fn parse_score(value: &str) -> Option<u32> {
+    if let Some(percent) = value.strip_suffix('%') {
+        return percent.parse::<u32>().ok().filter(|&p| p <= 100);
+    }
+    if let Some(fraction) = value.strip_prefix("0.").or_else(|| value.strip_prefix('.')) {
+        let digits = fraction.get(..2).unwrap_or(fraction);
+        let score = format!("{:0<2}", digits).parse::<u32>().ok()?;
+        return Some(score);
+    }
+    value.parse::<u32>().ok().filter(|&p| p <= 100)
+}
+
+pub struct File<'a> {
+    pub hash: &'a str,
+    pub data: &'a [u8],
+    // Sources which are deleted can be renamed, the other ones only copied
+    pub deleted: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Match {
+    pub source: usize,
+    pub score: u32,
+    pub is_copy: bool,
+}
+
+// For each destination, the source it comes from (if any)
+pub fn find(sources: &[File], dests: &[File], opts: &Options) -> Vec<Option<Match>> {
+    let mut matches: Vec<Option<Match>> = dests.iter().map(|_| None).collect();
+    if !opts.renames || dests.is_empty() {
+        return matches;
+    }
+    let can_use = |s: &File| s.deleted || opts.copies;
+    let mut renamed = vec![false; sources.len()];
+
+    // Identical contents first, preferring sources which are not renamed yet
+    for (d, dest) in dests.iter().enumerate() {
+        let mut candidates: Vec<usize> = (0..sources.len())
+            .filter(|&s| can_use(&sources[s]) && sources[s].hash == dest.hash)
+            .collect();
+        candidates.sort_by_key(|&s| (!sources[s].deleted || renamed[s]) as u8);
+        if let Some(&s) = candidates.first() {
+            matches[d] = Some(take(s, 100, &sources[s], &mut renamed));
+        }
+    }
+
+    let left_dests: Vec<usize> = (0..dests.len()).filter(|&d| matches[d].is_none()).collect();
+    let left_sources: Vec<usize> = (0..sources.len())
+        .filter(|&s| can_use(&sources[s]) && (!renamed[s] || opts.copies))
+        .collect();
+    if left_dests.len() * left_sources.len() > opts.limit * opts.limit {
+        return matches;
+    }
+
+    let mut scores = Vec::new();
+    for &d in &left_dests {
+        for &s in &left_sources {
+            let score = similarity(sources[s].data, dests[d].data);
+            if score >= opts.threshold {
+                scores.push((score, d, s));
+            }
+        }
+    }
+    // Best scores first, renames before copies
+    scores.sort_by_key(|&(score, d, s)| (100 - score, !sources[s].deleted, d, s));
+    for (score, d, s) in scores {
+        if matches[d].is_some() || (renamed[s] && !opts.copies) {
+            continue;
+        }
+        matches[d] = Some(take(s, score, &sources[s], &mut renamed));
+    }
+    matches
+}
+
+// The first use of a deleted source is a rename, the other ones copies
+fn take(s: usize, score: u32, source: &File, renamed: &mut [bool]) -> Match {
+    let is_copy = !source.deleted || renamed[s];
+    renamed[s] |= source.deleted;
+    Match {
+        source: s,
+        score,
+        is_copy,
+    }
+}
+
+// Percentage of the bytes of the larger file found in the other one,
+// comparing them line by line
+pub fn similarity(a: &[u8], b: &[u8]) -> u32 {
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 100;
+    }
+
+    let mut lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in a.split_inclusive(|&c| c == b'\n') {
+        *lines.entry(line).or_insert(0) += 1;
+    }
+    let mut common = 0;
+    for line in b.split_inclusive(|&c| c == b'\n') {
+        if let Some(count) = lines.get_mut(line) {
+            if *count > 0 {
+                *count -= 1;
+                common += line.len();
+            }
+        }
+    }
+    (common * 100 / max_len) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use rename::{find, parse_score, similarity, File, Match, Options};
+
+    #[test]
+    fn scores() {
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nc\nd\n"), 100);
+        assert_eq!(similarity(b"a\nb\nc\nd\n", b"a\nb\nc\nx\n"), 75);
+        assert_eq!(similarity(b"a\nb\n", b"c\nd\n"), 0);
+        assert_eq!(parse_score("90%"), Some(90));
+        assert_eq!(parse_score("0.5"), Some(50));
+        assert_eq!(parse_score("75"), Some(75));
+        assert_eq!(parse_score("x"), None);
+    }
+
+    #[test]
+    fn renames_and_copies() {
+        let file = |hash, data, deleted| File {
+            hash,
+            data,
+            deleted,
+        };
+        let sources = [
+            file("1", b"a\nb\nc\nd\n", true),
+            file("2", b"e\nf\ng\nh\n", false),
+        ];
+        let dests = [
+            file("1", b"a\nb\nc\nd\n", false),
+            file("3", b"e\nf\ng\nx\n", false),
+        ];
+
+        let mut opts = Options::new();
+        opts.renames = true;
+        let matches = find(&sources, &dests, &opts);
+        let rename = Match {
+            source: 0,
+            score: 100,
+            is_copy: false,
+        };
+        assert_eq!(matches, vec![Some(rename), None]);
+
+        opts.copies = true;
+        let matches = find(&sources, &dests, &opts);
+        let copy = Match {
+            source: 1,
+            score: 75,
+            is_copy: true,
+        };
+        assert_eq!(matches[1], Some(copy));
+
+        opts.threshold = 80;
+        assert_eq!(find(&sources, &dests, &opts)[1], None);
+    }
+}
@@ -0,0 +1,524 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use builtin::commit;
+use builtin::commit::Commit;
+use builtin::read_tree;
+use builtin::tag;
+use cli;
+use index;
+use object;
+use pathspec::Pathspec;
+use refs;
+use rename;
+
+#[derive(Debug)]
+pub enum Error {
+    CommitError(commit::Error),
+    IndexError(index::Error),
+    IoError(io::Error),
+    ObjectError(object::Error),
+    ReadTreeError(read_tree::Error),
+    TagError(tag::Error),
+    UnknownRevision(String),
+}
+
+impl From<commit::Error> for Error {
+    fn from(e: commit::Error) -> Error {
+        Error::CommitError(e)
+    }
+}
+impl From<index::Error> for Error {
+    fn from(e: index::Error) -> Error {
+        Error::IndexError(e)
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::IoError(e)
+    }
+}
+impl From<object::Error> for Error {
+    fn from(e: object::Error) -> Error {
+        Error::ObjectError(e)
+    }
+}
+impl From<read_tree::Error> for Error {
+    fn from(e: read_tree::Error) -> Error {
+        Error::ReadTreeError(e)
+    }
+}
+impl From<tag::Error> for Error {
+    fn from(e: tag::Error) -> Error {
+        Error::TagError(e)
+    }
+}
+
+// Same as resolve_object, tags being peeled to the object they point to
+pub fn resolve(rev: &str) -> Result<String, Error> {
+    if let Some(i) = rev.find(':') {
+        return resolve_path(&rev[..i], &rev[i + 1..]);
+    }
+    Ok(tag::peel(&resolve_object(rev)?)?)
+}
+
+// <name>, <name>~<n>, <name>^<n>, <name>^{<type>} (and any combination of
+// them), and <rev>:<path> or :<path> for the objects of a tree or of the index
+pub fn resolve_object(rev: &str) -> Result<String, Error> {
+    if let Some(i) = rev.find(':') {
+        return resolve_path(&rev[..i], &rev[i + 1..]);
+    }
+
+    let split = rev.find(['~', '^']).unwrap_or(rev.len());
+    let (base, mut ops) = rev.split_at(split);
+    let mut hash = resolve_name(base)?;
+
+    while !ops.is_empty() {
+        let op = &ops[..1];
+        ops = &ops[1..];
+        if op == "^" && ops.starts_with('{') {
+            let end = match ops.find('}') {
+                Some(end) => end,
+                None => return Err(Error::UnknownRevision(rev.to_string())),
+            };
+            hash = peel_to(&hash, &ops[1..end], rev)?;
+            ops = &ops[end + 1..];
+            continue;
+        }
+
+        // Tags of commits stand for their commit
+        hash = tag::peel(&hash)?;
+        let nb_digits = ops.find(|c: char| !c.is_ascii_digit()).unwrap_or(ops.len());
+        let n = match nb_digits {
+            0 => 1,
+            _ => ops[..nb_digits].parse::<usize>().unwrap_or(1),
+        };
+        ops = &ops[nb_digits..];
+
+        if op == "~" {
+            for _ in 0..n {
+                hash = match commit::get_parents_hashes(&hash)?.first() {
+                    Some(p) => p.to_string(),
+                    None => return Err(Error::UnknownRevision(rev.to_string())),
+                };
+            }
+        } else if n > 0 {
+            hash = match commit::get_parents_hashes(&hash)?.get(n - 1) {
+                Some(p) => p.to_string(),
+                None => return Err(Error::UnknownRevision(rev.to_string())),
+            };
+        }
+    }
+
+    Ok(hash)
+}
+
+// <rev>^{<type>}: the object of that type a tag or a commit leads to, any
+// object not being a tag for <rev>^{}
+fn peel_to(hash: &str, obj_type: &str, rev: &str) -> Result<String, Error> {
+    let mut hash = match obj_type {
+        "tag" | "object" => hash.to_string(),
+        _ => tag::peel(hash)?,
+    };
+    if obj_type == "tree" && object::Object::new(&hash)?.obj_type == "commit" {
+        hash = commit::get_tree_hash(&hash)?;
+    }
+
+    match obj_type {
+        "" | "object" => Ok(hash),
+        t if object::Object::new(&hash)?.obj_type == t => Ok(hash),
+        _ => Err(Error::UnknownRevision(rev.to_string())),
+    }
+}
+
+fn resolve_path(rev: &str, path: &str) -> Result<String, Error> {
+    let unknown = || Error::UnknownRevision(format!("{}:{}", rev, path));
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+    if rev.is_empty() {
+        let entries = index::read_entries()?;
+        return match entries.iter().find(|e| e.path == path) {
+            Some(e) => Ok(e.hash.to_string()),
+            None => Err(unknown()),
+        };
+    }
+
+    let mut hash = commit::get_tree_hash(&resolve(rev)?)?;
+    let mut rest = path;
+    while !rest.is_empty() {
+        let entries = read_tree::read_tree(&hash)?;
+        // Trees written by older versions store full paths in a single tree
+        if let Some(e) = entries.iter().find(|e| e.path == rest) {
+            return Ok(e.hash.to_string());
+        }
+
+        let (name, next) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+        hash = match entries.iter().find(|e| e.path == name) {
+            Some(e) => e.hash.to_string(),
+            None => return Err(unknown()),
+        };
+        rest = next;
+    }
+
+    Ok(hash)
+}
+
+fn resolve_name(name: &str) -> Result<String, Error> {
+    let name = match name {
+        "" | "@" => "HEAD",
+        n => n,
+    };
+
+    // <ref>@{<n>}: the value the ref had n changes ago, from its log
+    if let (Some(i), true) = (name.find("@{"), name.ends_with('}')) {
+        if let Ok(n) = name[i + 2..name.len() - 1].parse::<usize>() {
+            return resolve_log_entry(&name[..i], n);
+        }
+    }
+
+    for candidate in candidate_refs(name) {
+        let path = Path::new(".git").join(&candidate);
+        if !path.is_file() || candidate.contains("..") {
+            continue;
+        }
+
+        let value = fs::read_to_string(&path)?;
+        let value = value.trim();
+        if let Some(target) = value.strip_prefix("ref: ") {
+            return resolve_name(target);
+        }
+        // FETCH_HEAD: <hash> branch '<name>' of <url>
+        let hash = value.split_whitespace().next().unwrap_or("");
+        if object::exists(hash) {
+            return Ok(hash.to_string());
+        }
+    }
+
+    let is_hash = name.len() >= 4 && name.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hash {
+        if let Ok(hash) = object::expand_hash(name) {
+            return Ok(hash);
+        }
+    }
+
+    Err(Error::UnknownRevision(name.to_string()))
+}
+
+// Refs a name can stand for, in order of precedence
+fn candidate_refs(name: &str) -> Vec<String> {
+    vec![
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ]
+}
+
+fn resolve_log_entry(name: &str, n: usize) -> Result<String, Error> {
+    let unknown = || Error::UnknownRevision(format!("{}@{{{}}}", name, n));
+    let name = match name {
+        "" => "HEAD",
+        n => n,
+    };
+    let full_name = candidate_refs(name)
+        .into_iter()
+        .find(|c| Path::new(".git").join(c).is_file() && !c.contains(".."))
+        .ok_or_else(unknown)?;
+    let log = refs::read_log(&full_name)?;
+    match log.len() > n {
+        true => Ok(log[log.len() - 1 - n].new.to_string()),
+        false => Err(unknown()),
+    }
+}
+
+pub fn is_revision(name: &str) -> bool {
+    resolve(name).is_ok()
+}
+
+// <a>..<b> or <a>...<b> (true), a missing side being HEAD
+pub fn split_range(rev: &str) -> Option<(String, String, bool)> {
+    let i = rev.find("..")?;
+    let symmetric = rev[i..].starts_with("...");
+    let end = i + if symmetric { 3 } else { 2 };
+    let side = |s: &str| match s {
+        "" => "HEAD".to_string(),
+        s => s.to_string(),
+    };
+    Some((side(&rev[..i]), side(&rev[end..]), symmetric))
+}
+
+// Without "--", arguments after the first one which is not a revision (nor
+// a range of revisions) are paths, which must exist
+pub fn split_revs_and_paths(args: &[String]) -> Result<(Vec<String>, Vec<String>), Error> {
+    if args.iter().any(|a| a == "--") {
+        return Ok(cli::split_paths(args));
+    }
+
+    let is_rev = |rev: &str| match split_range(rev) {
+        Some((a, b, _)) => is_revision(&a) && is_revision(&b),
+        None => is_revision(rev),
+    };
+    let mut revs = args.to_vec();
+    let paths = match revs.iter().position(|r| !is_rev(r)) {
+        Some(i) => revs.split_off(i),
+        None => Vec::new(),
+    };
+    for path in &paths {
+        if !Path::new(path).exists() {
+            return Err(Error::UnknownRevision(path.to_string()));
+        }
+    }
+    Ok((revs, paths))
+}
+
+struct Ready {
+    time: i64,
+    order: usize,
+    hash: String,
+}
+
+impl Ord for Ready {
+    fn cmp(&self, other: &Ready) -> Ordering {
+        self.time
+            .cmp(&other.time)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+impl PartialOrd for Ready {
+    fn partial_cmp(&self, other: &Ready) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for Ready {
+    fn eq(&self, other: &Ready) -> bool {
+        self.order == other.order
+    }
+}
+impl Eq for Ready {}
+
+// Every commit reachable from `starts`, newest first, never showing a parent
+// before all of its children.
+pub fn walk(starts: &[String]) -> Result<Vec<Commit>, Error> {
+    walk_with(starts, &mut |commit| Ok(commit.parents.clone()))
+}
+
+// Same as walk, only going through the parents picked by `select`, which
+// replace the parents of the returned commits.
+pub fn walk_with(
+    starts: &[String],
+    select: &mut dyn FnMut(&Commit) -> Result<Vec<String>, Error>,
+) -> Result<Vec<Commit>, Error> {
+    let mut commits: HashMap<String, (Commit, usize)> = HashMap::new();
+    let mut nb_children: HashMap<String, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+    for start in starts {
+        if !commits.contains_key(start) {
+            let mut commit = Commit::new(start)?;
+            commit.parents = select(&commit)?;
+            let order = commits.len();
+            commits.insert(start.to_string(), (commit, order));
+            queue.push_back(start.to_string());
+        }
+    }
+
+    while let Some(hash) = queue.pop_front() {
+        let parents = commits[&hash].0.parents.clone();
+        for parent in parents {
+            *nb_children.entry(parent.to_string()).or_insert(0) += 1;
+            if !commits.contains_key(&parent) {
+                let mut commit = Commit::new(&parent)?;
+                commit.parents = select(&commit)?;
+                let order = commits.len();
+                commits.insert(parent.to_string(), (commit, order));
+                queue.push_back(parent);
+            }
+        }
+    }
+
+    let mut heap = BinaryHeap::new();
+    for (hash, (commit, order)) in &commits {
+        if !nb_children.contains_key(hash) {
+            heap.push(Ready {
+                time: commit.committer.time,
+                order: *order,
+                hash: hash.to_string(),
+            });
+        }
+    }
+
+    let mut sorted = Vec::new();
+    while let Some(ready) = heap.pop() {
+        let commit = commits[&ready.hash].0.clone();
+        for parent in &commit.parents {
+            let count = nb_children.get_mut(parent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                let (parent_commit, order) = &commits[parent];
+                heap.push(Ready {
+                    time: parent_commit.committer.time,
+                    order: *order,
+                    hash: parent.to_string(),
+                });
+            }
+        }
+        sorted.push(commit);
+    }
+
+    Ok(sorted)
+}
+
+// Commits changing the paths of `pathspec`. Like git's default history
+// simplification, a merge giving the same content as one of its parents only
+// follows that parent.
+pub fn walk_paths(starts: &[String], pathspec: &Pathspec) -> Result<Vec<Commit>, Error> {
+    let mut trees = HashMap::new();
+    let mut shown = HashSet::new();
+    let commits = walk_with(starts, &mut |commit| {
+        let tree = filtered_tree(&commit.tree, pathspec, &mut trees)?;
+        if commit.parents.is_empty() {
+            if !tree.is_empty() {
+                shown.insert(commit.hash.to_string());
+            }
+            return Ok(Vec::new());
+        }
+
+        for parent in &commit.parents {
+            let parent_tree = commit::get_tree_hash(parent)?;
+            if filtered_tree(&parent_tree, pathspec, &mut trees)? == tree {
+                return Ok(vec![parent.to_string()]);
+            }
+        }
+        shown.insert(commit.hash.to_string());
+        Ok(commit.parents.clone())
+    })?;
+
+    Ok(rewrite_parents(commits, &shown))
+}
+
+// Commits changing a single file, following it across renames
+pub fn walk_follow(starts: &[String], path: &str) -> Result<Vec<Commit>, Error> {
+    let commits = walk(starts)?;
+    let mut path = path.to_string();
+    let mut shown = HashSet::new();
+    for commit in &commits {
+        let tree = read_tree::flatten_tree(&commit.tree)?;
+        let parent_tree = match commit.parents.first() {
+            Some(p) => read_tree::flatten_tree(&commit::get_tree_hash(p)?)?,
+            None => Vec::new(),
+        };
+
+        let find = |tree: &[read_tree::Entry], path: &str| {
+            tree.iter()
+                .find(|e| e.path == path)
+                .map(|e| (e.hash.to_string(), e.mode))
+        };
+        let latest = find(&tree, &path);
+        let oldest = find(&parent_tree, &path);
+        if latest != oldest {
+            shown.insert(commit.hash.to_string());
+        }
+
+        if let (Some((hash, _)), None) = (latest, oldest) {
+            if let Some(source) = find_rename_source(&hash, &parent_tree, &tree)? {
+                path = source;
+            }
+        }
+    }
+
+    Ok(rewrite_parents(commits, &shown))
+}
+
+// Path of the file deleted between the two trees the blob was renamed from
+fn find_rename_source(
+    hash: &str,
+    parent_tree: &[read_tree::Entry],
+    tree: &[read_tree::Entry],
+) -> Result<Option<String>, Error> {
+    let deleted: Vec<&read_tree::Entry> = parent_tree
+        .iter()
+        .filter(|e| tree.iter().all(|t| t.path != e.path))
+        .collect();
+    let mut data = Vec::new();
+    for entry in &deleted {
+        data.push(object::Object::new(&entry.hash)?.data);
+    }
+    let sources: Vec<rename::File> = deleted
+        .iter()
+        .zip(&data)
+        .map(|(e, data)| rename::File {
+            hash: &e.hash,
+            data,
+            deleted: true,
+        })
+        .collect();
+    let dest_data = object::Object::new(hash)?.data;
+    let dest = rename::File {
+        hash,
+        data: &dest_data,
+        deleted: false,
+    };
+
+    let mut opts = rename::Options::from_config();
+    opts.renames = true;
+    let source = rename::find(&sources, &[dest], &opts).remove(0);
+    Ok(source.map(|m| deleted[m.source].path.to_string()))
+}
+
+fn filtered_tree(
+    tree: &str,
+    pathspec: &Pathspec,
+    cache: &mut HashMap<String, Vec<(String, String, u32)>>,
+) -> Result<Vec<(String, String, u32)>, Error> {
+    if let Some(entries) = cache.get(tree) {
+        return Ok(entries.clone());
+    }
+
+    let entries: Vec<(String, String, u32)> = read_tree::flatten_tree(tree)?
+        .into_iter()
+        .filter(|e| pathspec.matches(&e.path))
+        .map(|e| (e.path, e.hash, e.mode))
+        .collect();
+    cache.insert(tree.to_string(), entries.clone());
+    Ok(entries)
+}
+
+// Keeps the `shown` commits, their parents become their closest shown
+// ancestors
+pub fn rewrite_parents(commits: Vec<Commit>, shown: &HashSet<String>) -> Vec<Commit> {
+    let mut nearest: HashMap<String, Vec<String>> = HashMap::new();
+    for commit in commits.iter().rev() {
+        let value = match shown.contains(&commit.hash) {
+            true => vec![commit.hash.to_string()],
+            false => nearest_parents(&commit.parents, &nearest),
+        };
+        nearest.insert(commit.hash.to_string(), value);
+    }
+
+    let mut res = Vec::new();
+    for mut commit in commits {
+        if shown.contains(&commit.hash) {
+            commit.parents = nearest_parents(&commit.parents, &nearest);
+            res.push(commit);
+        }
+    }
+    res
+}
+
+fn nearest_parents(parents: &[String], nearest: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    for parent in parents {
+        for p in nearest.get(parent).into_iter().flatten() {
+            if !res.contains(p) {
+                res.push(p.to_string());
+            }
+        }
+    }
+    res
+}
@@ -0,0 +1,233 @@
+// Shell glob matching as used by pathspecs, ignore and attribute patterns:
+// '*', '?', '[...]' and, when matching whole paths, '**' across directories
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Flags {
+    // '*' and '?' do not match '/'
+    pub pathname: bool,
+    pub casefold: bool,
+}
+
+pub fn wildmatch(pattern: &str, text: &str, flags: Flags) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text, flags)
+}
+
+pub fn has_glob_chars(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '\\'])
+}
+
+fn match_from(p: &[char], t: &[char], flags: Flags) -> bool {
+    let mut pi = 0;
+    let mut ti = 0;
+    while pi < p.len() {
+        match p[pi] {
+            '*' => {
+                let is_double = pi + 1 < p.len() && p[pi + 1] == '*';
+                if is_double && flags.pathname {
+                    // "**/" matches zero or more directories, a trailing "**"
+                    // matches everything left
+                    let mut rest = pi + 2;
+                    while rest < p.len() && p[rest] == '*' {
+                        rest += 1;
+                    }
+                    let at_dir_start = pi == 0 || p[pi - 1] == '/';
+                    if rest == p.len() {
+                        return true;
+                    }
+                    if at_dir_start && p[rest] == '/' {
+                        let rest = &p[rest + 1..];
+                        if match_from(rest, &t[ti..], flags) {
+                            return true;
+                        }
+                        return (ti..t.len())
+                            .filter(|&i| t[i] == '/')
+                            .any(|i| match_from(rest, &t[i + 1..], flags));
+                    }
+                    return (ti..=t.len()).any(|i| match_from(&p[rest..], &t[i..], flags));
+                }
+
+                let mut rest = pi + 1;
+                while rest < p.len() && p[rest] == '*' {
+                    rest += 1;
+                }
+                for i in ti..=t.len() {
+                    if match_from(&p[rest..], &t[i..], flags) {
+                        return true;
+                    }
+                    if i < t.len() && flags.pathname && t[i] == '/' {
+                        return false;
+                    }
+                }
+                return false;
+            }
+            '?' => {
+                if ti == t.len() || (flags.pathname && t[ti] == '/') {
+                    return false;
+                }
+                pi += 1;
+                ti += 1;
+            }
+            '[' => {
+                if ti == t.len() || (flags.pathname && t[ti] == '/') {
+                    return false;
+                }
+                match match_class(&p[pi..], t[ti], flags) {
+                    Some((true, len)) => {
+                        pi += len;
+                        ti += 1;
+                    }
+                    Some((false, _)) => return false,
+                    // No closing bracket, literal '['
+                    None => {
+                        if t[ti] != '[' {
+                            return false;
+                        }
+                        pi += 1;
+                        ti += 1;
+                    }
+                }
+            }
+            c => {
+                let (c, len) = match c == '\\' && pi + 1 < p.len() {
+                    true => (p[pi + 1], 2),
+                    false => (c, 1),
+                };
+                if ti == t.len() || !same_char(c, t[ti], flags) {
+                    return false;
+                }
+                pi += len;
+                ti += 1;
+            }
+        }
+    }
+
+    ti == t.len()
+}
+
+// Returns whether `c` is in the class and the class length in the pattern
+fn match_class(p: &[char], c: char, flags: Flags) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = i < p.len() && (p[i] == '!' || p[i] == '^');
+    if negated {
+        i += 1;
+    }
+
+    let mut found = false;
+    let mut first = true;
+    while i < p.len() {
+        if p[i] == ']' && !first {
+            return Some((found != negated, i + 1));
+        }
+        first = false;
+
+        if p[i] == '[' && i + 1 < p.len() && p[i + 1] == ':' {
+            // Without a class name, '[' is a character of the class
+            let rest: String = p[i..].iter().collect();
+            let end = rest[2..].find(":]").map(|end| end + 2);
+            if let Some(end) = end.filter(|&end| end > 2) {
+                let class = &rest[2..end];
+                found |= match class {
+                    "alnum" => c.is_alphanumeric(),
+                    "alpha" => c.is_alphabetic(),
+                    "digit" => c.is_ascii_digit(),
+                    "lower" => c.is_lowercase(),
+                    "upper" => c.is_uppercase(),
+                    "space" => c.is_whitespace(),
+                    "punct" => c.is_ascii_punctuation(),
+                    "xdigit" => c.is_ascii_hexdigit(),
+                    _ => false,
+                };
+                i += rest[..end + 2].chars().count();
+                continue;
+            }
+        }
+
+        let mut start = p[i];
+        if start == '\\' && i + 1 < p.len() {
+            i += 1;
+            start = p[i];
+        }
+        if i + 2 < p.len() && p[i + 1] == '-' && p[i + 2] != ']' {
+            let end = p[i + 2];
+            let in_range = |x: char| start <= x && x <= end;
+            found |= in_range(c) || (flags.casefold && (in_range(lower(c)) || in_range(upper(c))));
+            i += 3;
+        } else {
+            found |= same_char(start, c, flags);
+            i += 1;
+        }
+    }
+
+    None
+}
+
+fn same_char(a: char, b: char, flags: Flags) -> bool {
+    a == b || (flags.casefold && lower(a) == lower(b))
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn upper(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use wildmatch::{wildmatch, Flags};
+
+    fn path_match(pattern: &str, text: &str) -> bool {
+        let flags = Flags {
+            pathname: true,
+            casefold: false,
+        };
+        wildmatch(pattern, text, flags)
+    }
+
+    #[test]
+    fn simple_globs() {
+        assert!(path_match("*.rs", "main.rs"));
+        assert!(!path_match("*.rs", "src/main.rs"));
+        assert!(wildmatch("*.rs", "src/main.rs", Flags::default()));
+        assert!(path_match("file_?", "file_a"));
+        assert!(!path_match("file_?", "file_ab"));
+        assert!(path_match("[a-c]at", "bat"));
+        assert!(!path_match("[!a-c]at", "bat"));
+        assert!(path_match("\\*star", "*star"));
+        assert!(path_match("[[:digit:]]x", "1x"));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(path_match("**/foo", "foo"));
+        assert!(path_match("**/foo", "a/b/foo"));
+        assert!(path_match("a/**/b", "a/b"));
+        assert!(path_match("a/**/b", "a/x/y/b"));
+        assert!(path_match("a/**", "a/x/y"));
+        assert!(!path_match("a/**/b", "ab"));
+    }
+
+    #[test]
+    fn malformed_classes() {
+        // No class name: '[' and ':' are characters of the bracket
+        assert!(path_match("[[:]", "["));
+        assert!(path_match("[[:]", ":"));
+        assert!(!path_match("[[:]", "a"));
+        // Never closed: the bracket is taken literally
+        assert!(path_match("[[:x", "[[:x"));
+        assert!(!path_match("[[:x", "x"));
+    }
+
+    #[test]
+    fn casefold() {
+        let flags = Flags {
+            pathname: true,
+            casefold: true,
+        };
+        assert!(wildmatch("README*", "readme.md", flags));
+        assert!(wildmatch("[A-C]x", "bX", flags));
+    }
+}
@@ -1,22 +1,39 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::os::unix;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::str;
 
+use attributes::Attributes;
 use builtin::commit;
+use builtin::diff;
+use builtin::diff::Source;
 use builtin::read_tree;
+use convert::Converter;
+use ignore::Ignore;
 use index;
 use object;
 use object::Object;
+use pathspec::Pathspec;
 use refs;
+use rename;
+use xdiff;
+use xdiff::{Algorithm, Chunk};
 
 #[derive(Debug)]
 pub enum Error {
     CommitError(commit::Error),
+    DiffError(diff::Error),
     IndexError(index::Error),
     IoError(io::Error),
+    // Files with local changes and untracked files which would be
+    // overwritten
+    LocalChanges(Vec<String>, Vec<String>),
     ObjectError(object::Error),
+    // Items of a pathspec which match no file
+    PathspecNotMatched(Vec<String>),
     ReadTreeError(read_tree::Error),
 }
 
@@ -25,6 +42,11 @@ impl From<commit::Error> for Error {
         Error::CommitError(e)
     }
 }
+impl From<diff::Error> for Error {
+    fn from(e: diff::Error) -> Error {
+        Error::DiffError(e)
+    }
+}
 impl From<index::Error> for Error {
     fn from(e: index::Error) -> Error {
         Error::IndexError(e)
@@ -46,11 +68,26 @@ impl From<read_tree::Error> for Error {
     }
 }
 
+// How local changes are handled when another commit is checked out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checkout {
+    // Kept when the file is the same in both commits, the checkout being
+    // refused when they would be overwritten
+    Safe,
+    // Thrown away
+    Force,
+    // Merged into the files of the new commit
+    Merge,
+}
+
 #[derive(Debug)]
 pub struct Change {
     pub state: State,
     pub path: String,
     pub hash: String,
+    pub old_hash: String,
+    pub mode: u32,
+    pub old_mode: u32,
 }
 
 #[derive(Debug, PartialEq)]
@@ -59,34 +96,48 @@ pub enum State {
     New,
     Deleted,
     Same,
+    // `old_hash` and `old_mode` are the ones of the source file
+    Renamed { from: String, score: u32 },
+    Copied { from: String, score: u32 },
+}
+
+impl Change {
+    // Path of the file before the change
+    pub fn old_path(&self) -> &str {
+        match &self.state {
+            State::Renamed { from, .. } | State::Copied { from, .. } => from,
+            _ => &self.path,
+        }
+    }
+}
+
+pub fn read_commit_tree(commit: &str) -> Result<Vec<read_tree::Entry>, Error> {
+    if commit.is_empty() {
+        return Ok(Vec::new());
+    }
+    let tree_hash = commit::get_tree_hash(commit)?;
+    Ok(read_tree::flatten_tree(&tree_hash)?)
 }
 
 pub fn diff_from_commit(oldest: &str, latest: &str) -> Result<Vec<Change>, Error> {
-    let tree_hash = match oldest.is_empty() {
-        true => String::new(),
-        false => commit::get_tree_hash(&oldest)?,
-    };
-    let oldest_tree = match tree_hash.is_empty() {
-        true => Vec::new(),
-        false => read_tree::read_tree(&tree_hash)?,
-    };
-    let tree_hash = commit::get_tree_hash(&latest)?;
-    let latest_tree = read_tree::read_tree(&tree_hash)?;
+    let oldest_tree = read_commit_tree(oldest)?;
+    let latest_tree = read_commit_tree(latest)?;
 
     let mut changes = Vec::new();
     for entry in &latest_tree {
         match oldest_tree.iter().find(|e| entry.path == e.path) {
             Some(e) => {
-                let oldest_obj = Object::new(&e.hash)?;
-                let latest_obj = Object::new(&entry.hash)?;
-                let state = match oldest_obj.data != latest_obj.data {
+                let state = match e.hash != entry.hash || e.mode != entry.mode {
                     true => State::Modified,
                     false => State::Same,
                 };
                 changes.push(Change {
-                    state: state,
-                    path: e.path.to_string(),
-                    hash: e.hash.to_string(),
+                    state,
+                    path: entry.path.to_string(),
+                    hash: entry.hash.to_string(),
+                    old_hash: e.hash.to_string(),
+                    mode: entry.mode,
+                    old_mode: e.mode,
                 });
             }
 
@@ -94,6 +145,9 @@ pub fn diff_from_commit(oldest: &str, latest: &str) -> Result<Vec<Change>, Error
                 state: State::New,
                 path: entry.path.to_string(),
                 hash: entry.hash.to_string(),
+                old_hash: String::new(),
+                mode: entry.mode,
+                old_mode: 0,
             }),
         }
     }
@@ -105,6 +159,9 @@ pub fn diff_from_commit(oldest: &str, latest: &str) -> Result<Vec<Change>, Error
                 state: State::Deleted,
                 path: entry.path.to_string(),
                 hash: entry.hash.to_string(),
+                old_hash: entry.hash.to_string(),
+                mode: entry.mode,
+                old_mode: entry.mode,
             });
         }
     }
@@ -112,108 +169,1012 @@ pub fn diff_from_commit(oldest: &str, latest: &str) -> Result<Vec<Change>, Error
     Ok(changes)
 }
 
-pub fn update_from_commit(commit: &str) -> Result<(), Error> {
-    let cur_commit = refs::get_ref_hash("HEAD")?;
-    let changes = diff_from_commit(&cur_commit, &commit)?;
+// (hash, mode) of a file
+type FileId = (String, u32);
 
-    let mut new_index = Vec::new();
-    for change in changes {
-        update_single_change(&change)?;
-        if change.state != State::Deleted {
-            let entry = index::Entry::new(&change.path)?;
-            new_index.push(entry);
+// (hash, mode) of the files of a commit by path
+fn read_commit_files(commit: &str) -> Result<HashMap<String, FileId>, Error> {
+    Ok(read_commit_tree(commit)?
+        .into_iter()
+        .map(|e| (e.path, (e.hash, e.mode)))
+        .collect())
+}
+
+// Files whose index entry differs from HEAD, and files of the working dir
+// which differ from their entry with their (hash, mode), None when deleted
+type LocalFiles = (HashSet<String>, HashMap<String, Option<FileId>>);
+
+fn read_local_changes(head: &str) -> Result<LocalFiles, Error> {
+    index::refresh()?;
+    let all = Pathspec::new(&[]);
+    let no_renames = rename::Options::new();
+    let head = Source::Tree(head.to_string());
+    let staged = diff::diff_sources(&head, &Source::Index, &all, &no_renames)?
+        .iter()
+        .map(|pair| pair.path().to_string())
+        .collect();
+    let unstaged = diff::diff_sources(&Source::Index, &Source::WorkDir, &all, &no_renames)?
+        .into_iter()
+        .map(|pair| {
+            let path = pair.path().to_string();
+            (path, pair.new.map(|b| (b.hash, b.mode)))
+        })
+        .collect();
+    Ok((staged, unstaged))
+}
+
+// Whether an untracked file can be replaced by a blob: when it is ignored or
+// already has its content
+fn can_overwrite(
+    path: &str,
+    (hash, mode): &FileId,
+    ignore: &mut Ignore,
+    converter: &Converter,
+) -> Result<bool, Error> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(true),
+    };
+    // The files of a directory are checked by untracked_in_the_way
+    if meta.is_dir() {
+        return Ok(true);
+    }
+    ignore.read_parents(path)?;
+    if ignore.is_ignored(path, meta.is_dir()) {
+        return Ok(true);
+    }
+    let file_mode = index::mode_of(&meta, Some(*mode), converter.trust_executable_bit());
+    match diff::Blob::from_file(path, file_mode, converter) {
+        Ok(blob) => Ok(&blob.hash == hash && file_mode == *mode),
+        Err(_) => Ok(false),
+    }
+}
+
+// Untracked files in the way of a file to write, with whether they are
+// ignored: a file where one of its parent directories goes, or the files of
+// a directory where it goes. Tracked files are removed before.
+fn untracked_in_the_way(
+    path: &str,
+    mode: u32,
+    entries: &BTreeMap<String, index::Entry>,
+    ignore: &mut Ignore,
+) -> Result<Vec<(String, bool)>, Error> {
+    let mut res = Vec::new();
+    for file in paths_in_the_way(path, mode, &|p| entries.contains_key(p))? {
+        ignore.read_parents(&file)?;
+        let is_ignored = ignore.is_ignored(&file, false);
+        res.push((file, is_ignored));
+    }
+    Ok(res)
+}
+
+// Paths which prevent a file from being written, once the ones for which
+// `is_removed` is true are removed
+fn paths_in_the_way(
+    path: &str,
+    mode: u32,
+    is_removed: &dyn Fn(&str) -> bool,
+) -> io::Result<Vec<String>> {
+    let mut res = Vec::new();
+    for (i, _) in path.match_indices('/') {
+        let parent = &path[..i];
+        match fs::symlink_metadata(parent) {
+            Ok(ref meta) if meta.is_dir() => continue,
+            Ok(_) if !is_removed(parent) => res.push(parent.to_string()),
+            _ => (),
         }
+        return Ok(res);
     }
 
-    index::write_entries(new_index)?;
-    Ok(())
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    if is_dir && mode != index::MODE_GITLINK {
+        let mut dirs = vec![path.to_string()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                } else if !is_removed(&path) {
+                    res.push(path);
+                }
+            }
+        }
+        res.sort();
+    }
+    Ok(res)
 }
 
-pub fn update_from_merge(commit1: &str, commit2: &str) -> Result<(), Error> {
-    let common_ancestor = commit::lowest_common_ancestor(&commit1, &commit2)?;
-    let changes1 = diff_from_commit(&common_ancestor, &commit1)?;
-    let changes2 = diff_from_commit(&common_ancestor, &commit2)?;
+// Checks out a commit, two-tree merge style: files which are the same in
+// HEAD and in the commit keep their local changes, the other ones are only
+// updated when they have none (unless forced or merged). Returns the files
+// left with conflicts by a merge.
+pub fn update_from_commit(commit: &str, mode: Checkout) -> Result<Vec<String>, Error> {
+    let head = refs::get_ref_hash("HEAD")?;
+    let old_files = read_commit_files(&head)?;
+    let new_files = read_commit_files(commit)?;
+    let (staged, unstaged) = read_local_changes(&head)?;
+    let converter = Converter::load()?;
+    let mut ignore = Ignore::standard()?;
+    let mut entries: BTreeMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
 
-    let mut new_index = Vec::new();
-    for change in &changes1 {
-        match changes2.iter().find(|c| c.path == change.path) {
-            Some(c) => {
-                let obj1 = Object::new(&change.hash)?;
-                let obj2 = Object::new(&c.hash)?;
-                if obj1.data != obj2.data {
-                    // Merge conflict (no merge at all or intelligent conflict
-                    // marker, just mark everything as conflict)
-                    let content1 = str::from_utf8(&obj1.data).unwrap();
-                    let content2 = str::from_utf8(&obj2.data).unwrap();
-                    let conflict = format!(
-                        "<<<<<< {}\n{}\n======\n{}\n>>>>>> {}",
-                        commit1, content1, content2, commit2
-                    );
-                    fs::write(&change.path, conflict)?;
+    let mut paths: BTreeSet<String> = entries.keys().cloned().collect();
+    paths.extend(old_files.keys().cloned());
+    paths.extend(new_files.keys().cloned());
+
+    // Files to update, the ones with local changes to merge, and untracked
+    // files in the way which can be removed
+    let mut updates = Vec::new();
+    let mut merges = Vec::new();
+    let mut removals = Vec::new();
+    let mut changed = Vec::new();
+    let mut untracked = Vec::new();
+    for path in &paths {
+        let old = old_files.get(path);
+        let new = new_files.get(path);
+        let entry = entries.get(path).map(|e| (e.hash.to_string(), e.mode));
+        let is_dirty = staged.contains(path) || unstaged.contains_key(path);
+        if mode == Checkout::Force {
+            if entry.as_ref() != new || is_dirty || (entry.is_none() && new.is_some()) {
+                updates.push(path.as_str());
+                if let Some((_, new_mode)) = new {
+                    let in_the_way = untracked_in_the_way(path, *new_mode, &entries, &mut ignore)?;
+                    removals.extend(in_the_way.into_iter().map(|(file, _)| file));
                 }
+            }
+            continue;
+        }
+        if old == new || entry.as_ref() == new {
+            continue;
+        }
+
+        // Deleted on both sides
+        let is_deleted = new.is_none() && unstaged.get(path) == Some(&None);
+        if !is_dirty || (is_deleted && !staged.contains(path)) {
+            // A directory of tracked files is replaced once they are removed
+            if let (None, Some(new), false) = (&entry, new, is_tracked_dir(&entries, path)) {
+                if !can_overwrite(path, new, &mut ignore, &converter)? {
+                    untracked.push(path.to_string());
+                    continue;
+                }
+            }
+            if let Some((_, new_mode)) = new {
+                for (file, is_ignored) in
+                    untracked_in_the_way(path, *new_mode, &entries, &mut ignore)?
+                {
+                    match is_ignored {
+                        true => removals.push(file),
+                        false => untracked.push(file),
+                    }
+                }
+            }
+            updates.push(path.as_str());
+        } else if mode == Checkout::Merge {
+            merges.push(path);
+        } else {
+            changed.push(path.to_string());
+        }
+    }
+    untracked.sort();
+    untracked.dedup();
+    if !changed.is_empty() || !untracked.is_empty() {
+        return Err(Error::LocalChanges(changed, untracked));
+    }
+
+    updates.extend(removals.iter().map(|p| p.as_str()));
+    apply_files(&updates, &new_files, &mut entries, (true, true), &converter)?;
 
-                let entry = index::Entry::new(&change.path)?;
-                new_index.push(entry);
+    let mut conflicts = Vec::new();
+    for path in merges {
+        let new = new_files.get(path);
+        let merged = merge_local_changes(path, (old_files.get(path), new), commit, &converter)?;
+        if !merged {
+            conflicts.push(path.to_string());
+        }
+        match new {
+            Some((hash, mode)) => {
+                let mut entry = index::Entry::with_hash(path, *mode, hash)?;
+                // The file keeps changes, its stat data must not match
+                entry.size = 0;
+                entries.insert(path.to_string(), entry);
             }
             None => {
-                update_single_change(&change)?;
-                if change.state != State::Deleted {
-                    let entry = index::Entry::new(&change.path)?;
-                    new_index.push(entry);
+                entries.remove(path);
+            }
+        }
+    }
+
+    index::write_entries(entries.into_values().collect())?;
+    Ok(conflicts)
+}
+
+// How the index and the working dir follow a reset of HEAD
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reset {
+    // Both are left alone
+    Soft,
+    // Only the index is reset
+    Mixed,
+    // Both are reset, local changes are thrown away
+    Hard,
+    // Both are reset, but unstaged changes are kept for files whose index
+    // entry is already the one of the commit
+    Merge,
+    // The index is reset, files which differ between HEAD and the commit are
+    // updated when they have no local changes
+    Keep,
+}
+
+// Resets the index and the working dir to a commit, before HEAD is moved to it
+pub fn reset_to_commit(commit: &str, mode: Reset) -> Result<(), Error> {
+    match mode {
+        Reset::Soft => return Ok(()),
+        Reset::Hard => {
+            update_from_commit(commit, Checkout::Force)?;
+            return Ok(());
+        }
+        _ => (),
+    }
+
+    let head = refs::get_ref_hash("HEAD")?;
+    let old_files = read_commit_files(&head)?;
+    let new_files = read_commit_files(commit)?;
+    let (_, unstaged) = read_local_changes(&head)?;
+    let converter = Converter::load()?;
+    let mut ignore = Ignore::standard()?;
+    let mut entries: BTreeMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
+
+    let mut paths: BTreeSet<String> = entries.keys().cloned().collect();
+    paths.extend(old_files.keys().cloned());
+    paths.extend(new_files.keys().cloned());
+
+    // Files updated in the index only, in both, and untracked files in the
+    // way which can be removed
+    let mut index_updates = Vec::new();
+    let mut updates = Vec::new();
+    let mut removals = Vec::new();
+    let mut changed = Vec::new();
+    let mut untracked = Vec::new();
+    for path in &paths {
+        let old = old_files.get(path);
+        let new = new_files.get(path);
+        let entry = entries.get(path).map(|e| (e.hash.to_string(), e.mode));
+        let is_dirty = unstaged.contains_key(path);
+        let is_up_to_date = entry.as_ref() == new;
+        let update = match mode {
+            _ if is_up_to_date => None,
+            Reset::Mixed => Some(false),
+            Reset::Merge if !is_dirty => Some(true),
+            Reset::Keep if old == new => Some(false),
+            Reset::Keep if !is_dirty && entry.as_ref() == old => Some(true),
+            _ => {
+                changed.push(path.to_string());
+                None
+            }
+        };
+        match update {
+            Some(true) => {
+                if let (None, Some(new), false) = (&entry, new, is_tracked_dir(&entries, path)) {
+                    if !can_overwrite(path, new, &mut ignore, &converter)? {
+                        untracked.push(path.to_string());
+                        continue;
+                    }
+                }
+                if let Some((_, new_mode)) = new {
+                    for (file, is_ignored) in
+                        untracked_in_the_way(path, *new_mode, &entries, &mut ignore)?
+                    {
+                        match is_ignored {
+                            true => removals.push(file),
+                            false => untracked.push(file),
+                        }
+                    }
                 }
+                updates.push(path.as_str());
+            }
+            Some(false) => index_updates.push(path.as_str()),
+            None => (),
+        }
+    }
+    untracked.sort();
+    untracked.dedup();
+    if !changed.is_empty() || !untracked.is_empty() {
+        return Err(Error::LocalChanges(changed, untracked));
+    }
+
+    updates.extend(removals.iter().map(|p| p.as_str()));
+    apply_files(
+        &index_updates,
+        &new_files,
+        &mut entries,
+        (true, false),
+        &converter,
+    )?;
+    apply_files(&updates, &new_files, &mut entries, (true, true), &converter)?;
+    index::write_entries(entries.into_values().collect())?;
+    // Entries whose file was left alone get their stat data back
+    index::refresh()?;
+    Ok(())
+}
+
+// Whether a path is a directory holding tracked files
+fn is_tracked_dir(entries: &BTreeMap<String, index::Entry>, path: &str) -> bool {
+    let dir = format!("{}/", path);
+    entries
+        .range(dir.to_string()..)
+        .next()
+        .is_some_and(|(p, _)| p.starts_with(&dir))
+}
+
+// Restores the files matching a pathspec from a commit (from the index when
+// there is none) to the index and/or the working dir. In overlay mode, the
+// files which are not in the source are left alone instead of being removed.
+// Returns the paths restored.
+pub fn restore_files(
+    source: Option<&str>,
+    pathspec: &Pathspec,
+    (to_index, to_work_dir): (bool, bool),
+    overlay: bool,
+) -> Result<Vec<String>, Error> {
+    let mut entries: BTreeMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
+    let files = match source {
+        Some(commit) => read_commit_files(commit)?,
+        None => entries
+            .values()
+            .map(|e| (e.path.to_string(), (e.hash.to_string(), e.mode)))
+            .collect(),
+    };
+
+    let mut paths: BTreeSet<String> = files
+        .keys()
+        .filter(|p| pathspec.matches(p))
+        .cloned()
+        .collect();
+    if !overlay {
+        // Files of the working dir are only removed when tracked
+        paths.extend(entries.keys().filter(|p| pathspec.matches(p)).cloned());
+    }
+    let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+    let unmatched = pathspec.unmatched(&paths);
+    if !unmatched.is_empty() {
+        return Err(Error::PathspecNotMatched(unmatched));
+    }
+
+    let converter = Converter::load()?;
+    apply_files(
+        &paths,
+        &files,
+        &mut entries,
+        (to_index, to_work_dir),
+        &converter,
+    )?;
+    index::write_entries(entries.into_iter().map(|(_, e)| e).collect())?;
+    if !to_work_dir {
+        // Entries whose file already has the content get their stat data back
+        index::refresh()?;
+    }
+    Ok(paths.iter().map(|p| p.to_string()).collect())
+}
+
+// Sets files to their (hash, mode) in `files` in the index and/or the
+// working dir, the ones missing from it being removed
+fn apply_files(
+    paths: &[&str],
+    files: &HashMap<String, FileId>,
+    entries: &mut BTreeMap<String, index::Entry>,
+    (to_index, to_work_dir): (bool, bool),
+    converter: &Converter,
+) -> Result<(), Error> {
+    // Nothing is changed when a file cannot be written
+    if to_work_dir {
+        let removed: HashSet<&str> = paths
+            .iter()
+            .filter(|p| !files.contains_key(**p))
+            .cloned()
+            .collect();
+        let mut in_the_way = Vec::new();
+        for &path in paths {
+            if let Some((_, mode)) = files.get(path) {
+                in_the_way.extend(paths_in_the_way(path, *mode, &|p| removed.contains(p))?);
             }
         }
+        if !in_the_way.is_empty() {
+            in_the_way.sort();
+            in_the_way.dedup();
+            return Err(Error::LocalChanges(Vec::new(), in_the_way));
+        }
     }
 
-    for change in &changes2 {
-        let not_seen = changes1.iter().all(|c| c.path != change.path);
-        if not_seen {
-            update_single_change(&change)?;
-            if change.state != State::Deleted {
-                let entry = index::Entry::new(&change.path)?;
-                new_index.push(entry);
+    // Files are removed first, so that directories can take their place
+    for &path in paths {
+        if !files.contains_key(path) {
+            if to_work_dir {
+                remove_path(path)?;
+            }
+            if to_index {
+                entries.remove(path);
             }
         }
     }
 
+    for &path in paths {
+        let (hash, mode) = match files.get(path) {
+            Some((hash, mode)) => (hash, *mode),
+            None => continue,
+        };
+        if to_work_dir {
+            write_blob(path, hash, mode, converter)?;
+        }
+        if to_index {
+            let entry = match to_work_dir {
+                true => index::Entry::with_hash(path, mode, hash)?,
+                false => index::Entry::without_stat(path, mode, hash),
+            };
+            entries.insert(path.to_string(), entry);
+        } else if to_work_dir {
+            // The stat data of an entry whose file was written is refreshed
+            if let Some(entry) = entries.get_mut(path) {
+                if &entry.hash == hash && entry.mode == mode {
+                    entry.refresh_stat(&fs::symlink_metadata(path)?);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Three-way merge of the file of the working dir with the changes between
+// the two commits. Returns false when it conflicts, in which case the local
+// file is kept when it cannot be merged.
+fn merge_local_changes(
+    path: &str,
+    (old, new): (Option<&FileId>, Option<&FileId>),
+    commit: &str,
+    converter: &Converter,
+) -> Result<bool, Error> {
+    let meta = fs::symlink_metadata(path).ok();
+    let local_mode = meta.map(|m| index::mode_of(&m, None, converter.trust_executable_bit()));
+    let is_file = |mode: u32| mode == index::MODE_FILE || mode == index::MODE_EXECUTABLE;
+    let ((old_hash, old_mode), (new_hash, new_mode), local_mode) = match (old, new, local_mode) {
+        (Some(old), Some(new), Some(local))
+            if is_file(old.1) && is_file(new.1) && is_file(local) =>
+        {
+            (old, new, local)
+        }
+        // Deleted locally, the new version is written
+        (_, Some((hash, mode)), None) => {
+            write_blob(path, hash, *mode, converter)?;
+            return Ok(false);
+        }
+        _ => return Ok(false),
+    };
+
+    let base = Object::new(old_hash)?.data;
+    let theirs = Object::new(new_hash)?.data;
+    let ours = index::read_blob_data(path, local_mode, converter)?;
+    let (merged, has_conflicts) = merge_file(&base, &ours, &theirs, ("local", commit));
+    let mode = match local_mode == *old_mode {
+        true => *new_mode,
+        false => local_mode,
+    };
+    write_file(path, &converter.to_work_tree(path, merged)?, mode)?;
+    Ok(!has_conflicts)
+}
+
+// Message listing the files which prevent a checkout or a merge
+pub fn format_local_changes(changed: &[String], untracked: &[String], action: &str) -> String {
+    let doing = match action {
+        "merge" | "reset" => action,
+        _ => "switch branches",
+    };
+    let mut res = String::new();
+    if !changed.is_empty() {
+        res.push_str(&format!(
+            "error: Your local changes to the following files would be overwritten by {}:\n",
+            action
+        ));
+        for path in changed {
+            res.push_str(&format!("\t{}\n", path));
+        }
+        res.push_str(&format!(
+            "Please commit your changes or stash them before you {}.\n",
+            doing
+        ));
+    }
+    if !untracked.is_empty() {
+        res.push_str(&format!(
+            "error: The following untracked working tree files would be overwritten by {}:\n",
+            action
+        ));
+        for path in untracked {
+            res.push_str(&format!("\t{}\n", path));
+        }
+        res.push_str(&format!(
+            "Please move or remove them before you {}.\n",
+            doing
+        ));
+    }
+    res.push_str("Aborting\n");
+    res
+}
+
+// Turns the New changes coming from Deleted (or, for copies, Modified)
+// files into Renamed or Copied ones, the Deleted changes of renamed files are
+// removed
+pub fn detect_renames(changes: &mut Vec<Change>, opts: &rename::Options) -> Result<(), Error> {
+    if !opts.renames {
+        return Ok(());
+    }
+
+    // Submodules are not renamed
+    let is_gitlink = |i: usize| changes[i].mode == index::MODE_GITLINK;
+    let sources: Vec<usize> = (0..changes.len())
+        .filter(|&i| !is_gitlink(i))
+        .filter(|&i| match changes[i].state {
+            State::Deleted => true,
+            State::Modified => opts.copies,
+            _ => false,
+        })
+        .collect();
+    let dests: Vec<usize> = (0..changes.len())
+        .filter(|&i| !is_gitlink(i) && changes[i].state == State::New)
+        .collect();
+    if dests.is_empty() {
+        return Ok(());
+    }
+
+    let mut source_data = Vec::new();
+    for &i in &sources {
+        source_data.push(Object::new(&changes[i].old_hash)?.data);
+    }
+    let mut dest_data = Vec::new();
+    for &i in &dests {
+        dest_data.push(Object::new(&changes[i].hash)?.data);
+    }
+    let source_files: Vec<rename::File> = sources
+        .iter()
+        .zip(&source_data)
+        .map(|(&i, data)| rename::File {
+            hash: &changes[i].old_hash,
+            data,
+            deleted: changes[i].state == State::Deleted,
+        })
+        .collect();
+    let dest_files: Vec<rename::File> = dests
+        .iter()
+        .zip(&dest_data)
+        .map(|(&i, data)| rename::File {
+            hash: &changes[i].hash,
+            data,
+            deleted: false,
+        })
+        .collect();
+    let matches = rename::find(&source_files, &dest_files, opts);
+
+    let mut renamed = Vec::new();
+    for (d, m) in matches.into_iter().enumerate() {
+        let m = match m {
+            Some(m) => m,
+            None => continue,
+        };
+        let source = sources[m.source];
+        let from = changes[source].path.to_string();
+        let (old_hash, old_mode) = (
+            changes[source].old_hash.to_string(),
+            changes[source].old_mode,
+        );
+        let change = &mut changes[dests[d]];
+        change.old_hash = old_hash;
+        change.old_mode = old_mode;
+        change.state = match m.is_copy {
+            true => State::Copied {
+                from,
+                score: m.score,
+            },
+            false => {
+                renamed.push(source);
+                State::Renamed {
+                    from,
+                    score: m.score,
+                }
+            }
+        };
+    }
+
+    let mut i = 0;
+    changes.retain(|_| {
+        i += 1;
+        !renamed.contains(&(i - 1))
+    });
+    Ok(())
+}
+
+// Returns the paths left with conflicts
+pub fn update_from_merge(commit1: &str, commit2: &str) -> Result<Vec<String>, Error> {
+    let common_ancestor = commit::lowest_common_ancestor(&commit1, &commit2)?;
+    merge_from_base(&common_ancestor, (commit1, commit2), (commit1, commit2))
+}
+
+// Merges the changes of `commit2` since `base` into the working dir, where
+// `commit1` is checked out, `labels` naming the sides of the conflicts.
+// Returns the paths left with conflicts.
+pub fn merge_from_base(
+    base: &str,
+    (commit1, commit2): (&str, &str),
+    labels: (&str, &str),
+) -> Result<Vec<String>, Error> {
+    let mut changes1 = diff_from_commit(base, commit1)?;
+    let mut changes2 = diff_from_commit(base, commit2)?;
+    let mut renames = rename::Options::from_config();
+    renames.renames = true;
+    renames.copies = false;
+    detect_renames(&mut changes1, &renames)?;
+    detect_renames(&mut changes2, &renames)?;
+    let attributes = Attributes::new();
+    let converter = Converter::load()?;
+
+    // Local changes are kept when the merge does not touch their file, but
+    // the index has to match HEAD
+    let entries: HashMap<String, index::Entry> = index::read_entries()?
+        .into_iter()
+        .map(|e| (e.path.to_string(), e))
+        .collect();
+    let (staged, unstaged) = read_local_changes(commit1)?;
+    let touched: HashSet<&str> = changes2
+        .iter()
+        .filter(|c| c.state != State::Same)
+        .flat_map(|c| vec![c.path.as_str(), c.old_path()])
+        .collect();
+    let mut changed: Vec<String> = staged.into_iter().collect();
+    changed.extend(
+        unstaged
+            .keys()
+            .filter(|p| touched.contains(p.as_str()))
+            .cloned(),
+    );
+    changed.sort();
+    changed.dedup();
+    let mut ignore = Ignore::standard()?;
+    let mut untracked = Vec::new();
+    for c in changes2.iter().filter(|c| c.state != State::Deleted) {
+        let is_tracked = entries.contains_key(&c.path);
+        let blob = (c.hash.to_string(), c.mode);
+        if !is_tracked && !can_overwrite(&c.path, &blob, &mut ignore, &converter)? {
+            untracked.push(c.path.to_string());
+        }
+    }
+    if !changed.is_empty() || !untracked.is_empty() {
+        return Err(Error::LocalChanges(changed, untracked));
+    }
+
+    // Changes are matched on the path of the file in the common ancestor
+    let mut base_paths: Vec<&str> = changes1
+        .iter()
+        .chain(changes2.iter())
+        .map(|c| c.old_path())
+        .collect();
+    base_paths.sort();
+    base_paths.dedup();
+
+    let mut conflicts = Vec::new();
+    for base_path in base_paths {
+        let ours = changes1.iter().find(|c| c.old_path() == base_path);
+        let theirs = changes2.iter().find(|c| c.old_path() == base_path);
+        let sides = (ours, theirs);
+        let conflict = merge_change(base_path, sides, labels, &attributes, &converter)?;
+        if conflict {
+            conflicts.push(base_path.to_string());
+        }
+    }
+
+    let mut paths: Vec<&str> = changes1
+        .iter()
+        .chain(changes2.iter())
+        .flat_map(|c| vec![c.path.as_str(), c.old_path()])
+        .collect();
+    paths.sort();
+    paths.dedup();
+    let mut new_index = Vec::new();
+    for path in paths {
+        if !touched.contains(path) {
+            if let Some(entry) = entries.get(path) {
+                new_index.push(entry.clone());
+                continue;
+            }
+        }
+        if fs::symlink_metadata(path).is_ok() {
+            let known_mode = changes1
+                .iter()
+                .chain(changes2.iter())
+                .find(|c| c.path == path && c.state != State::Deleted)
+                .map(|c| c.mode);
+            new_index.push(index::Entry::new(path, known_mode, &converter)?);
+        }
+    }
+
     index::write_entries(new_index)?;
+    Ok(conflicts)
+}
+
+// Path, blob and mode of the file after the change, None when deleted
+fn result_of(change: Option<&Change>) -> Option<(&str, &str, u32)> {
+    match change {
+        Some(c) if c.state != State::Deleted => Some((&c.path, &c.hash, c.mode)),
+        _ => None,
+    }
+}
+
+// Applies their change of a file on top of ours (which are in the working
+// dir already). Returns whether it conflicts.
+fn merge_change(
+    base_path: &str,
+    (ours, theirs): (Option<&Change>, Option<&Change>),
+    labels: (&str, &str),
+    attributes: &Attributes,
+    converter: &Converter,
+) -> Result<bool, Error> {
+    let (base_hash, base_mode) = match ours.or(theirs) {
+        Some(c) if c.state != State::New => (Some(c.old_hash.as_str()), c.old_mode),
+        _ => (None, 0),
+    };
+    let base = base_hash.map(|hash| (base_path, hash, base_mode));
+    let (our_result, their_result) = (result_of(ours), result_of(theirs));
+    if our_result == their_result || their_result == base {
+        return Ok(false);
+    }
+    if our_result == base {
+        if let Some((path, _, _)) = our_result {
+            remove_path(path)?;
+        }
+        if let Some((path, hash, mode)) = their_result {
+            write_blob(path, hash, mode, converter)?;
+        }
+        return Ok(false);
+    }
+
+    let ((our_path, our_hash, our_mode), (their_path, their_hash, their_mode)) =
+        match (our_result, their_result) {
+            (Some(o), Some(t)) => (o, t),
+            (None, None) => return Ok(false),
+            // Modified on one side and deleted on the other, the modified
+            // version is kept
+            (None, Some((path, hash, mode))) => {
+                write_blob(path, hash, mode, converter)?;
+                return Ok(true);
+            }
+            (Some(_), None) => return Ok(true),
+        };
+
+    // Renamed on both sides to different paths, ours is kept
+    if our_path != base_path && their_path != base_path && our_path != their_path {
+        return Ok(true);
+    }
+    let path = match our_path != base_path {
+        true => our_path,
+        false => their_path,
+    };
+    if path != our_path {
+        remove_path(our_path)?;
+    }
+    let mode = match our_mode == base_mode {
+        true => their_mode,
+        false => our_mode,
+    };
+    if our_hash == their_hash {
+        write_blob(path, our_hash, mode, converter)?;
+        return Ok(false);
+    }
+
+    // Different links or submodule commits cannot be merged, ours is kept
+    let is_file = |mode: u32| mode == index::MODE_FILE || mode == index::MODE_EXECUTABLE;
+    if !is_file(our_mode) || !is_file(their_mode) {
+        write_blob(path, our_hash, our_mode, converter)?;
+        return Ok(true);
+    }
+
+    let base = match base_hash {
+        Some(hash) if is_file(base_mode) => Object::new(hash)?.data,
+        _ => Vec::new(),
+    };
+    let ours = Object::new(our_hash)?.data;
+    let theirs = Object::new(their_hash)?.data;
+    let is_binary = attributes.is_unset(path, "merge")
+        || [&base, &ours, &theirs].iter().any(|d| diff::is_binary(d));
+    if is_binary {
+        // Whole file conflict, our version is kept
+        write_file(path, &converter.to_work_tree(path, ours)?, mode)?;
+        return Ok(true);
+    }
+
+    let (merged, has_conflicts) = merge_file(&base, &ours, &theirs, labels);
+    write_file(path, &converter.to_work_tree(path, merged)?, mode)?;
+    Ok(has_conflicts)
+}
+
+// Checks out a blob, or creates the directory of a submodule
+pub fn write_blob(path: &str, hash: &str, mode: u32, converter: &Converter) -> Result<(), Error> {
+    if mode == index::MODE_GITLINK {
+        fs::create_dir_all(path)?;
+        return Ok(());
+    }
+    let blob = Object::new(hash)?;
+    let data = match mode {
+        index::MODE_SYMLINK => blob.data,
+        _ => converter.to_work_tree(path, blob.data)?,
+    };
+    write_file(path, &data, mode)
+}
+
+// Writes a regular file with the executable bit of its mode, or a symbolic
+// link whose target is `data`
+fn write_file(path: &str, data: &[u8], mode: u32) -> Result<(), Error> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Writing to a link would change the file it points to, and a directory
+    // left after its files were removed is replaced by the file
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.is_dir() => fs::remove_dir(path)?,
+        Ok(ref meta) if meta.file_type().is_symlink() || mode == index::MODE_SYMLINK => {
+            fs::remove_file(path)?
+        }
+        _ => (),
+    }
+
+    if mode == index::MODE_SYMLINK {
+        let target = String::from_utf8_lossy(data).to_string();
+        unix::fs::symlink(target, path)?;
+        return Ok(());
+    }
+    fs::write(path, data)?;
+    let mut permissions = fs::metadata(path)?.permissions();
+    let file_mode = permissions.mode();
+    let file_mode = match mode == index::MODE_EXECUTABLE {
+        // Executable by whoever can read the file
+        true => file_mode | (file_mode & 0o444) >> 2,
+        false => file_mode & !0o111,
+    };
+    if file_mode != permissions.mode() {
+        permissions.set_mode(file_mode);
+        fs::set_permissions(path, permissions)?;
+    }
     Ok(())
 }
 
-fn update_single_change(change: &Change) -> Result<(), Error> {
-    match change.state {
-        State::New | State::Modified | State::Same => {
-            let blob = Object::new(&change.hash)?;
-            fs::write(&change.path, blob.data)?;
+// Removes a file, a link or the directory of a submodule, which is left in
+// place when the submodule is checked out. The directories left empty are
+// removed too.
+pub fn remove_path(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.is_dir() => {
+            let _ = fs::remove_dir(path);
         }
-        State::Deleted => fs::remove_file(&change.path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
     }
 
+    let mut parent = Path::new(path).parent();
+    while let Some(dir) = parent {
+        if dir.as_os_str().is_empty() || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
     Ok(())
 }
 
-pub fn get_all_files_path() -> io::Result<Vec<String>> {
-    let mut files = Vec::new();
+// Line based three-way merge, conflicting parts are put between markers.
+// Also returns whether there was any conflict.
+pub fn merge_file(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: (&str, &str),
+) -> (Vec<u8>, bool) {
+    let split = |data: &[u8]| -> Vec<Vec<u8>> {
+        data.split_inclusive(|&b| b == b'\n')
+            .map(|l| l.to_vec())
+            .collect()
+    };
+    let (base, ours, theirs) = (split(base), split(ours), split(theirs));
+
+    let mut res = Vec::new();
+    let mut has_conflicts = false;
+    let push_lines = |res: &mut Vec<u8>, lines: &[&Vec<u8>]| {
+        for line in lines {
+            res.extend_from_slice(line);
+        }
+        if !res.is_empty() && !res.ends_with(b"\n") {
+            res.push(b'\n');
+        }
+    };
+    for chunk in xdiff::merge(&base, &ours, &theirs, Algorithm::Myers) {
+        match chunk {
+            Chunk::Clean(lines) => {
+                for line in lines {
+                    res.extend_from_slice(line);
+                }
+            }
+            Chunk::Conflict { ours, theirs, .. } => {
+                has_conflicts = true;
+                res.extend_from_slice(format!("<<<<<<< {}\n", labels.0).as_bytes());
+                push_lines(&mut res, &ours);
+                res.extend_from_slice(b"=======\n");
+                push_lines(&mut res, &theirs);
+                res.extend_from_slice(format!(">>>>>>> {}\n", labels.1).as_bytes());
+            }
+        }
+    }
+    (res, has_conflicts)
+}
+
+// Files of the working dir which are not tracked, and the ones ignored (with
+// a trailing '/' for directories, which are not walked)
+pub fn get_untracked_files(
+    tracked: &HashSet<String>,
+    ignore: &mut Ignore,
+) -> io::Result<(Vec<String>, Vec<String>)> {
+    let mut untracked = Vec::new();
+    let mut ignored = Vec::new();
     let mut queue = VecDeque::new();
-    queue.push_back(PathBuf::from("."));
+    queue.push_back(String::new());
 
     while let Some(dir) = queue.pop_front() {
-        for entry in fs::read_dir(&dir)? {
-            let path = entry?.path();
-            if path.is_dir() {
-                if !path.starts_with("./.git") {
-                    queue.push_back(path);
-                }
-            } else {
-                let mut path = path.to_str().unwrap();
-                if path.starts_with("./") {
-                    path = &path[2..];
-                }
+        ignore.read_dir(&dir)?;
+        for entry in fs::read_dir(Path::new(".").join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = match dir.is_empty() {
+                true => name,
+                false => format!("{}/{}", dir, name),
+            };
+            let is_dir = entry.file_type()?.is_dir();
+            // Tracked directories are submodules
+            if path == ".git" || tracked.contains(&path) {
+                continue;
+            }
 
-                files.push(path.to_string());
+            match (ignore.is_ignored(&path, is_dir), is_dir) {
+                (true, true) => ignored.push(format!("{}/", path)),
+                (true, false) => ignored.push(path),
+                // Other repositories are not walked
+                (false, true) if entry.path().join(".git").exists() => {
+                    untracked.push(format!("{}/", path))
+                }
+                (false, true) => queue.push_back(path),
+                (false, false) => untracked.push(path),
             }
         }
     }
 
-    Ok(files)
+    untracked.sort();
+    ignored.sort();
+    Ok((untracked, ignored))
+}
+
+#[cfg(test)]
+mod tests {
+    use work_dir::merge_file;
+
+    #[test]
+    fn conflict_markers() {
+        let base = b"a\nb\nc\n";
+        let labels = ("ours", "theirs");
+        let merged = merge_file(base, b"a\nB\nc\n", b"a\nb\nc\nd\n", labels);
+        assert_eq!(merged, (b"a\nB\nc\nd\n".to_vec(), false));
+
+        let merged = merge_file(base, b"a\nB\nc\n", b"a\nx\nc", labels);
+        let expected = "a\n<<<<<<< ours\nB\nc\n=======\nx\nc\n>>>>>>> theirs\n";
+        assert_eq!(merged, (expected.as_bytes().to_vec(), true));
+    }
 }
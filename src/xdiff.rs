@@ -0,0 +1,623 @@
+// Line diff algorithms shared by diff, log, show and merge. Every algorithm
+// first trims the common prefix and suffix of both sides.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "myers" | "default" | "minimal" => Some(Algorithm::Myers),
+            "patience" => Some(Algorithm::Patience),
+            "histogram" => Some(Algorithm::Histogram),
+            _ => None,
+        }
+    }
+}
+
+// One step of the edit script: an `Equal` consumes a line of both sides, a
+// `Delete` one of the old side and an `Insert` one of the new side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// A changed region: `old_len` lines at `old_start` replaced by `new_len`
+// lines at `new_start`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+// Part of a three-way merge result
+#[derive(Debug, PartialEq)]
+pub enum Chunk<'a, T: 'a> {
+    Clean(Vec<&'a T>),
+    Conflict {
+        ours: Vec<&'a T>,
+        base: Vec<&'a T>,
+        theirs: Vec<&'a T>,
+    },
+}
+
+pub fn diff<T: Hash + Eq>(a: &[T], b: &[T], algorithm: Algorithm) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len() + b.len());
+    match algorithm {
+        Algorithm::Myers => myers(a, b, &mut edits),
+        Algorithm::Patience => patience(a, b, &mut edits),
+        Algorithm::Histogram => histogram(a, b, &mut edits),
+    }
+
+    // Like git, the deleted lines of a change come before the inserted ones
+    let mut start = 0;
+    while start < edits.len() {
+        let len = edits[start..]
+            .iter()
+            .take_while(|&&e| e != Edit::Equal)
+            .count();
+        edits[start..start + len].sort_by_key(|&e| e != Edit::Delete);
+        start += len.max(1);
+    }
+    edits
+}
+
+pub fn hunks(edits: &[Edit]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_hunk = false;
+    for edit in edits {
+        if *edit == Edit::Equal {
+            i += 1;
+            j += 1;
+            in_hunk = false;
+            continue;
+        }
+
+        if !in_hunk {
+            hunks.push(Hunk {
+                old_start: i,
+                old_len: 0,
+                new_start: j,
+                new_len: 0,
+            });
+            in_hunk = true;
+        }
+        let hunk = hunks.last_mut().unwrap();
+        match edit {
+            Edit::Delete => {
+                hunk.old_len += 1;
+                i += 1;
+            }
+            _ => {
+                hunk.new_len += 1;
+                j += 1;
+            }
+        }
+    }
+    hunks
+}
+
+// Changes made on both sides since `base` are kept, overlapping (or adjacent)
+// changes which do not give the same result conflict
+pub fn merge<'a, T: Hash + Eq>(
+    base: &'a [T],
+    ours: &'a [T],
+    theirs: &'a [T],
+    algorithm: Algorithm,
+) -> Vec<Chunk<'a, T>> {
+    let our_hunks = hunks(&diff(base, ours, algorithm));
+    let their_hunks = hunks(&diff(base, theirs, algorithm));
+
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    // Lines added or removed before the current position, on each side
+    let (mut our_delta, mut their_delta) = (0isize, 0isize);
+    let mut pos = 0;
+    while i < our_hunks.len() || j < their_hunks.len() {
+        let first_ours = j == their_hunks.len()
+            || (i < our_hunks.len() && our_hunks[i].old_start <= their_hunks[j].old_start);
+        let start = match first_ours {
+            true => our_hunks[i].old_start,
+            false => their_hunks[j].old_start,
+        };
+        let mut end = start;
+        let (i_start, j_start) = (i, j);
+        loop {
+            if i < our_hunks.len() && our_hunks[i].old_start <= end {
+                end = end.max(our_hunks[i].old_start + our_hunks[i].old_len);
+                i += 1;
+            } else if j < their_hunks.len() && their_hunks[j].old_start <= end {
+                end = end.max(their_hunks[j].old_start + their_hunks[j].old_len);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        if pos < start {
+            chunks.push(Chunk::Clean(base[pos..start].iter().collect()));
+        }
+        let our_side = side_range(&our_hunks[i_start..i], start, end, &mut our_delta);
+        let their_side = side_range(&their_hunks[j_start..j], start, end, &mut their_delta);
+        let ours_lines: Vec<&T> = ours[our_side.0..our_side.1].iter().collect();
+        let theirs_lines: Vec<&T> = theirs[their_side.0..their_side.1].iter().collect();
+        if i == i_start || ours_lines == theirs_lines {
+            chunks.push(Chunk::Clean(theirs_lines));
+        } else if j == j_start {
+            chunks.push(Chunk::Clean(ours_lines));
+        } else {
+            chunks.push(Chunk::Conflict {
+                ours: ours_lines,
+                base: base[start..end].iter().collect(),
+                theirs: theirs_lines,
+            });
+        }
+        pos = end;
+    }
+    if pos < base.len() {
+        chunks.push(Chunk::Clean(base[pos..].iter().collect()));
+    }
+    chunks
+}
+
+// Lines of one side matching base[start..end], given the hunks of that side
+// inside the range
+fn side_range(hunks: &[Hunk], start: usize, end: usize, delta: &mut isize) -> (usize, usize) {
+    let side_start = (start as isize + *delta) as usize;
+    for hunk in hunks {
+        *delta += hunk.new_len as isize - hunk.old_len as isize;
+    }
+    (side_start, (end as isize + *delta) as usize)
+}
+
+// Returns the lengths of the common prefix and suffix
+fn trim<T: Eq>(a: &[T], b: &[T]) -> (usize, usize) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    (prefix, suffix)
+}
+
+// Calls `inner` on the middle part once the common lines have been trimmed
+fn with_trimmed<T: Eq>(
+    a: &[T],
+    b: &[T],
+    edits: &mut Vec<Edit>,
+    inner: fn(&[T], &[T], &mut Vec<Edit>),
+) {
+    let (prefix, suffix) = trim(a, b);
+    edits.extend((0..prefix).map(|_| Edit::Equal));
+    let a = &a[prefix..a.len() - suffix];
+    let b = &b[prefix..b.len() - suffix];
+    if a.is_empty() || b.is_empty() {
+        edits.extend((0..a.len()).map(|_| Edit::Delete));
+        edits.extend((0..b.len()).map(|_| Edit::Insert));
+    } else {
+        inner(a, b, edits);
+    }
+    edits.extend((0..suffix).map(|_| Edit::Equal));
+}
+
+fn myers<T: Eq>(a: &[T], b: &[T], edits: &mut Vec<Edit>) {
+    with_trimmed(a, b, edits, bisect);
+}
+
+// Linear space Myers: walks the shortest edit script from both ends at once
+// until the paths meet, then diffs each half separately
+fn bisect<T: Eq>(a: &[T], b: &[T], edits: &mut Vec<Edit>) {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let v_len = 2 * max_d + 2;
+    let mut v1 = vec![-1isize; v_len as usize];
+    let mut v2 = vec![-1isize; v_len as usize];
+    v1[offset as usize + 1] = 0;
+    v2[offset as usize + 1] = 0;
+    let delta = n - m;
+    // With an odd delta, the forward path is the one meeting the reverse one
+    let front = delta % 2 != 0;
+    // Diagonals going out of the edit graph are not explored again
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (offset + k1) as usize;
+            let mut x1 = match k1 == -d || (k1 != d && v1[k1_offset - 1] < v1[k1_offset + 1]) {
+                true => v1[k1_offset + 1],
+                false => v1[k1_offset - 1] + 1,
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            v1[k1_offset] = x1;
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let k2_offset = offset + delta - k1;
+                if k2_offset >= 0 && k2_offset < v_len && v2[k2_offset as usize] != -1 {
+                    let x2 = n - v2[k2_offset as usize];
+                    if x1 >= x2 {
+                        return split(a, b, x1 as usize, y1 as usize, edits);
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (offset + k2) as usize;
+            let mut x2 = match k2 == -d || (k2 != d && v2[k2_offset - 1] < v2[k2_offset + 1]) {
+                true => v2[k2_offset + 1],
+                false => v2[k2_offset - 1] + 1,
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            v2[k2_offset] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = offset + delta - k2;
+                if k1_offset >= 0 && k1_offset < v_len && v1[k1_offset as usize] != -1 {
+                    let x1 = v1[k1_offset as usize];
+                    let y1 = offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return split(a, b, x1 as usize, y1 as usize, edits);
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+
+    // Nothing in common
+    edits.extend((0..a.len()).map(|_| Edit::Delete));
+    edits.extend((0..b.len()).map(|_| Edit::Insert));
+}
+
+fn split<T: Eq>(a: &[T], b: &[T], x: usize, y: usize, edits: &mut Vec<Edit>) {
+    myers(&a[..x], &b[..y], edits);
+    myers(&a[x..], &b[y..], edits);
+}
+
+fn patience<T: Hash + Eq>(a: &[T], b: &[T], edits: &mut Vec<Edit>) {
+    with_trimmed(a, b, edits, patience_inner);
+}
+
+// Lines appearing exactly once on both sides are matched first (keeping the
+// longest sequence in the same order on both sides), everything between them
+// is diffed recursively
+fn patience_inner<T: Hash + Eq>(a: &[T], b: &[T], edits: &mut Vec<Edit>) {
+    let mut counts: HashMap<&T, (usize, usize, usize)> = HashMap::new();
+    for (i, line) in a.iter().enumerate() {
+        let entry = counts.entry(line).or_insert((0, 0, i));
+        entry.0 += 1;
+    }
+    let mut unique = Vec::new();
+    for (j, line) in b.iter().enumerate() {
+        if let Some(entry) = counts.get_mut(line) {
+            entry.1 += 1;
+            if entry.0 == 1 && entry.1 == 1 {
+                unique.push((entry.2, j));
+            }
+        }
+    }
+    unique.retain(|&(i, _)| counts[&a[i]].1 == 1);
+    unique.sort();
+    if unique.is_empty() {
+        return bisect(a, b, edits);
+    }
+
+    let anchors = longest_increasing(&unique);
+    let (mut i, mut j) = (0, 0);
+    for (ai, bj) in anchors {
+        patience(&a[i..ai], &b[j..bj], edits);
+        edits.push(Edit::Equal);
+        i = ai + 1;
+        j = bj + 1;
+    }
+    patience(&a[i..], &b[j..], edits);
+}
+
+// Longest subsequence of pairs (sorted on their first value) which is also
+// increasing on their second value, using patience sorting
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // Top of each pile, and the previous pair of each pair
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous: Vec<Option<usize>> = vec![None; pairs.len()];
+    for (k, pair) in pairs.iter().enumerate() {
+        let pile = piles
+            .binary_search_by(|&top| pairs[top].1.cmp(&pair.1))
+            .unwrap_or_else(|p| p);
+        if pile > 0 {
+            previous[k] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(k);
+        } else {
+            piles[pile] = k;
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut cur = piles.last().cloned();
+    while let Some(k) = cur {
+        res.push(pairs[k]);
+        cur = previous[k];
+    }
+    res.reverse();
+    res
+}
+
+// Lines too common to be good anchors
+const MAX_CHAIN_LENGTH: usize = 64;
+
+// Like patience, anchored on the common region whose rarest line appears the
+// least in `a`, which also works without unique lines
+fn histogram<T: Hash + Eq>(a: &[T], b: &[T], edits: &mut Vec<Edit>) {
+    let mut positions: HashMap<&T, Vec<usize>> = HashMap::new();
+    for (i, line) in a.iter().enumerate() {
+        positions.entry(line).or_default().push(i);
+    }
+    histogram_window(a, b, (0, a.len()), (0, b.len()), &positions, edits);
+}
+
+// Diffs a[a_lo..a_hi] with b[b_lo..b_hi]. The part after the anchor is handled
+// in a loop to keep recursion shallow on long files.
+fn histogram_window<T: Hash + Eq>(
+    a: &[T],
+    b: &[T],
+    (mut a_lo, mut a_hi): (usize, usize),
+    (mut b_lo, mut b_hi): (usize, usize),
+    positions: &HashMap<&T, Vec<usize>>,
+    edits: &mut Vec<Edit>,
+) {
+    let mut suffixes = 0;
+    loop {
+        let (prefix, suffix) = trim(&a[a_lo..a_hi], &b[b_lo..b_hi]);
+        edits.extend((0..prefix).map(|_| Edit::Equal));
+        suffixes += suffix;
+        a_lo += prefix;
+        b_lo += prefix;
+        a_hi -= suffix;
+        b_hi -= suffix;
+        if a_lo == a_hi || b_lo == b_hi {
+            edits.extend((a_lo..a_hi).map(|_| Edit::Delete));
+            edits.extend((b_lo..b_hi).map(|_| Edit::Insert));
+            break;
+        }
+
+        match rarest_region(a, b, (a_lo, a_hi), (b_lo, b_hi), positions) {
+            None => {
+                bisect(&a[a_lo..a_hi], &b[b_lo..b_hi], edits);
+                break;
+            }
+            Some((i, j, len)) => {
+                histogram_window(a, b, (a_lo, i), (b_lo, j), positions, edits);
+                edits.extend((0..len).map(|_| Edit::Equal));
+                a_lo = i + len;
+                b_lo = j + len;
+            }
+        }
+    }
+    edits.extend((0..suffixes).map(|_| Edit::Equal));
+}
+
+// Start in `a`, start in `b` and length of the best common region of the
+// windows
+fn rarest_region<T: Hash + Eq>(
+    a: &[T],
+    b: &[T],
+    (a_lo, a_hi): (usize, usize),
+    (b_lo, b_hi): (usize, usize),
+    positions: &HashMap<&T, Vec<usize>>,
+) -> Option<(usize, usize, usize)> {
+    // Occurrences of a line inside the window of `a`
+    let in_window = |line: &T| -> &[usize] {
+        let all = &positions[line];
+        let lo = all.binary_search(&a_lo).unwrap_or_else(|p| p);
+        let hi = all.binary_search(&a_hi).unwrap_or_else(|p| p);
+        &all[lo..hi]
+    };
+
+    // (lowest count, start in a, start in b, length)
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut j = b_lo;
+    while j < b_hi {
+        let mut next_j = j + 1;
+        let candidates = match positions.contains_key(&b[j]) {
+            true => in_window(&b[j]),
+            false => &[],
+        };
+        if candidates.len() > MAX_CHAIN_LENGTH {
+            j = next_j;
+            continue;
+        }
+        for &i in candidates {
+            let (mut a_start, mut b_start) = (i, j);
+            while a_start > a_lo && b_start > b_lo && a[a_start - 1] == b[b_start - 1] {
+                a_start -= 1;
+                b_start -= 1;
+            }
+            let (mut a_end, mut b_end) = (i + 1, j + 1);
+            while a_end < a_hi && b_end < b_hi && a[a_end] == b[b_end] {
+                a_end += 1;
+                b_end += 1;
+            }
+
+            let count = (a_start..a_end)
+                .map(|k| in_window(&a[k]).len())
+                .min()
+                .unwrap();
+            let len = a_end - a_start;
+            let is_better = match best {
+                None => true,
+                Some((c, _, _, l)) => count < c || (count == c && len > l),
+            };
+            if is_better {
+                best = Some((count, a_start, b_start, len));
+            }
+            next_j = next_j.max(b_end);
+        }
+        j = next_j;
+    }
+
+    best.map(|(_, i, j, len)| (i, j, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use xdiff::{diff, hunks, merge, Algorithm, Chunk, Edit, Hunk};
+
+    const ALGORITHMS: [Algorithm; 3] =
+        [Algorithm::Myers, Algorithm::Patience, Algorithm::Histogram];
+
+    // Applies the edit script to `a`, checking it gives `b`
+    fn apply(a: &[&str], b: &[&str], edits: &[Edit]) -> Vec<String> {
+        let (mut i, mut j) = (0, 0);
+        let mut res = Vec::new();
+        for edit in edits {
+            match edit {
+                Edit::Equal => {
+                    assert_eq!(a[i], b[j]);
+                    res.push(a[i].to_string());
+                    i += 1;
+                    j += 1;
+                }
+                Edit::Delete => i += 1,
+                Edit::Insert => {
+                    res.push(b[j].to_string());
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+        res
+    }
+
+    #[test]
+    fn edit_scripts() {
+        let cases = [
+            ("abcabba", "cbabac"),
+            ("", "abc"),
+            ("abc", ""),
+            ("abcdef", "abcdef"),
+            ("xaxbxcx", "abc"),
+            ("abcd", "wxyz"),
+        ];
+        for &(a, b) in cases.iter() {
+            let a: Vec<&str> = a.split("").filter(|s| !s.is_empty()).collect();
+            let b: Vec<&str> = b.split("").filter(|s| !s.is_empty()).collect();
+            for &algorithm in ALGORITHMS.iter() {
+                let edits = diff(&a, &b, algorithm);
+                assert_eq!(apply(&a, &b, &edits), b);
+            }
+        }
+
+        // Myers gives a shortest edit script
+        let a: Vec<char> = "abcabba".chars().collect();
+        let b: Vec<char> = "cbabac".chars().collect();
+        let edits = diff(&a, &b, Algorithm::Myers);
+        assert_eq!(edits.iter().filter(|&&e| e != Edit::Equal).count(), 5);
+    }
+
+    #[test]
+    fn deletes_come_before_inserts() {
+        let a = ["1", "2", "x", "x", "3"];
+        let b = ["1", "2", "y", "x", "y", "3"];
+        for &algorithm in ALGORITHMS.iter() {
+            let edits = diff(&a, &b, algorithm);
+            assert_eq!(apply(&a, &b, &edits), b);
+            for pair in edits.windows(2) {
+                assert_ne!(pair, [Edit::Insert, Edit::Delete]);
+            }
+        }
+    }
+
+    #[test]
+    fn patience_anchors_on_unique_lines() {
+        // "b" is unique on both sides but out of order with "}" and "{"
+        let a = ["{", "a", "}", "{", "b", "}"];
+        let b = ["{", "b", "}", "{", "c", "}"];
+        let edits = diff(&a, &b, Algorithm::Patience);
+        assert_eq!(apply(&a, &b, &edits), b.to_vec());
+        let expected = vec![
+            Hunk {
+                old_start: 1,
+                old_len: 1,
+                new_start: 1,
+                new_len: 1,
+            },
+            Hunk {
+                old_start: 4,
+                old_len: 1,
+                new_start: 4,
+                new_len: 1,
+            },
+        ];
+        assert_eq!(hunks(&edits), expected);
+    }
+
+    #[test]
+    fn large_inputs() {
+        let a: Vec<usize> = (0..50000).collect();
+        let b: Vec<usize> = (0..50000)
+            .map(|i| if i % 1000 == 0 { i + 100000 } else { i })
+            .collect();
+        for &algorithm in ALGORITHMS.iter() {
+            assert_eq!(hunks(&diff(&a, &b, algorithm)).len(), 50);
+        }
+    }
+
+    #[test]
+    fn three_way_merge() {
+        let base = ["a", "b", "c", "d", "e"];
+        let ours = ["a", "B", "c", "d", "e"];
+        let theirs = ["a", "b", "c", "d", "E"];
+        let merged = merge(&base, &ours, &theirs, Algorithm::Myers);
+        let lines: Vec<&str> = merged
+            .iter()
+            .flat_map(|c| match c {
+                Chunk::Clean(lines) => lines.clone(),
+                _ => panic!("unexpected conflict"),
+            })
+            .cloned()
+            .collect();
+        assert_eq!(lines, ["a", "B", "c", "d", "E"]);
+
+        let theirs = ["a", "x", "c", "d", "e"];
+        let merged = merge(&base, &ours, &theirs, Algorithm::Myers);
+        let expected = Chunk::Conflict {
+            ours: vec![&"B"],
+            base: vec![&"b"],
+            theirs: vec![&"x"],
+        };
+        assert_eq!(merged[1], expected);
+    }
+}